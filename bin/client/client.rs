@@ -1,9 +1,11 @@
+use client_server_tcp::{address::host, CodecKind};
 use interface::ClientData;
+use tls::{TlsConnector, DEFAULT_SERVER_NAME};
 
-mod binary_message;
 mod client_commands;
 mod interface;
 mod stream;
+mod tls;
 
 #[derive(Debug)]
 /// Mode to use when connecting.
@@ -14,29 +16,62 @@ pub enum ConnectionMode {
 
 fn main() -> Result<(), String> {
     // We take the user's arguments.
-    // For the client, there must be 2 arguments, type of connection and connection port/unix socket name.
+    // For the client, there must be 2 arguments, type of connection and connection port/unix socket name,
+    // a TCP port can also be a full address such as `example.com:8080` or `[::1]:8080`,
+    // optionally followed by `--option value` pairs:
+    // * `--codec CODEC` - the wire format to speak.
+    // * `--tls-ca PATH` - a PEM file of CA certificates to trust besides the public ones, can be repeated.
+    // * `--tls-server-name NAME` - the name the server's certificate must be valid for,
+    //   the host of the address by default, or `localhost` for a bare port.
+    // Giving any of the TLS options connects over TLS.
     let args: Vec<String> = std::env::args().collect();
-    if args.len() != 3 {
-        return Err(
-            "incorrect number of arguments provided. use `server TYPE PORT` where TYPE = TCP|UNIX"
-                .to_string(),
-        );
+    if args.len() < 3 {
+        return Err("incorrect arguments provided. use `client TYPE PORT [--option value]...` where TYPE = TCP|UNIX".to_string());
+    }
+
+    let mut codec = CodecKind::Binary;
+    let mut tls_ca_files = vec![];
+    let mut tls_server_name = None;
+    let mut options = args[3..].iter();
+    while let Some(option) = options.next() {
+        let value = options
+            .next()
+            .ok_or(format!("missing value for option {option}"))?;
+
+        match option.as_str() {
+            "--codec" => codec = value.parse()?,
+            "--tls-ca" => tls_ca_files.push(value.clone()),
+            "--tls-server-name" => tls_server_name = Some(value.as_str()),
+            _ => return Err(format!("unknown option {option}")),
+        }
     }
 
     let server_type = args[1].as_str();
     let port = args[2].as_str();
 
+    let tls = match (tls_ca_files.is_empty(), tls_server_name) {
+        (true, None) => None,
+        (_, server_name) => Some(TlsConnector::new(
+            &tls_ca_files,
+            server_name.or(host(port)).unwrap_or(DEFAULT_SERVER_NAME),
+        )?),
+    };
+
     // We decide which mode to use depending on the user's arguments
     match server_type {
         "TCP" => {
             // Creating the dynamic object so we can use the same function for both modes
-            let (stream, mut client_state) = ClientData::new(ConnectionMode::TCP, port.to_string());
+            let (stream, mut client_state) =
+                ClientData::new(ConnectionMode::TCP, port.to_string(), codec, tls);
 
             client_state.await_input(stream);
         }
         "UNIX" => {
+            if tls.is_some() {
+                return Err("TLS is only supported over TCP".to_string());
+            }
             let (stream, mut client_state) =
-                ClientData::new(ConnectionMode::UNIX, port.to_string());
+                ClientData::new(ConnectionMode::UNIX, port.to_string(), codec, None);
             client_state.await_input(stream);
         }
 
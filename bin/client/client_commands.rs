@@ -1,8 +1,9 @@
-use crate::{
-    binary_message::{BinaryMessage, MessageType},
-    interface::{ClientData, CLEAR_TERM_SEQ},
+use crate::interface::{ClientData, CLEAR_TERM_SEQ};
+use crate::stream::Outgoing;
+use client_server_tcp::{
+    handshake::MIN_PROTOCOL_VERSION, BinaryMessage, ErrorCode, ServerCommandList,
+    ServerMessageResponse,
 };
-use std::{string::FromUtf8Error, sync::mpsc::Sender};
 
 /// Sends a hint to the server.
 ///
@@ -10,13 +11,14 @@ use std::{string::FromUtf8Error, sync::mpsc::Sender};
 ///
 /// # Arguments
 ///
-/// * `outgoing: &Sender<Message>` - A reference to a Sender object, used to send messages to the server.
+/// * `outgoing: &Outgoing` - A reference to the outgoing queue, used to send messages to the server.
 /// * `text: &String` - Any message to the server that has no command.
-pub fn message(outgoing: &Sender<BinaryMessage>, text: String) {
+/// * `request_id: u32` - Id of the request, echoed back by the server on the reply.
+pub fn message(outgoing: &Outgoing, text: String, request_id: u32) {
     // Hint the other player
-    let binary_message = BinaryMessage::new_message(text);
+    let binary_message = BinaryMessage::new_message(text).with_request_id(Some(request_id));
 
-    outgoing.send(binary_message).unwrap();
+    outgoing.send(binary_message);
 }
 
 /// Sends a command to the server.
@@ -25,29 +27,28 @@ pub fn message(outgoing: &Sender<BinaryMessage>, text: String) {
 ///
 /// # Arguments
 ///
-/// * `outgoing: &Sender<Message>` - A reference to a Sender object, used to send messages to the server.
+/// * `outgoing: &Outgoing` - A reference to the outgoing queue, used to send messages to the server.
 /// * `text: &String` - Any message to the server that has a command.
-pub fn command(outgoing: &Sender<BinaryMessage>, text: String) {
-    // Hint the other player
-    let binary_message = BinaryMessage::new_command(text);
-    outgoing.send(binary_message).unwrap();
-}
+/// * `request_id: u32` - Id of the request, echoed back by the server on the reply.
+pub fn command(outgoing: &Outgoing, text: String, request_id: u32) {
+    // Parse the command with the shared vocabulary, so we never send something the server can't understand
+    let command = match ServerCommandList::try_from(BinaryMessage::new_command(text)) {
+        Ok(ServerCommandList::Unknown) => {
+            eprintln!("unknown command, not sending");
+            return;
+        }
+        Err(err) => {
+            eprintln!("{err}, not sending");
+            return;
+        }
+        Ok(command) => command,
+    };
 
-#[derive(Debug)]
-/// Possible responses from the server
-pub enum ServerMessageResponse {
-    Unknown,
-    ID(Vec<u8>),
-    Error(Vec<u8>),
-    Message(Vec<u8>),
-    RequestAck,
-    RequestedGame,
-    GameVictory,
-    GameDefeat,
-    GameCanceled,
+    outgoing.send(BinaryMessage::from(command).with_request_id(Some(request_id)));
 }
 
-impl ServerMessageResponse {
+/// Client-side handling of the responses and events received from the server.
+pub trait ServerReply {
     /// Handles the server reply.
     ///
     /// This function takes a mutable reference to a `ClientData` object and handles the server reply based on the type of message received. It updates the client's state and prints messages to the terminal.
@@ -55,7 +56,12 @@ impl ServerMessageResponse {
     /// # Arguments
     ///
     /// * `client: &mut ClientData` - A mutable reference to a `ClientData` object, representing the client's state.
-    pub fn handle_server_reply(&self, client: &mut ClientData) {
+    /// * `request_id: Option<u32>` - Id of the request this is a reply to, `None` for events pushed by the server.
+    fn handle_server_reply(&self, client: &mut ClientData, request_id: Option<u32>);
+}
+
+impl ServerReply for ServerMessageResponse {
+    fn handle_server_reply(&self, client: &mut ClientData, request_id: Option<u32>) {
         // We handle the message by matching it and then determining where to put the results.
         // In some cases, we change the interface type.
         let mut server_reply = String::new();
@@ -64,10 +70,26 @@ impl ServerMessageResponse {
         match self {
             ServerMessageResponse::Unknown => server_reply = "unknown message type".to_string(),
 
-            ServerMessageResponse::ID(data) => {
-                let id = u64::from_be_bytes(data[..].try_into().unwrap_or_default());
+            ServerMessageResponse::Welcome(info) => {
+                // Refuse to talk to a server that speaks an older protocol than we support
+                if !info.is_compatible() {
+                    eprintln!(
+                        "server speaks protocol version {}, but at least version {MIN_PROTOCOL_VERSION} is required, exiting",
+                        info.version
+                    );
+                    std::process::exit(0);
+                }
+
+                client.set_capabilities(info.capabilities);
+
+                server_reply = format!(
+                    "Connected to a server speaking protocol version {}, enter the password",
+                    info.version
+                );
+            }
 
-                client.set_id(id);
+            ServerMessageResponse::ID(id) => {
+                client.set_id(*id);
 
                 // If ID == 0 we exit as the ID wasn't assigned by server
                 if client.get_id() == 0 {
@@ -78,14 +100,23 @@ impl ServerMessageResponse {
                 server_reply = format!("Received and set an ID from server: {id}");
             }
 
-            ServerMessageResponse::Error(data) => {
-                server_reply = String::from_utf8(data.clone())
-                    .unwrap_or("Incoming data was not UTF-8".to_string());
+            ServerMessageResponse::Error { code, message } => {
+                // The password is checked once, there is no point in staying connected after it fails
+                if *code == ErrorCode::AuthFailed {
+                    eprintln!("login refused: {message}, exiting");
+                    std::process::exit(0);
+                }
+                // The server closes the connection right after
+                if *code == ErrorCode::IdleTimeout || *code == ErrorCode::RateLimited {
+                    eprintln!("disconnected by the server: {message}, exiting");
+                    std::process::exit(0);
+                }
+
+                server_reply = format!("ERROR {code}: {message}");
             }
 
-            ServerMessageResponse::Message(data) => {
-                event_message = String::from_utf8(data.clone())
-                    .unwrap_or("Incoming data was not UTF-8".to_string());
+            ServerMessageResponse::Message(text) => {
+                event_message = text.clone();
             }
 
             // Arg
@@ -115,6 +146,29 @@ Use /HINT to send a hint, and /GUESS to send a guess (host sends hints and oponn
                 println!("{CLEAR_TERM_SEQ}");
                 server_reply = "Game was cancelled".to_string();
             }
+
+            ServerMessageResponse::Hint(hint) => {
+                event_message = format!("Hint: {hint}");
+            }
+
+            ServerMessageResponse::WrongGuess { guess, attempts } => {
+                event_message = format!("Your opponent guessed {guess}, {attempts} attempts left");
+            }
+
+            // The server closes the connection right after, so there's nothing left to do
+            ServerMessageResponse::Shutdown(reason) => {
+                eprintln!("the server is shutting down: {reason}, exiting");
+                std::process::exit(0);
+            }
+
+            // Pings are answered by the main loop, before they get here
+            ServerMessageResponse::Ping => (),
+
+            // The server doesn't take the connection, it's closed right after
+            ServerMessageResponse::Busy(reason) => {
+                eprintln!("the server is busy: {reason}, try again later");
+                std::process::exit(0);
+            }
         }
 
         // Some UI stuff
@@ -136,6 +190,9 @@ REQUEST
  
         "
         );
+        if let Some(request_id) = request_id {
+            println!("REPLY TO REQUEST #{request_id}");
+        }
         if !server_reply.is_empty() {
             println!("SERVER REPLY: {server_reply}");
         }
@@ -144,32 +201,3 @@ REQUEST
         }
     }
 }
-
-impl TryFrom<BinaryMessage> for ServerMessageResponse {
-    type Error = FromUtf8Error;
-
-    fn try_from(binary_message: BinaryMessage) -> Result<Self, FromUtf8Error> {
-        match binary_message.get_type() {
-            MessageType::Command => {
-                // Split data to command and the binary part
-                let (command, binary) = binary_message.split()?;
-
-                // Match the command
-                Ok(match command.to_ascii_uppercase().as_str() {
-                    "ID" => Self::ID(binary),
-                    "ERROR" => Self::Error(binary),
-                    "REQUESTACK" => Self::RequestAck,
-                    "REQUESTEDGAME" => Self::RequestedGame,
-                    "DEFEAT" => Self::GameDefeat,
-                    "CANCELED" => Self::GameCanceled,
-                    "VICTORY" => Self::GameVictory,
-                    _ => Self::Unknown,
-                })
-            }
-            MessageType::Message => Ok(ServerMessageResponse::Message(
-                binary_message.get_message().clone(),
-            )),
-            _ => Ok(Self::Unknown),
-        }
-    }
-}
@@ -1,17 +1,22 @@
 use std::{
+    collections::HashSet,
     io::{Read, Write},
     net::TcpStream,
-    os::unix::net::UnixStream,
+    os::fd::AsRawFd,
+};
+
+use client_server_tcp::{
+    address::{tcp_address, UnixAddress},
+    BinaryMessage, Capabilities, CodecKind, ProtocolInfo, ServerCommandList, ServerMessageResponse,
 };
 
 use crate::{
-    client_commands::{command, message},
-    stream::{handle_input, handle_stream},
+    client_commands::{command, message, ServerReply},
+    stream::{handle_input, handle_stream, ClientEvent, Connection},
+    tls::TlsConnector,
     ConnectionMode,
 };
 
-/// Loop rate on UI that refreshes automatically (including sending data to server)
-const LOOP_RATE_MS: u64 = 500;
 pub const CLEAR_TERM_SEQ: &str = "\x1B[2J\x1B[1;1H";
 
 /// Helper trait to generalize UNIX and TCP implementation
@@ -23,34 +28,61 @@ impl<T: Read + Write + Send + Sync> ReadWrite for T {}
 /// Main client data that shows which interface is shown and client's ID.
 pub struct ClientData {
     client_id: u64,
+    capabilities: Capabilities,
+    last_request_id: u32,
+    codec: CodecKind,
 }
 
 impl ClientData {
-    /// Creates a new client, depending on whether it's a TCP or UNIX client.
-    pub fn new(mode: ConnectionMode, connection_endpoint: String) -> (Box<dyn ReadWrite>, Self) {
-        (
-            match &mode {
-                ConnectionMode::TCP => {
-                    // Just simply using the same variable.
-                    let ip_address = format!("127.0.0.1:{connection_endpoint}");
-                    let stream = TcpStream::connect(ip_address).unwrap();
-                    stream
-                        .set_read_timeout(Some(std::time::Duration::from_millis(300)))
-                        .unwrap();
-                    Box::new(stream)
+    /// Creates a new client, depending on whether it's a TCP or UNIX client, speaking the given codec.
+    /// A TCP client given a [`TlsConnector`] encrypts the connection with TLS.
+    pub fn new(
+        mode: ConnectionMode,
+        connection_endpoint: String,
+        codec: CodecKind,
+        tls: Option<TlsConnector>,
+    ) -> (Connection, Self) {
+        // Streams are non-blocking, the connection's thread waits until they're ready instead.
+        let (stream, fd): (Box<dyn ReadWrite>, _) = match &mode {
+            ConnectionMode::TCP => {
+                // A bare port connects to the local machine, otherwise every address of the host is tried.
+                let ip_address = tcp_address(&connection_endpoint);
+                let stream = TcpStream::connect(ip_address).unwrap();
+                // The handshake is done before the stream is made non-blocking, it only has to block once.
+                match tls {
+                    Some(tls) => {
+                        let tls_stream = tls
+                            .connect(stream)
+                            .unwrap_or_else(|err| panic!("TLS handshake failed: {err}"));
+                        tls_stream.sock.set_nonblocking(true).unwrap();
+                        let fd = tls_stream.sock.as_raw_fd();
+                        (Box::new(tls_stream), fd)
+                    }
+                    None => {
+                        stream.set_nonblocking(true).unwrap();
+                        let fd = stream.as_raw_fd();
+                        (Box::new(stream), fd)
+                    }
                 }
-                ConnectionMode::UNIX => {
-                    let socket = format!("/tmp/{connection_endpoint}");
-                    let stream = UnixStream::connect(socket).unwrap();
-                    // UNIX is by default blocking, so we set the nonblocking read here.
-                    stream.set_nonblocking(true).unwrap();
+            }
+            ConnectionMode::UNIX => {
+                // A bare name is a socket in /tmp, otherwise a path or an `@name` abstract socket.
+                let socket = UnixAddress::parse(&connection_endpoint);
+                let stream = socket.connect().unwrap();
+                stream.set_nonblocking(true).unwrap();
+                let fd = stream.as_raw_fd();
+                (Box::new(stream), fd)
+            }
+        };
 
-                    Box::new(stream)
-                }
-            },
+        (
+            Connection::new(stream, fd).unwrap(),
             // Sets the user ID to 0, also the screen to the login variant
             Self {
                 client_id: u64::default(),
+                capabilities: Capabilities::NONE,
+                last_request_id: 0,
+                codec,
             },
         )
     }
@@ -65,26 +97,45 @@ impl ClientData {
         self.client_id
     }
 
-    pub fn await_input(&mut self, stream: Box<dyn ReadWrite>) -> ! {
-        // Create the 3 text event buffers.
+    /// Generates the id for the next request, so its reply can be told apart from other messages.
+    pub fn next_request_id(&mut self) -> u32 {
+        self.last_request_id = self.last_request_id.wrapping_add(1);
+        self.last_request_id
+    }
 
-        // Create 3 channel pairs for separate thread-based events.
-        // 1st is all incoming commands from the server.
-        // 2nd is all outgoing commands to the server.
-        // 3rd is an input handler.
-        let (incoming_send, incoming_recv) = std::sync::mpsc::channel();
+    /// Set the capabilities agreed on with the server during the handshake.
+    pub fn set_capabilities(&mut self, capabilities: Capabilities) {
+        self.capabilities = capabilities;
+    }
+
+    pub fn await_input(&mut self, connection: Connection) -> ! {
+        // Create 2 channel pairs for separate thread-based events.
+        // 1st is all events for the main loop, the server's messages and the user input.
+        // 2nd is all outgoing commands to the server, sending one wakes the connection's thread.
+        let (events_send, events_recv) = std::sync::mpsc::channel();
         let (outgoing_send, outgoing_recv) = std::sync::mpsc::channel();
-        let (input_send, input_recv) = std::sync::mpsc::channel();
+        let outgoing = connection.outgoing(outgoing_send);
 
         // Thread spawn oncoming stream for command management.
         // This thread handles both incoming and outgoing commands. We just read/write to channel.
+        let codec = self.codec.new_codec();
+        let incoming_send = events_send.clone();
         std::thread::spawn(move || {
-            handle_stream(stream, incoming_send, outgoing_recv);
+            handle_stream(connection, codec, incoming_send, outgoing_recv);
         });
 
+        // Every connection starts with the handshake, announcing which protocol version and capabilities we speak.
+        // Features the codec can't carry are left out, so the server never picks them.
+        let info = ProtocolInfo::current();
+        let hello = ProtocolInfo {
+            capabilities: info.capabilities.intersection(self.codec.capabilities()),
+            ..info
+        };
+        outgoing.send(ServerCommandList::Hello(hello).into());
+
         // Thread spawn for any input handling. This makes sure the terminal isn't blocked.
         std::thread::spawn(move || {
-            handle_input(input_send);
+            handle_input(events_send);
         });
 
         println!("{CLEAR_TERM_SEQ}");
@@ -94,55 +145,47 @@ Enter the password for the server, then press ENTER:
         "
         );
 
-        // Main event loop
+        // Heartbeats sent in answer to a ping, their replies aren't shown
+        let mut heartbeats = HashSet::new();
+
+        // Main event loop, it waits until the server sends something or the user types something
         loop {
-            // Check if we received a message from the server, if not then continue. If yes, handle it
-            match incoming_recv.try_recv() {
-                Ok(response) => response.handle_server_reply(self),
-                Err(err) => {
-                    // If a channel is disconnected, something went terribly wrong and we need to panic.
-                    // Else, we just ignore that the channel is empty.
-                    if let std::sync::mpsc::TryRecvError::Disconnected = err {
-                        panic!("critical error, incoming channel disconnected!")
-                    }
+            let data = match events_recv.recv() {
+                // The server disconnects clients that stop answering, so pings are answered without bothering the user
+                Ok(ClientEvent::Reply(ServerMessageResponse::Ping, _)) => {
+                    let request_id = self.next_request_id();
+                    heartbeats.insert(request_id);
+                    outgoing.send(
+                        BinaryMessage::from(ServerCommandList::HeartBeat)
+                            .with_request_id(Some(request_id)),
+                    );
+                    continue;
                 }
-            }
-
-            // `action_number` is the parsed value from user input. We set it to -1 if no input is detected.
-            // This allows us to properly refresh the screen on a loop with new data.
-            // (example: spectator screen refreshes data regularly)
-            // `arguments` is a parsed and split_by_whitespace vector of the arguments of each command.
-
-            let data: String = match input_recv.try_recv() {
-                Ok(value) => value,
-                Err(err) => {
-                    match err {
-                        std::sync::mpsc::TryRecvError::Disconnected => {
-                            // If a channel is disconnected, something went terribly wrong and we need to panic.
-                            // Else, we just ignore that the channel is empty.
-                            panic!("critical error, incoming channel disconnected!")
-                        }
-                        _ => String::new(),
-                    }
+                Ok(ClientEvent::Reply(_, Some(request_id))) if heartbeats.remove(&request_id) => {
+                    continue;
                 }
+                Ok(ClientEvent::Reply(response, request_id)) => {
+                    response.handle_server_reply(self, request_id);
+                    continue;
+                }
+                Ok(ClientEvent::Input(data)) => data,
+                Ok(ClientEvent::Closed(reason)) => panic!("{reason}"),
+                // If a channel is disconnected, something went terribly wrong and we need to panic.
+                Err(_) => panic!("critical error, incoming channel disconnected!"),
             };
 
             if data.is_empty() {
-                std::thread::sleep(std::time::Duration::from_millis(LOOP_RATE_MS));
                 continue;
             }
 
             // /COMMAND
             if let Some(stripped) = data.strip_prefix('/') {
-                command(&outgoing_send, stripped.to_string());
+                command(&outgoing, stripped.to_string(), self.next_request_id());
                 println!("sending command");
             } else {
-                message(&outgoing_send, data);
+                message(&outgoing, data, self.next_request_id());
                 println!("sending message");
             }
-
-            // Wait a while to not spam the UI
-            std::thread::sleep(std::time::Duration::from_millis(LOOP_RATE_MS));
         }
     }
 }
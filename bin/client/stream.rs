@@ -1,83 +1,219 @@
+use std::os::fd::RawFd;
 use std::sync::mpsc::{Receiver, Sender};
+use std::sync::Arc;
 
-use crate::{
-    binary_message::BinaryMessage, client_commands::ServerMessageResponse, interface::ReadWrite,
+use mio::unix::SourceFd;
+use mio::{Events, Interest, Poll, Token, Waker};
+
+use client_server_tcp::{
+    binary_message::LARGE_MAX_FRAME_SIZE, BinaryMessage, Codec, ServerMessageResponse,
 };
 
+use crate::interface::ReadWrite;
+
+/// Token of the connection to the server.
+const SERVER: Token = Token(0);
+/// Token of the waker, used when there are messages to be sent.
+const OUTGOING: Token = Token(1);
+
+/// Everything the main loop waits for, so it only ever blocks on a single channel.
+pub enum ClientEvent {
+    /// A message from the server, along with the id of the request it replies to.
+    Reply(ServerMessageResponse, Option<u32>),
+    /// A line typed by the user.
+    Input(String),
+    /// The connection to the server is gone, with the reason why.
+    Closed(String),
+}
+
+/// The non-blocking connection to the server, along with the poll telling when it's ready.
+pub struct Connection {
+    stream: Box<dyn ReadWrite>,
+    poll: Poll,
+    waker: Arc<Waker>,
+}
+
+impl Connection {
+    /// Registers a non-blocking stream with a new poll.
+    ///
+    /// # Arguments
+    ///
+    /// * `stream: Box<dyn ReadWrite>` - The connected stream, it must not block.
+    /// * `fd: RawFd` - The file descriptor of the socket under the stream, it's what the poll watches.
+    pub fn new(stream: Box<dyn ReadWrite>, fd: RawFd) -> std::io::Result<Self> {
+        let poll = Poll::new()?;
+        poll.registry().register(
+            &mut SourceFd(&fd),
+            SERVER,
+            Interest::READABLE | Interest::WRITABLE,
+        )?;
+        let waker = Arc::new(Waker::new(poll.registry(), OUTGOING)?);
+
+        Ok(Self {
+            stream,
+            poll,
+            waker,
+        })
+    }
+
+    /// Creates the handle used to send messages over this connection.
+    pub fn outgoing(&self, sender: Sender<BinaryMessage>) -> Outgoing {
+        Outgoing {
+            sender,
+            waker: self.waker.clone(),
+        }
+    }
+}
+
+/// Queues messages for the server and wakes the connection's thread to send them.
+pub struct Outgoing {
+    sender: Sender<BinaryMessage>,
+    waker: Arc<Waker>,
+}
+
+impl Outgoing {
+    /// Queues a message to be sent to the server.
+    pub fn send(&self, message: BinaryMessage) {
+        self.sender.send(message).unwrap();
+        self.waker.wake().unwrap();
+    }
+}
+
 /// Handles the incoming and outgoing messages for a client-server connection.
 ///
-/// This function continuously reads from the outgoing channel and writes to the stream, and reads from the stream and writes to the incoming channel. It uses non-blocking reads from the outgoing channel and the stream.
+/// This function waits until the stream is ready or there are messages to be sent, it never sleeps.
+/// Queued messages are written as far as the stream takes them, and the rest is written once it's writable again.
+/// Bytes read from the stream are buffered until they form complete frames, and every complete frame is forwarded.
+/// When the connection is gone, a [`ClientEvent::Closed`] is sent and the function returns.
 ///
 /// # Arguments
 ///
-/// * `stream: impl ReadWrite` - An object that implements the ReadWrite trait, typically a TCP stream.
-/// * `incoming: Sender<ServerMessageResponse>` - A Sender object for the incoming channel, used to send messages to the server.
-/// * `outgoing: Receiver<Message>` - A Receiver object for the outgoing channel, used to receive messages from the server.
+/// * `connection: Connection` - The connection to the server.
+/// * `codec: Box<dyn Codec>` - The codec of the wire format the client speaks.
+/// * `incoming: Sender<ClientEvent>` - A Sender object for the event channel, used to pass the server's messages and the request ids they reply to.
+/// * `outgoing: Receiver<BinaryMessage>` - A Receiver object for the outgoing channel, used to receive the messages for the server.
 pub fn handle_stream(
-    mut stream: impl ReadWrite,
-    incoming: Sender<ServerMessageResponse>,
+    connection: Connection,
+    codec: Box<dyn Codec>,
+    incoming: Sender<ClientEvent>,
     outgoing: Receiver<BinaryMessage>,
-) -> ! {
+) {
+    let reason = match serve_stream(connection, codec, &incoming, outgoing) {
+        Ok(()) => "connection closed by peer".to_string(),
+        Err(err) => err.to_string(),
+    };
+    // The main loop may be gone already, there's no one left to tell then
+    let _ = incoming.send(ClientEvent::Closed(reason));
+}
+
+/// Serves the connection until the server closes it or an error occurs.
+fn serve_stream(
+    mut connection: Connection,
+    mut codec: Box<dyn Codec>,
+    incoming: &Sender<ClientEvent>,
+    outgoing: Receiver<BinaryMessage>,
+) -> std::io::Result<()> {
+    // We always advertise large frames, so accept them from the start
+    codec.set_max_frame_size(LARGE_MAX_FRAME_SIZE);
+
+    let mut events = Events::with_capacity(16);
+    // This is the stream write queue, what the stream didn't take yet
+    let mut pending: Vec<u8> = vec![];
+
     loop {
-        // This is the stream write queue
-        match outgoing.try_recv() {
-            Ok(message) => {
-                stream.write_all(&message.serialize()).unwrap();
+        if let Err(err) = connection.poll.poll(&mut events, None) {
+            if err.kind() == std::io::ErrorKind::Interrupted {
+                continue;
             }
-            Err(err) => match err {
-                std::sync::mpsc::TryRecvError::Empty => (),
-                std::sync::mpsc::TryRecvError::Disconnected => {
-                    panic!("a critical channel error occured, channeld disconnected")
-                }
-            },
+            return Err(err);
         }
 
-        // While the buffer has a fairly tight limit, it can be extended if needed.
-        // This should be enough for spectating a few games.
-        // I wouldn't send an app's source code over it tho.
-        let mut temp_buffer = vec![0; 4096];
-        match stream.read(&mut temp_buffer) {
-            Ok(size) => {
-                if size == 0 {
-                    // If the server terminates connection,
-                    // then panic the thread, no point in continuing.
-                    panic!("Connection closed by peer");
-                }
+        while let Ok(message) = outgoing.try_recv() {
+            match codec.encode(&message) {
+                Ok(frames) => frames.iter().for_each(|frame| pending.extend(frame)),
+                Err(err) => eprintln!("error encoding a message, not sending: {err}"),
+            }
+        }
 
-                let message: ServerMessageResponse =
-                    match BinaryMessage::deserialize(&temp_buffer).try_into() {
-                        Ok(value) => value,
-                        Err(err) => {
-                            eprintln!("error deserializing message: {err}");
-                            continue;
-                        }
-                    };
+        write_pending(&mut connection.stream, &mut pending)?;
 
-                incoming.send(message).unwrap();
+        // The stream is read until nothing more has arrived, the poll only reports new data.
+        loop {
+            match codec.read_from(&mut connection.stream) {
+                // If the server terminates connection, there's no point in continuing.
+                Ok(0) => return Ok(()),
+                Ok(_) => (),
+                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
             }
-            Err(e) => {
-                if let std::io::ErrorKind::WouldBlock = e.kind() {
-                    // We don't care about would block, so we continue to check for incoming messages.
-                    continue;
-                } else {
-                    panic!("{e}");
+
+            // A single read can carry several frames, or only a part of one.
+            loop {
+                let frame = match codec.next_frame() {
+                    Ok(Some(frame)) => frame,
+                    Ok(None) => break,
+                    Err(err) => {
+                        eprintln!("error reading frame: {err}");
+                        continue;
+                    }
+                };
+
+                let request_id = frame.get_request_id();
+                let message: ServerMessageResponse = match frame.try_into() {
+                    Ok(value) => value,
+                    Err(err) => {
+                        eprintln!("error deserializing message: {err}");
+                        continue;
+                    }
+                };
+
+                // Frames are encoded the way the server agreed to from now on
+                if let ServerMessageResponse::Welcome(info) = &message {
+                    codec.set_capabilities(info.capabilities);
+                }
+
+                if incoming
+                    .send(ClientEvent::Reply(message, request_id))
+                    .is_err()
+                {
+                    // The main loop is gone, so is the client
+                    return Ok(());
                 }
             }
         }
+    }
+}
+
+/// Writes the queued bytes until the stream stops taking them, what's left waits until it's writable again.
+fn write_pending(stream: &mut Box<dyn ReadWrite>, pending: &mut Vec<u8>) -> std::io::Result<()> {
+    while !pending.is_empty() {
+        match stream.write(pending) {
+            Ok(0) => return Err(std::io::ErrorKind::WriteZero.into()),
+            Ok(size) => {
+                pending.drain(..size);
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => return Ok(()),
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
 
-        // This is a small delay to prevent the thread from hogging the CPU.
-        std::thread::sleep(std::time::Duration::from_millis(250));
+    // A TLS stream may still hold encrypted bytes of its own
+    match stream.flush() {
+        Err(e) if e.kind() != std::io::ErrorKind::WouldBlock => Err(e),
+        _ => Ok(()),
     }
 }
 
 /// Handles the user input from the command line.
 ///
-/// This function continuously reads from the standard input, parses the input into a command ID and arguments, and sends them to the `input_send` channel. The command ID is expected to be a 64-bit integer, and the arguments are the rest of the input split by whitespace.
+/// This function continuously reads lines from the standard input and sends them to the main loop, which decides whether they're commands or messages.
 ///
 /// # Arguments
 ///
-/// * `input_send: Sender<(i64, Vec<String>)>` - A Sender object for the input channel, used to send the parsed user input to the main thread.
-pub fn handle_input(input_send: Sender<String>) -> ! {
+/// * `input_send: Sender<ClientEvent>` - A Sender object for the event channel, used to send the user input to the main thread.
+pub fn handle_input(input_send: Sender<ClientEvent>) -> ! {
     loop {
         // Parse the input, else return it's not a proper number and continue
         let mut input = String::default();
@@ -92,6 +228,6 @@ pub fn handle_input(input_send: Sender<String>) -> ! {
         }
 
         input.pop();
-        input_send.send(input).unwrap();
+        input_send.send(ClientEvent::Input(input)).unwrap();
     }
 }
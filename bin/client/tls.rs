@@ -0,0 +1,70 @@
+use std::net::TcpStream;
+use std::sync::Arc;
+
+use rustls::pki_types::{pem::PemObject, CertificateDer, ServerName};
+use rustls::{ClientConfig, ClientConnection, RootCertStore, StreamOwned};
+
+/// Name checked against the server's certificate when none is given.
+pub const DEFAULT_SERVER_NAME: &str = "localhost";
+
+/// A TCP connection encrypted with TLS, it's read and written exactly like the plain stream.
+pub type TlsStream = StreamOwned<ClientConnection, TcpStream>;
+
+/// Settings for connecting to a server over TLS.
+pub struct TlsConnector {
+    config: Arc<ClientConfig>,
+    server_name: ServerName<'static>,
+}
+
+impl TlsConnector {
+    /// Creates a connector trusting the public web roots and the CA certificates of the given PEM files.
+    ///
+    /// # Arguments
+    ///
+    /// * `ca_files: &[String]` - Extra certificate authorities to trust, e.g. a self-signed CA for testing.
+    /// * `server_name: &str` - The name the server's certificate must be valid for.
+    pub fn new(ca_files: &[String], server_name: &str) -> Result<Self, String> {
+        let mut roots = RootCertStore::empty();
+        roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+
+        for ca_file in ca_files {
+            let certificates = CertificateDer::pem_file_iter(ca_file)
+                .and_then(|certificates| certificates.collect::<Result<Vec<_>, _>>())
+                .map_err(|err| format!("error reading certificates from {ca_file}: {err}"))?;
+            if certificates.is_empty() {
+                return Err(format!("no certificates found in {ca_file}"));
+            }
+            for certificate in certificates {
+                roots
+                    .add(certificate)
+                    .map_err(|err| format!("invalid CA certificate in {ca_file}: {err}"))?;
+            }
+        }
+
+        let server_name = ServerName::try_from(server_name.to_string())
+            .map_err(|_| format!("invalid server name {server_name}"))?;
+
+        let config = ClientConfig::builder()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+
+        Ok(Self {
+            config: Arc::new(config),
+            server_name,
+        })
+    }
+
+    /// Performs the TLS handshake over a connected stream.
+    ///
+    /// The handshake is finished while the stream still blocks, so a server with an untrusted certificate is refused right away.
+    pub fn connect(&self, mut stream: TcpStream) -> std::io::Result<TlsStream> {
+        let mut connection = ClientConnection::new(self.config.clone(), self.server_name.clone())
+            .map_err(std::io::Error::other)?;
+
+        while connection.is_handshaking() {
+            connection.complete_io(&mut stream)?;
+        }
+
+        Ok(StreamOwned::new(connection, stream))
+    }
+}
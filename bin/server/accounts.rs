@@ -0,0 +1,100 @@
+use std::ffi::{CStr, CString};
+
+/// Size of the buffer the system fills with a user entry.
+const ENTRY_BUFFER_SIZE: usize = 16 * 1024;
+
+/// Groups looked up for a user at first, the list grows if the user is in more.
+const GROUP_LIST_SIZE: usize = 64;
+
+/// Gets the id of a user given by name or by id.
+pub fn user_id(user: &str) -> Result<u32, String> {
+    if let Ok(id) = user.parse() {
+        return Ok(id);
+    }
+
+    let name = CString::new(user).map_err(|_| format!("invalid user {user}"))?;
+    // SAFETY: the name is a valid C string, and the entry is read right away, before another call can overwrite it.
+    let entry = unsafe { libc::getpwnam(name.as_ptr()) };
+    if entry.is_null() {
+        return Err(format!("unknown user {user}"));
+    }
+    // SAFETY: the entry was checked to not be null.
+    Ok(unsafe { (*entry).pw_uid })
+}
+
+/// Gets the id of a group given by name or by id.
+pub fn group_id(group: &str) -> Result<u32, String> {
+    if let Ok(id) = group.parse() {
+        return Ok(id);
+    }
+
+    let name = CString::new(group).map_err(|_| format!("invalid group {group}"))?;
+    // SAFETY: the name is a valid C string, and the entry is read right away, before another call can overwrite it.
+    let entry = unsafe { libc::getgrnam(name.as_ptr()) };
+    if entry.is_null() {
+        return Err(format!("unknown group {group}"));
+    }
+    // SAFETY: the entry was checked to not be null.
+    Ok(unsafe { (*entry).gr_gid })
+}
+
+/// Gets the name of a user, or `None` if the user has no entry.
+///
+/// Connection threads look users up at the same time, so the reentrant lookup is used.
+pub fn user_name(uid: u32) -> Option<String> {
+    let mut buffer = vec![0 as libc::c_char; ENTRY_BUFFER_SIZE];
+    // SAFETY: a passwd of zeroes is valid, its pointers are only read once the lookup filled them in.
+    let mut entry: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut result = std::ptr::null_mut();
+
+    // SAFETY: every pointer refers to memory owned here, and the buffer length is its real length.
+    let code = unsafe {
+        libc::getpwuid_r(
+            uid,
+            &mut entry,
+            buffer.as_mut_ptr(),
+            buffer.len(),
+            &mut result,
+        )
+    };
+    if code != 0 || result.is_null() {
+        return None;
+    }
+
+    // SAFETY: the lookup succeeded, so the name points to a C string inside the buffer.
+    let name = unsafe { CStr::from_ptr(entry.pw_name) };
+    Some(name.to_string_lossy().into_owned())
+}
+
+/// Gets every group a user is a member of, its primary group and its supplementary groups.
+///
+/// # Arguments
+///
+/// * `name: &str` - The name of the user.
+/// * `gid: u32` - The primary group of the user, it's always part of the list.
+pub fn group_list(name: &str, gid: u32) -> Vec<u32> {
+    let Ok(name) = CString::new(name) else {
+        return vec![gid];
+    };
+
+    let mut groups: Vec<libc::gid_t> = vec![0; GROUP_LIST_SIZE];
+    loop {
+        let mut count = groups.len() as libc::c_int;
+        // SAFETY: the name is a valid C string, and the count is the real length of the list the groups are written to.
+        let result =
+            unsafe { libc::getgrouplist(name.as_ptr(), gid, groups.as_mut_ptr(), &mut count) };
+
+        // The list was too short, the count now says how long it has to be
+        if result == -1 {
+            let needed = (count.max(0) as usize).max(groups.len() * 2);
+            groups.resize(needed, 0);
+            continue;
+        }
+
+        groups.truncate(count.max(0) as usize);
+        if !groups.contains(&gid) {
+            groups.push(gid);
+        }
+        return groups;
+    }
+}
@@ -0,0 +1,241 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::time::Instant;
+
+/// Connections served at once, unless configured otherwise.
+pub const DEFAULT_MAX_CONNECTIONS: usize = 1024;
+
+/// Connections a single TCP client address may hold at once, unless configured otherwise.
+pub const DEFAULT_MAX_PER_IP: usize = 64;
+
+/// Connections accepted per second, unless configured otherwise.
+pub const DEFAULT_ACCEPT_RATE: usize = 100;
+
+#[derive(Debug, Clone)]
+/// How many connections the server takes, a limit of 0 turns that limit off.
+pub struct ConnectionLimits {
+    max_connections: usize,
+    max_per_ip: usize,
+    accept_rate: usize,
+}
+
+impl Default for ConnectionLimits {
+    fn default() -> Self {
+        Self {
+            max_connections: DEFAULT_MAX_CONNECTIONS,
+            max_per_ip: DEFAULT_MAX_PER_IP,
+            accept_rate: DEFAULT_ACCEPT_RATE,
+        }
+    }
+}
+
+impl ConnectionLimits {
+    /// Sets how many connections are served at once.
+    pub fn set_max_connections(&mut self, max_connections: usize) {
+        self.max_connections = max_connections;
+    }
+
+    /// Sets how many connections a single TCP client address may hold, UNIX clients only count towards the total.
+    pub fn set_max_per_ip(&mut self, max_per_ip: usize) {
+        self.max_per_ip = max_per_ip;
+    }
+
+    /// Sets how many connections are accepted per second, a burst of up to that many is taken at once.
+    pub fn set_accept_rate(&mut self, accept_rate: usize) {
+        self.accept_rate = accept_rate;
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Why a connection was refused.
+pub enum Refusal {
+    /// The server serves as many connections as it takes.
+    ServerFull,
+    /// The address of the client holds as many connections as a single one may.
+    AddressFull(IpAddr),
+    /// Connections arrive faster than the accept rate.
+    RateExceeded,
+}
+
+impl std::fmt::Display for Refusal {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Refusal::ServerFull => write!(f, "too many connections"),
+            Refusal::AddressFull(address) => {
+                write!(f, "too many connections from {address}")
+            }
+            Refusal::RateExceeded => write!(f, "too many new connections, slow down"),
+        }
+    }
+}
+
+#[derive(Debug)]
+/// Decides which freshly accepted connections the server takes, and counts the connections of every TCP client address.
+pub struct Admission {
+    limits: ConnectionLimits,
+    per_ip: HashMap<IpAddr, usize>,
+    // The accepts left in the current burst, refilled at the accept rate
+    allowance: f64,
+    refilled: Instant,
+}
+
+impl Admission {
+    /// Creates a new [`Admission`] enforcing the given limits.
+    pub fn new(limits: ConnectionLimits) -> Self {
+        Self {
+            allowance: limits.accept_rate as f64,
+            limits,
+            per_ip: HashMap::new(),
+            refilled: Instant::now(),
+        }
+    }
+
+    /// Decides whether a connection is taken, a taken TCP connection is counted until it's released.
+    ///
+    /// # Arguments
+    ///
+    /// * `open: usize` - The number of connections the server is serving.
+    /// * `address: Option<IpAddr>` - The address of a TCP client, UNIX clients have none.
+    pub fn admit(&mut self, open: usize, address: Option<IpAddr>) -> Result<(), Refusal> {
+        self.admit_at(open, address, Instant::now())
+    }
+
+    fn admit_at(
+        &mut self,
+        open: usize,
+        address: Option<IpAddr>,
+        now: Instant,
+    ) -> Result<(), Refusal> {
+        if self.limits.max_connections > 0 && open >= self.limits.max_connections {
+            return Err(Refusal::ServerFull);
+        }
+
+        if let Some(address) = address {
+            let count = self.per_ip.get(&address).copied().unwrap_or(0);
+            if self.limits.max_per_ip > 0 && count >= self.limits.max_per_ip {
+                return Err(Refusal::AddressFull(address));
+            }
+        }
+
+        if self.limits.accept_rate > 0 {
+            let rate = self.limits.accept_rate as f64;
+            let elapsed = now.saturating_duration_since(self.refilled).as_secs_f64();
+            self.allowance = (self.allowance + elapsed * rate).min(rate);
+            self.refilled = now;
+
+            if self.allowance < 1.0 {
+                return Err(Refusal::RateExceeded);
+            }
+            self.allowance -= 1.0;
+        }
+
+        if let Some(address) = address {
+            *self.per_ip.entry(address).or_default() += 1;
+        }
+        Ok(())
+    }
+
+    /// Stops counting a closed connection of a TCP client.
+    pub fn release(&mut self, address: IpAddr) {
+        if let Some(count) = self.per_ip.get_mut(&address) {
+            *count -= 1;
+            if *count == 0 {
+                self.per_ip.remove(&address);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+    use std::time::Duration;
+
+    use super::*;
+
+    fn limits(max_connections: usize, max_per_ip: usize, accept_rate: usize) -> ConnectionLimits {
+        let mut limits = ConnectionLimits::default();
+        limits.set_max_connections(max_connections);
+        limits.set_max_per_ip(max_per_ip);
+        limits.set_accept_rate(accept_rate);
+        limits
+    }
+
+    const CLIENT: IpAddr = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1));
+    const OTHER_CLIENT: IpAddr = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2));
+
+    #[test]
+    fn refuses_connections_past_the_maximum() {
+        let mut admission = Admission::new(limits(2, 0, 0));
+
+        assert_eq!(admission.admit(0, None), Ok(()));
+        assert_eq!(admission.admit(1, Some(CLIENT)), Ok(()));
+        assert_eq!(admission.admit(2, None), Err(Refusal::ServerFull));
+        assert_eq!(
+            admission.admit(2, Some(OTHER_CLIENT)),
+            Err(Refusal::ServerFull)
+        );
+    }
+
+    #[test]
+    fn refuses_an_address_holding_too_many_connections() {
+        let mut admission = Admission::new(limits(0, 2, 0));
+
+        assert_eq!(admission.admit(0, Some(CLIENT)), Ok(()));
+        assert_eq!(admission.admit(1, Some(CLIENT)), Ok(()));
+        assert_eq!(
+            admission.admit(2, Some(CLIENT)),
+            Err(Refusal::AddressFull(CLIENT))
+        );
+        assert_eq!(admission.admit(2, Some(OTHER_CLIENT)), Ok(()));
+        // UNIX clients only count towards the total
+        assert_eq!(admission.admit(3, None), Ok(()));
+
+        admission.release(CLIENT);
+        assert_eq!(admission.admit(3, Some(CLIENT)), Ok(()));
+    }
+
+    #[test]
+    fn refills_the_accept_rate_over_time() {
+        let mut admission = Admission::new(limits(0, 0, 2));
+        let start = admission.refilled;
+
+        assert_eq!(admission.admit_at(0, None, start), Ok(()));
+        assert_eq!(admission.admit_at(1, None, start), Ok(()));
+        assert_eq!(
+            admission.admit_at(2, None, start),
+            Err(Refusal::RateExceeded)
+        );
+
+        // Half a second at 2 per second is worth one more connection
+        let later = start + Duration::from_millis(500);
+        assert_eq!(admission.admit_at(2, None, later), Ok(()));
+        assert_eq!(
+            admission.admit_at(3, None, later),
+            Err(Refusal::RateExceeded)
+        );
+
+        // A long pause refills no more than a single burst
+        let much_later = later + Duration::from_secs(60);
+        assert_eq!(admission.admit_at(3, None, much_later), Ok(()));
+        assert_eq!(admission.admit_at(4, None, much_later), Ok(()));
+        assert_eq!(
+            admission.admit_at(5, None, much_later),
+            Err(Refusal::RateExceeded)
+        );
+    }
+
+    #[test]
+    fn a_refused_connection_is_not_counted() {
+        let mut admission = Admission::new(limits(0, 1, 1));
+        let start = admission.refilled;
+
+        assert_eq!(admission.admit_at(0, Some(CLIENT), start), Ok(()));
+        assert_eq!(
+            admission.admit_at(1, Some(OTHER_CLIENT), start),
+            Err(Refusal::RateExceeded)
+        );
+        let later = start + Duration::from_secs(1);
+        assert_eq!(admission.admit_at(1, Some(OTHER_CLIENT), later), Ok(()));
+    }
+}
@@ -0,0 +1,163 @@
+use std::collections::HashMap;
+use std::ops::RangeBounds;
+use std::time::{Duration, Instant};
+
+/// Time between two heartbeats, unless configured otherwise.
+pub const DEFAULT_HEARTBEAT_INTERVAL_MS: u64 = 30000;
+
+/// Heartbeats a user may miss before it's disconnected, unless configured otherwise.
+pub const DEFAULT_MISSED_HEARTBEATS: u32 = 3;
+
+#[derive(Debug)]
+/// When every logged in user was last heard from.
+///
+/// Any command counts, not just a `HEARTBEAT`. A user who has been quiet for a whole interval is sent a `PING`,
+/// one who stays quiet for as many intervals as it may miss heartbeats is considered gone.
+pub struct Heartbeats {
+    interval: Duration,
+    missed_limit: u32,
+    last_seen: HashMap<u64, Instant>,
+}
+
+impl Default for Heartbeats {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_millis(DEFAULT_HEARTBEAT_INTERVAL_MS),
+            missed_limit: DEFAULT_MISSED_HEARTBEATS,
+            last_seen: HashMap::new(),
+        }
+    }
+}
+
+impl Heartbeats {
+    /// Sets the time between two heartbeats, a zero interval turns the heartbeats off.
+    pub fn set_interval(&mut self, interval: Duration) {
+        self.interval = interval;
+    }
+
+    /// Sets how many heartbeats a user may miss before it's disconnected, at least 2,
+    /// so a quiet user is always pinged before it's disconnected.
+    pub fn set_missed_limit(&mut self, missed_limit: u32) {
+        self.missed_limit = missed_limit.max(2);
+    }
+
+    /// Gets the time between two heartbeats, unless they're off.
+    pub fn interval(&self) -> Option<Duration> {
+        Some(self.interval).filter(|interval| !interval.is_zero())
+    }
+
+    /// Gets how many heartbeats a user may miss.
+    pub fn missed_limit(&self) -> u32 {
+        self.missed_limit
+    }
+
+    /// Starts tracking a user who just logged in.
+    pub fn register(&mut self, user_id: u64) {
+        self.last_seen.insert(user_id, Instant::now());
+    }
+
+    /// Stops tracking a user who left.
+    pub fn unregister(&mut self, user_id: u64) {
+        self.last_seen.remove(&user_id);
+    }
+
+    /// Records that a user was just heard from.
+    pub fn seen(&mut self, user_id: u64) {
+        if let Some(last_seen) = self.last_seen.get_mut(&user_id) {
+            *last_seen = Instant::now();
+        }
+    }
+
+    /// Gets the users who have been quiet for a whole interval, but not long enough to be gone.
+    pub fn idle_users(&self, now: Instant) -> Vec<u64> {
+        self.users_quiet_for(now, self.interval..self.interval * self.missed_limit)
+    }
+
+    /// Gets the users who missed too many heartbeats.
+    pub fn expired_users(&self, now: Instant) -> Vec<u64> {
+        self.users_quiet_for(now, self.interval * self.missed_limit..)
+    }
+
+    /// Gets the users who haven't been heard from for a time in the given range.
+    fn users_quiet_for(&self, now: Instant, quiet: impl RangeBounds<Duration>) -> Vec<u64> {
+        self.last_seen
+            .iter()
+            .filter(|(_, last_seen)| quiet.contains(&now.saturating_duration_since(**last_seen)))
+            .map(|(user_id, _)| *user_id)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INTERVAL: Duration = Duration::from_secs(10);
+
+    // Tests look at the users from this far in the future, so no one is last seen before the clock started
+    const LONGEST_QUIET: Duration = Duration::from_secs(100);
+
+    /// Tracks users last heard from the given times before `start`.
+    fn heartbeats(missed_limit: u32, start: Instant, quiet: &[(u64, Duration)]) -> Heartbeats {
+        let mut heartbeats = Heartbeats::default();
+        heartbeats.set_interval(INTERVAL);
+        heartbeats.set_missed_limit(missed_limit);
+        for (user_id, quiet_for) in quiet {
+            heartbeats.last_seen.insert(*user_id, start - *quiet_for);
+        }
+        heartbeats
+    }
+
+    fn sorted(mut users: Vec<u64>) -> Vec<u64> {
+        users.sort();
+        users
+    }
+
+    #[test]
+    fn pings_quiet_users_and_expires_gone_ones() {
+        let now = Instant::now() + LONGEST_QUIET;
+        let heartbeats = heartbeats(
+            3,
+            now,
+            &[
+                (1, Duration::from_secs(5)),
+                (2, INTERVAL),
+                (3, INTERVAL * 2),
+                (4, INTERVAL * 3),
+                (5, INTERVAL * 10),
+            ],
+        );
+
+        assert_eq!(sorted(heartbeats.idle_users(now)), vec![2, 3]);
+        assert_eq!(sorted(heartbeats.expired_users(now)), vec![4, 5]);
+    }
+
+    #[test]
+    fn a_user_is_pinged_before_it_expires_with_the_lowest_limit() {
+        let now = Instant::now() + LONGEST_QUIET;
+        let heartbeats = heartbeats(1, now, &[(1, INTERVAL), (2, INTERVAL * 2)]);
+
+        assert_eq!(heartbeats.missed_limit(), 2);
+        assert_eq!(heartbeats.idle_users(now), vec![1]);
+        assert_eq!(heartbeats.expired_users(now), vec![2]);
+    }
+
+    #[test]
+    fn any_command_counts_as_a_heartbeat() {
+        let now = Instant::now() + LONGEST_QUIET;
+        let mut heartbeats = heartbeats(3, now, &[(1, INTERVAL * 3)]);
+
+        heartbeats.seen(1);
+        let later = Instant::now();
+        assert!(heartbeats.idle_users(later).is_empty());
+        assert!(heartbeats.expired_users(later).is_empty());
+    }
+
+    #[test]
+    fn a_zero_interval_turns_heartbeats_off() {
+        let mut heartbeats = Heartbeats::default();
+        heartbeats.set_interval(Duration::ZERO);
+
+        assert_eq!(heartbeats.interval(), None);
+    }
+}
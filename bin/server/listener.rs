@@ -0,0 +1,219 @@
+use std::net::{SocketAddr, TcpListener, ToSocketAddrs};
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+
+use client_server_tcp::address::{tcp_addresses, UnixAddress};
+use socket2::{Domain, Protocol, Socket, Type};
+
+use crate::accounts::group_id;
+use crate::{ListenerConfig, ServerType};
+
+/// Longest queue of connections waiting to be accepted.
+const LISTEN_BACKLOG: i32 = 128;
+
+/// File mode creation mask set while a UNIX socket is bound, only the server can connect
+/// until the configured mode and group are applied.
+const BIND_UMASK: libc::mode_t = 0o177;
+
+/// A bound socket, ready to accept connections.
+pub enum BoundListener {
+    Tcp(TcpListener),
+    /// A UNIX listener, with the socket file to remove once it's done.
+    Unix {
+        listener: UnixListener,
+        address: UnixAddress,
+        socket_file: Option<SocketFile>,
+    },
+}
+
+#[derive(Debug, Default, Clone)]
+/// Mode and group given to the socket files of UNIX listeners, they are left to the system when not set.
+pub struct UnixPermissions {
+    mode: Option<u32>,
+    group: Option<u32>,
+}
+
+impl UnixPermissions {
+    /// Sets the mode of the socket files, given in octal, e.g. `660`.
+    pub fn set_mode(&mut self, mode: &str) -> Result<(), String> {
+        let mode = u32::from_str_radix(mode, 8)
+            .ok()
+            .filter(|mode| *mode <= 0o7777)
+            .ok_or(format!("invalid socket mode {mode}, use octal such as 660"))?;
+        self.mode = Some(mode);
+        Ok(())
+    }
+
+    /// Sets the group owning the socket files, by name or by id.
+    pub fn set_group(&mut self, group: &str) -> Result<(), String> {
+        self.group = Some(group_id(group)?);
+        Ok(())
+    }
+
+    /// Returns whether any permission was set.
+    fn is_set(&self) -> bool {
+        self.mode.is_some() || self.group.is_some()
+    }
+
+    /// Applies the mode and group to a socket file bound under [`BIND_UMASK`].
+    /// Without a mode, the file gets the one the process umask would have given it.
+    fn apply(&self, path: &Path, umask: libc::mode_t) -> std::io::Result<()> {
+        if let Some(group) = self.group {
+            std::os::unix::fs::chown(path, None, Some(group))?;
+        }
+        let mode = self.mode.unwrap_or(0o777 & !umask);
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))
+    }
+}
+
+#[derive(Debug)]
+/// The file of a bound UNIX socket, removed when dropped so no stale socket is left behind.
+pub struct SocketFile {
+    path: PathBuf,
+}
+
+impl Drop for SocketFile {
+    fn drop(&mut self) {
+        std::fs::remove_file(&self.path).unwrap_or_else(|err| match err.kind() {
+            std::io::ErrorKind::NotFound => (),
+            _ => eprintln!("error removing socket {}: {}", self.path.display(), err),
+        });
+    }
+}
+
+/// Binds the sockets of a listener, a TCP listener gets one for every address it's given.
+pub fn bind_listener(
+    config: &ListenerConfig,
+    permissions: &UnixPermissions,
+) -> std::io::Result<Vec<BoundListener>> {
+    match config.server_type {
+        ServerType::TCP => {
+            let mut listeners = vec![];
+            for address in tcp_addresses(&config.endpoint) {
+                listeners.extend(bind_tcp(&address)?.into_iter().map(BoundListener::Tcp));
+            }
+            Ok(listeners)
+        }
+        ServerType::UNIX => {
+            let address = UnixAddress::parse(&config.endpoint);
+            Ok(vec![bind_unix(&address, permissions)?])
+        }
+    }
+}
+
+/// Binds a UNIX socket, replacing a socket file left behind by a server that's gone.
+///
+/// # Arguments
+///
+/// * `address: &UnixAddress` - The path or the abstract name of the socket.
+/// * `permissions: &UnixPermissions` - The mode and group of the socket file, abstract sockets have no file to apply them to.
+pub fn bind_unix(
+    address: &UnixAddress,
+    permissions: &UnixPermissions,
+) -> std::io::Result<BoundListener> {
+    if let UnixAddress::Path(path) = address {
+        remove_stale_socket(path)?;
+    }
+
+    // A socket file with permissions is created accessible to the server alone, so no one can connect
+    // before its group and mode are applied
+    let socket_addr = address.socket_addr()?;
+    let restricted = matches!(address, UnixAddress::Path(_)) && permissions.is_set();
+    // SAFETY: umask only swaps the mask of the process, the listeners are bound before any other thread creates files.
+    let umask = restricted.then(|| unsafe { libc::umask(BIND_UMASK) });
+    let listener = UnixListener::bind_addr(&socket_addr);
+    if let Some(umask) = umask {
+        // SAFETY: see above, this puts back the mask the process had.
+        unsafe { libc::umask(umask) };
+    }
+    let listener = listener?;
+
+    let socket_file = match address {
+        UnixAddress::Path(path) => {
+            // The socket file is owned from here, so it's removed again if the permissions can't be set
+            let socket_file = SocketFile { path: path.clone() };
+            if let Some(umask) = umask {
+                permissions.apply(path, umask)?;
+            }
+            Some(socket_file)
+        }
+        UnixAddress::Abstract(_) => {
+            if permissions.is_set() {
+                eprintln!("socket mode and group don't apply to the abstract socket {address}");
+            }
+            None
+        }
+    };
+
+    Ok(BoundListener::Unix {
+        listener,
+        address: address.clone(),
+        socket_file,
+    })
+}
+
+/// Removes a socket file left behind by a server that's gone.
+///
+/// A socket another server still listens on is never removed, and neither is a file that isn't a socket.
+fn remove_stale_socket(path: &Path) -> std::io::Result<()> {
+    let metadata = match std::fs::symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(err) => return Err(err),
+    };
+
+    if !metadata.file_type().is_socket() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::AlreadyExists,
+            format!("{} exists and isn't a socket", path.display()),
+        ));
+    }
+
+    // Only a live server accepts the connection, the socket of a server that's gone refuses it
+    match UnixStream::connect(path) {
+        Ok(_) => Err(std::io::Error::new(
+            std::io::ErrorKind::AddrInUse,
+            format!("another server is listening on {}", path.display()),
+        )),
+        Err(err) if err.kind() == std::io::ErrorKind::ConnectionRefused => {
+            std::fs::remove_file(path)
+        }
+        Err(err) => Err(err),
+    }
+}
+
+/// Binds every address a TCP address resolves to, so a host name listens on all of its addresses.
+///
+/// # Arguments
+///
+/// * `address: &str` - A socket address or a host name with a port, e.g. `[::]:8080` or `localhost:8080`.
+pub fn bind_tcp(address: &str) -> std::io::Result<Vec<TcpListener>> {
+    let mut addresses: Vec<SocketAddr> = vec![];
+    for resolved in address.to_socket_addrs()? {
+        if !addresses.contains(&resolved) {
+            addresses.push(resolved);
+        }
+    }
+
+    addresses.iter().map(bind_tcp_address).collect()
+}
+
+/// Binds a single socket address.
+///
+/// The unspecified IPv6 address `[::]` is made dual-stack, so it accepts IPv4 clients too whatever the system default is.
+/// Any other IPv6 address only accepts IPv6 clients, so it can be bound next to an IPv4 address on the same port.
+fn bind_tcp_address(address: &SocketAddr) -> std::io::Result<TcpListener> {
+    let socket = Socket::new(
+        Domain::for_address(*address),
+        Type::STREAM,
+        Some(Protocol::TCP),
+    )?;
+    if let SocketAddr::V6(address) = address {
+        socket.set_only_v6(!address.ip().is_unspecified())?;
+    }
+    socket.set_reuse_address(true)?;
+    socket.bind(&(*address).into())?;
+    socket.listen(LISTEN_BACKLOG)?;
+    Ok(socket.into())
+}
@@ -0,0 +1,247 @@
+use std::os::fd::AsRawFd;
+
+use crate::accounts::{group_id, group_list, user_id, user_name};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+/// How clients of UNIX sockets log in, TCP clients always send the password.
+pub enum UnixAuthMode {
+    /// Everyone sends the server password.
+    #[default]
+    Password,
+    /// Only allowed local users can log in, and they don't send a password.
+    Peer,
+    /// Allowed local users log in without a password, everyone else sends it.
+    PeerOrPassword,
+}
+
+impl std::str::FromStr for UnixAuthMode {
+    type Err = String;
+
+    fn from_str(mode: &str) -> Result<Self, String> {
+        match mode {
+            "password" => Ok(UnixAuthMode::Password),
+            "peer" => Ok(UnixAuthMode::Peer),
+            "peer-or-password" => Ok(UnixAuthMode::PeerOrPassword),
+            _ => Err(format!(
+                "unknown auth mode {mode}, use password, peer or peer-or-password"
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Who is on the other end of a UNIX socket, as told by the kernel when the connection was made.
+pub struct PeerCredentials {
+    pub pid: i32,
+    pub uid: u32,
+    pub gid: u32,
+}
+
+impl PeerCredentials {
+    /// Reads the credentials of the process connected to a UNIX socket with `SO_PEERCRED`.
+    pub fn of(stream: &impl AsRawFd) -> std::io::Result<Self> {
+        let mut credentials = libc::ucred {
+            pid: 0,
+            uid: 0,
+            gid: 0,
+        };
+        let mut length = std::mem::size_of::<libc::ucred>() as libc::socklen_t;
+
+        // SAFETY: the option is read into a ucred of exactly the given length, which is what SO_PEERCRED writes.
+        let result = unsafe {
+            libc::getsockopt(
+                stream.as_raw_fd(),
+                libc::SOL_SOCKET,
+                libc::SO_PEERCRED,
+                &mut credentials as *mut libc::ucred as *mut libc::c_void,
+                &mut length,
+            )
+        };
+        if result != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+
+        Ok(Self {
+            pid: credentials.pid,
+            uid: credentials.uid,
+            gid: credentials.gid,
+        })
+    }
+}
+
+impl std::fmt::Display for PeerCredentials {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let name = user_name(self.uid).unwrap_or_else(|| "unknown user".to_string());
+        write!(
+            f,
+            "{name} (uid {}, gid {}, pid {})",
+            self.uid, self.gid, self.pid
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// The identity of a local user, mapped from the credentials of its connection.
+///
+/// A trusted user keeps it for as long as it's logged in, so what it may do can be decided from its uid and groups.
+pub struct LocalUser {
+    pub uid: u32,
+    pub name: Option<String>,
+    /// The primary group and the supplementary groups of the user.
+    pub groups: Vec<u32>,
+}
+
+impl LocalUser {
+    /// Looks up the name and the groups of the user on the other end of a UNIX socket.
+    pub fn of(peer: PeerCredentials) -> Self {
+        let name = user_name(peer.uid);
+        let groups = match &name {
+            Some(name) => group_list(name, peer.gid),
+            // A user without an entry has no supplementary groups to look up
+            None => vec![peer.gid],
+        };
+
+        Self {
+            uid: peer.uid,
+            name,
+            groups,
+        }
+    }
+}
+
+impl std::fmt::Display for LocalUser {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match &self.name {
+            Some(name) => write!(f, "{name} (uid {})", self.uid),
+            None => write!(f, "uid {}", self.uid),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// How a connection has to log in.
+pub enum PeerLogin {
+    /// The local user is allowed, it's logged in without a password.
+    Trusted(LocalUser),
+    /// The client has to send the server password.
+    Password,
+    /// The local user isn't allowed and can't log in with a password either.
+    Refused(PeerCredentials),
+}
+
+#[derive(Debug, Default, Clone)]
+/// The auth mode of UNIX sockets and the local users and groups allowed to log in without a password.
+pub struct PeerAuth {
+    mode: UnixAuthMode,
+    users: Vec<u32>,
+    groups: Vec<u32>,
+}
+
+impl PeerAuth {
+    /// Sets how clients of UNIX sockets log in.
+    pub fn set_mode(&mut self, mode: UnixAuthMode) {
+        self.mode = mode;
+    }
+
+    /// Allows a local user, given by name or by id.
+    pub fn trust_user(&mut self, user: &str) -> Result<(), String> {
+        self.users.push(user_id(user)?);
+        Ok(())
+    }
+
+    /// Allows every local user who is a member of the given group, as primary or supplementary group, by name or by id.
+    pub fn trust_group(&mut self, group: &str) -> Result<(), String> {
+        self.groups.push(group_id(group)?);
+        Ok(())
+    }
+
+    /// Checks that the mode and the allowlist make sense together.
+    pub fn validate(&self) -> Result<(), String> {
+        let has_allowlist = !self.users.is_empty() || !self.groups.is_empty();
+        match (self.mode, has_allowlist) {
+            (UnixAuthMode::Password, true) => Err(
+                "--trust-user and --trust-group need --unix-auth peer or peer-or-password"
+                    .to_string(),
+            ),
+            (UnixAuthMode::Peer | UnixAuthMode::PeerOrPassword, false) => {
+                Err("--unix-auth needs --trust-user or --trust-group".to_string())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Decides how a connection logs in from the credentials of its peer, which only UNIX connections have.
+    pub fn login(&self, peer: Option<PeerCredentials>) -> PeerLogin {
+        let Some(peer) = peer.filter(|_| self.mode != UnixAuthMode::Password) else {
+            return PeerLogin::Password;
+        };
+
+        // A group counts whether it's the primary group of the user or one of its supplementary groups
+        let user = LocalUser::of(peer);
+        let trusted = self.users.contains(&user.uid)
+            || user.groups.iter().any(|group| self.groups.contains(group));
+        match (self.mode, trusted) {
+            (_, true) => PeerLogin::Trusted(user),
+            (UnixAuthMode::Peer, false) => PeerLogin::Refused(peer),
+            (_, false) => PeerLogin::Password,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Every system has a root user in the root group
+    const ROOT: PeerCredentials = PeerCredentials {
+        pid: 1,
+        uid: 0,
+        gid: 0,
+    };
+    // No system hands out this uid
+    const STRANGER: PeerCredentials = PeerCredentials {
+        pid: 2,
+        uid: 4_000_000_000,
+        gid: 4_000_000_000,
+    };
+
+    fn peer_auth(mode: UnixAuthMode) -> PeerAuth {
+        let mut peer_auth = PeerAuth::default();
+        peer_auth.set_mode(mode);
+        peer_auth
+    }
+
+    #[test]
+    fn trusts_members_of_an_allowed_group_with_their_identity() {
+        let mut peer_auth = peer_auth(UnixAuthMode::Peer);
+        peer_auth.trust_group("0").unwrap();
+
+        let PeerLogin::Trusted(user) = peer_auth.login(Some(ROOT)) else {
+            panic!("root should be trusted");
+        };
+        assert_eq!(user.uid, 0);
+        assert_eq!(user.name.as_deref(), Some("root"));
+        assert!(user.groups.contains(&0));
+    }
+
+    #[test]
+    fn refuses_or_asks_unknown_users_for_the_password() {
+        let mut peer_auth = peer_auth(UnixAuthMode::Peer);
+        peer_auth.trust_user("0").unwrap();
+        assert_eq!(
+            peer_auth.login(Some(STRANGER)),
+            PeerLogin::Refused(STRANGER)
+        );
+
+        peer_auth.set_mode(UnixAuthMode::PeerOrPassword);
+        assert_eq!(peer_auth.login(Some(STRANGER)), PeerLogin::Password);
+        // TCP clients have no credentials, they always send the password
+        assert_eq!(peer_auth.login(None), PeerLogin::Password);
+    }
+
+    #[test]
+    fn password_mode_ignores_credentials() {
+        let peer_auth = peer_auth(UnixAuthMode::Password);
+        assert_eq!(peer_auth.login(Some(ROOT)), PeerLogin::Password);
+    }
+}
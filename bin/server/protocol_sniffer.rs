@@ -0,0 +1,135 @@
+use client_server_tcp::CodecKind;
+
+use crate::ReadWrite;
+
+/// Time to wait for the first bytes of a connection before assuming it's a person at a terminal.
+///
+/// Binary clients and browsers speak first, while `nc` users wait for the password prompt.
+pub const SNIFF_TIMEOUT_MS: u64 = 500;
+
+/// Largest HTTP request head accepted while waiting for its end.
+const MAX_HTTP_HEAD_SIZE: usize = 8 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// Protocol spoken on a connection, detected once from its first bytes.
+pub enum ConnectionProtocol {
+    /// The game protocol spoken by the client, see [`BinaryMessage`](client_server_tcp::BinaryMessage), in the detected codec.
+    Framed(CodecKind),
+    /// A browser asking for the spectator page.
+    Http,
+    /// A browser upgrading to a WebSocket, which then speaks the text protocol.
+    WebSocket,
+    /// Newline-terminated text lines, e.g. from `nc`.
+    Text,
+}
+
+/// Reads the first bytes of a connection and detects the protocol it speaks.
+///
+/// The bytes read while detecting are kept in the prefix, the handler of the protocol has to start with them.
+/// If nothing conclusive arrives within [`SNIFF_TIMEOUT_MS`], the caller treats the connection as text.
+///
+/// # Arguments
+///
+/// * `stream: &mut impl ReadWrite` - The freshly accepted stream, it doesn't block.
+/// * `prefix: &mut Vec<u8>` - The bytes read so far, kept between calls.
+///
+/// # Returns
+///
+/// * [`Option<ConnectionProtocol>`] - The protocol, or `None` until enough bytes have arrived.
+pub fn sniff_protocol(
+    stream: &mut impl ReadWrite,
+    prefix: &mut Vec<u8>,
+) -> std::io::Result<Option<ConnectionProtocol>> {
+    loop {
+        if let Some(protocol) = classify(prefix) {
+            return Ok(Some(protocol));
+        }
+
+        let mut buffer = [0u8; 4096];
+        match stream.read(&mut buffer) {
+            Ok(0) => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::UnexpectedEof,
+                    "connection closed before its protocol was detected",
+                ))
+            }
+            Ok(size) => prefix.extend_from_slice(&buffer[..size]),
+            Err(e) => match e.kind() {
+                std::io::ErrorKind::WouldBlock => return Ok(None),
+                _ => return Err(e),
+            },
+        }
+    }
+}
+
+/// Detects the protocol from the bytes received so far, or returns `None` if more bytes are needed.
+fn classify(prefix: &[u8]) -> Option<ConnectionProtocol> {
+    let first = *prefix.first()?;
+
+    // Every binary or legacy frame starts with the high byte of its length, which is 0 for any frame we accept,
+    // and the JSON codec starts with an object. Text never starts with a control character.
+    if first == b'{' || first < 0x20 && !matches!(first, b'\t' | b'\r' | b'\n') {
+        return CodecKind::detect(prefix).map(ConnectionProtocol::Framed);
+    }
+
+    // HTTP methods are uppercase, so anything else is a line of text
+    if !first.is_ascii_uppercase() {
+        return Some(ConnectionProtocol::Text);
+    }
+
+    let Some(line_end) = prefix.iter().position(|&byte| byte == b'\n') else {
+        return match prefix.len() > MAX_HTTP_HEAD_SIZE {
+            true => Some(ConnectionProtocol::Text),
+            false => None,
+        };
+    };
+
+    if !is_request_line(&prefix[..line_end]) {
+        return Some(ConnectionProtocol::Text);
+    }
+
+    match split_http_head(prefix) {
+        Some((head, _)) if is_websocket_upgrade(head) => Some(ConnectionProtocol::WebSocket),
+        Some(_) => Some(ConnectionProtocol::Http),
+        // The head is too large, the spectator handler refuses whatever it is
+        None if prefix.len() > MAX_HTTP_HEAD_SIZE => Some(ConnectionProtocol::Http),
+        None => None,
+    }
+}
+
+/// Returns true for an HTTP/1.x request line, e.g. `GET / HTTP/1.1`.
+fn is_request_line(line: &[u8]) -> bool {
+    let line = String::from_utf8_lossy(line);
+    let parts: Vec<&str> = line.trim_end().split(' ').collect();
+
+    match parts.as_slice() {
+        [method, target, version] => {
+            !method.is_empty()
+                && method.bytes().all(|byte| byte.is_ascii_uppercase())
+                && target.starts_with('/')
+                && version.starts_with("HTTP/1.")
+        }
+        _ => false,
+    }
+}
+
+/// Returns true if the request head asks to upgrade the connection to a WebSocket.
+fn is_websocket_upgrade(head: &[u8]) -> bool {
+    http_header(head, "upgrade").is_some_and(|value| value.eq_ignore_ascii_case("websocket"))
+}
+
+/// Splits the bytes into the HTTP request head, without its terminating empty line, and the bytes after it.
+pub fn split_http_head(bytes: &[u8]) -> Option<(&[u8], &[u8])> {
+    let end = bytes.windows(4).position(|window| window == b"\r\n\r\n")?;
+    Some((&bytes[..end], &bytes[end + 4..]))
+}
+
+/// Gets the value of a header from the HTTP request head, the name is matched case-insensitively.
+pub fn http_header(head: &[u8], name: &str) -> Option<String> {
+    String::from_utf8_lossy(head)
+        .split("\r\n")
+        .skip(1)
+        .filter_map(|line| line.split_once(':'))
+        .find(|(header, _)| header.trim().eq_ignore_ascii_case(name))
+        .map(|(_, value)| value.trim().to_string())
+}
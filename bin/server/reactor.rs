@@ -0,0 +1,833 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::io::{ErrorKind, Read, Write};
+use std::net::{IpAddr, Shutdown};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+
+use client_server_tcp::text_protocol::format_response;
+use client_server_tcp::{BinaryMessage, CodecKind, ErrorCode, ServerMessageResponse};
+use mio::event::Source;
+use mio::net::{TcpListener, TcpStream, UnixListener, UnixStream};
+use mio::{Events, Interest, Poll, Registry, Token, Waker};
+use rustls::{ServerConfig, ServerConnection};
+
+use crate::admission::{Admission, Refusal};
+use crate::peer_auth::PeerCredentials;
+use crate::server_process::Connection;
+use crate::ServerData;
+
+/// Token of the waker, woken when the server starts shutting down.
+const WAKER: Token = Token(0);
+
+/// Most events handled per call to poll, the rest are picked up by the next call.
+const EVENTS_CAPACITY: usize = 1024;
+
+/// Time a connection may go without taking any of the bytes waiting for it, unless configured otherwise.
+pub const DEFAULT_WRITE_TIMEOUT_MS: u64 = 30000;
+
+/// Bytes that may wait to be sent on a connection before its requests aren't read anymore,
+/// a client that doesn't read its replies can't make the server buffer without end.
+const MAX_PENDING_OUTPUT: usize = 1024 * 1024;
+
+/// Bytes read from a refused connection before it's closed, the rest is left unread.
+const REFUSAL_DISCARD: usize = 4096;
+
+/// A connected TCP or UNIX socket, it never blocks.
+pub enum Socket {
+    Tcp(TcpStream),
+    Unix(UnixStream),
+}
+
+impl Socket {
+    /// Stops writing, the peer reads the end of the stream once it has read everything sent before.
+    fn shutdown_write(&self) -> std::io::Result<()> {
+        match self {
+            Socket::Tcp(stream) => stream.shutdown(Shutdown::Write),
+            Socket::Unix(stream) => stream.shutdown(Shutdown::Write),
+        }
+    }
+}
+
+impl Read for Socket {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self {
+            Socket::Tcp(stream) => stream.read(buf),
+            Socket::Unix(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for Socket {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            Socket::Tcp(stream) => stream.write(buf),
+            Socket::Unix(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            Socket::Tcp(stream) => stream.flush(),
+            Socket::Unix(stream) => stream.flush(),
+        }
+    }
+}
+
+impl Source for Socket {
+    fn register(
+        &mut self,
+        registry: &Registry,
+        token: Token,
+        interests: Interest,
+    ) -> std::io::Result<()> {
+        match self {
+            Socket::Tcp(stream) => stream.register(registry, token, interests),
+            Socket::Unix(stream) => stream.register(registry, token, interests),
+        }
+    }
+
+    fn reregister(
+        &mut self,
+        registry: &Registry,
+        token: Token,
+        interests: Interest,
+    ) -> std::io::Result<()> {
+        match self {
+            Socket::Tcp(stream) => stream.reregister(registry, token, interests),
+            Socket::Unix(stream) => stream.reregister(registry, token, interests),
+        }
+    }
+
+    fn deregister(&mut self, registry: &Registry) -> std::io::Result<()> {
+        match self {
+            Socket::Tcp(stream) => stream.deregister(registry),
+            Socket::Unix(stream) => stream.deregister(registry),
+        }
+    }
+}
+
+/// The bytes of a connection, read straight from its socket and written to a buffer that's sent when the socket takes it.
+///
+/// Writing never blocks, so the connection handlers write their replies whole and the reactor sends them as the socket
+/// becomes writable. Reading returns [`ErrorKind::WouldBlock`] once everything that arrived is read.
+/// TLS connections are decrypted and encrypted here, their handshake is done as the data flows.
+pub struct Transport {
+    socket: Socket,
+    tls: Option<ServerConnection>,
+    outbound: Vec<u8>,
+    // When the socket last took some bytes, while some are waiting
+    stalled_since: Option<Instant>,
+}
+
+impl Transport {
+    /// Creates a new [`Transport`], encrypted if a TLS connection is given.
+    pub fn new(socket: Socket, tls: Option<ServerConnection>) -> Self {
+        Self {
+            socket,
+            tls,
+            outbound: Vec::new(),
+            stalled_since: None,
+        }
+    }
+
+    /// Checks whether the TLS handshake is still going on.
+    pub fn is_handshaking(&self) -> bool {
+        self.tls.as_ref().is_some_and(|tls| tls.is_handshaking())
+    }
+
+    /// Checks whether some bytes are still waiting for the socket to take them.
+    pub fn has_pending_output(&self) -> bool {
+        !self.outbound.is_empty() || self.tls.as_ref().is_some_and(|tls| tls.wants_write())
+    }
+
+    /// Gets the number of bytes waiting to be sent.
+    pub fn pending_output(&self) -> usize {
+        self.outbound.len()
+    }
+
+    /// Gets the time since which the socket hasn't taken any of the bytes waiting for it.
+    pub fn stalled_since(&self) -> Option<Instant> {
+        self.stalled_since
+    }
+
+    /// Sends as much of the buffered bytes as the socket takes without blocking, the rest waits for the next call.
+    pub fn send_pending(&mut self) -> std::io::Result<()> {
+        let sent = self.write_socket()?;
+
+        // The clock only runs while bytes are waiting and the socket takes none of them
+        self.stalled_since = match (self.has_pending_output(), sent, self.stalled_since) {
+            (false, _, _) => None,
+            (true, false, Some(since)) => Some(since),
+            (true, _, _) => Some(Instant::now()),
+        };
+        Ok(())
+    }
+
+    /// Writes to the socket until it would block, returning whether it took anything.
+    fn write_socket(&mut self) -> std::io::Result<bool> {
+        let mut sent = false;
+        let Some(tls) = &mut self.tls else {
+            while !self.outbound.is_empty() {
+                match self.socket.write(&self.outbound) {
+                    Ok(0) => return Err(ErrorKind::WriteZero.into()),
+                    Ok(size) => {
+                        self.outbound.drain(..size);
+                        sent = true;
+                    }
+                    Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                    Err(e) => return Err(e),
+                }
+            }
+            return Ok(sent);
+        };
+
+        loop {
+            // rustls keeps the plaintext until the handshake is done, then encrypts it into records
+            if !self.outbound.is_empty() {
+                let size = tls.writer().write(&self.outbound)?;
+                self.outbound.drain(..size);
+            }
+            if !tls.wants_write() {
+                return Ok(sent);
+            }
+            match tls.write_tls(&mut self.socket) {
+                Ok(size) => sent |= size > 0,
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return Ok(sent),
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+impl Read for Transport {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let Some(tls) = &mut self.tls else {
+            return self.socket.read(buf);
+        };
+
+        loop {
+            match tls.reader().read(buf) {
+                Ok(size) => return Ok(size),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => (),
+                Err(e) => return Err(e),
+            }
+
+            // No plaintext is buffered, so decrypt whatever the socket has
+            if tls.read_tls(&mut self.socket)? == 0 {
+                return Ok(0);
+            }
+            tls.process_new_packets().map_err(std::io::Error::other)?;
+        }
+    }
+}
+
+impl Write for Transport {
+    /// Buffers the bytes, they are sent by [`Transport::send_pending`].
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.outbound.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// A connection just accepted by a [`Listener`].
+struct Accepted {
+    socket: Socket,
+    /// The TLS settings the connection is encrypted with.
+    tls_config: Option<Arc<ServerConfig>>,
+    /// The credentials of the process on the other end, UNIX clients only.
+    peer: Option<PeerCredentials>,
+    /// The address of the client, TCP clients only.
+    address: Option<IpAddr>,
+}
+
+/// A socket the reactor accepts connections on.
+pub enum Listener {
+    /// A TCP listener, its connections are encrypted if TLS is set.
+    Tcp(TcpListener, Option<Arc<ServerConfig>>),
+    /// A UNIX listener, the kernel tells who every client is.
+    Unix(UnixListener),
+    /// The text gateway, its connections always speak the text protocol, in plain text.
+    Text(TcpListener),
+}
+
+impl Listener {
+    /// Accepts the next waiting connection.
+    fn accept(&self) -> std::io::Result<Accepted> {
+        match self {
+            Listener::Tcp(listener, tls_config) => {
+                let (stream, address) = listener.accept()?;
+                Ok(Accepted {
+                    socket: Socket::Tcp(stream),
+                    tls_config: tls_config.clone(),
+                    peer: None,
+                    address: Some(address.ip()),
+                })
+            }
+            Listener::Text(listener) => {
+                let (stream, address) = listener.accept()?;
+                Ok(Accepted {
+                    socket: Socket::Tcp(stream),
+                    tls_config: None,
+                    peer: None,
+                    address: Some(address.ip()),
+                })
+            }
+            Listener::Unix(listener) => {
+                let (stream, _) = listener.accept()?;
+
+                // The kernel tells who connected, so trusted local users can skip the password
+                let peer = match PeerCredentials::of(&stream) {
+                    Ok(peer) => Some(peer),
+                    Err(err) => {
+                        eprintln!("error reading the credentials of a UNIX client: {err}");
+                        None
+                    }
+                };
+                Ok(Accepted {
+                    socket: Socket::Unix(stream),
+                    tls_config: None,
+                    peer,
+                    address: None,
+                })
+            }
+        }
+    }
+
+    /// Tells a refused client why before closing its connection.
+    ///
+    /// The client hasn't said which codec it speaks yet, so it gets a binary frame, which the client reads before
+    /// its handshake, and a client of the text gateway gets a line. A TLS client is closed without one,
+    /// nothing can be sent to it before its handshake.
+    fn refuse(&self, mut socket: Socket, refusal: Refusal) {
+        let response = match refusal {
+            Refusal::RateExceeded => ServerMessageResponse::Error {
+                code: ErrorCode::RateLimited,
+                message: refusal.to_string(),
+            },
+            _ => ServerMessageResponse::Busy(refusal.to_string()),
+        };
+        let reply = match self {
+            Listener::Tcp(_, Some(_)) => return,
+            Listener::Text(_) => format!("{}\n", format_response(&response)).into_bytes(),
+            _ => CodecKind::Binary
+                .new_codec()
+                .encode(&BinaryMessage::from(response).into_push())
+                .unwrap_or_default()
+                .concat(),
+        };
+
+        // The reply is tiny, a fresh socket always takes it whole
+        if let Err(err) = socket.write_all(&reply) {
+            eprintln!("error refusing a connection: {err}");
+            return;
+        }
+        let _ = socket.shutdown_write();
+
+        // Closing with unread bytes resets the connection, which could discard the reply before the client reads it.
+        // Whatever already arrived is read once, a client that keeps sending mustn't hold up the reactor
+        let mut discard = [0u8; REFUSAL_DISCARD];
+        let _ = socket.read(&mut discard);
+    }
+
+    fn source(&mut self) -> &mut dyn Source {
+        match self {
+            Listener::Tcp(listener, _) | Listener::Text(listener) => listener,
+            Listener::Unix(listener) => listener,
+        }
+    }
+}
+
+/// Serves every connection of the server from a single thread.
+///
+/// The sockets never block, the reactor waits until one of them is readable or writable and handles just that one,
+/// so a message is passed on as soon as it arrives and an idle client costs no thread.
+pub struct Reactor {
+    poll: Poll,
+    waker: Arc<Waker>,
+    listeners: Vec<Listener>,
+    connections: HashMap<Token, Connection>,
+    // Deadlines of connections that are detecting their protocol or can't send, stale ones are skipped
+    timers: BTreeSet<(Instant, Token)>,
+    next_token: usize,
+    // The connection each logged in user is on, messages for the user are delivered there
+    users: HashMap<u64, Token>,
+    // Connections whose users sent to full queues under the block policy, their messages are held back
+    blocked: HashMap<Token, u64>,
+    // Connections that stopped handling what arrived at the limit of a readiness event, they're read again next turn
+    unread: HashSet<Token>,
+    write_timeout: Duration,
+    admission: Admission,
+    // The client address of every TCP connection, counted by the admission until the connection is closed
+    addresses: HashMap<Token, IpAddr>,
+    heartbeat_interval: Option<Duration>,
+    // When the users are checked for heartbeats next, unless heartbeats are off
+    next_heartbeat: Option<Instant>,
+    server: Arc<RwLock<ServerData>>,
+}
+
+impl Reactor {
+    /// Creates a new [`Reactor`] accepting connections on the given listeners.
+    ///
+    /// # Arguments
+    ///
+    /// * `server: Arc<RwLock<ServerData>>` - The state shared by every connection.
+    /// * `listeners: Vec<Listener>` - The sockets to accept connections on.
+    pub fn new(
+        server: Arc<RwLock<ServerData>>,
+        mut listeners: Vec<Listener>,
+    ) -> std::io::Result<Self> {
+        let poll = Poll::new()?;
+        let waker = Arc::new(Waker::new(poll.registry(), WAKER)?);
+
+        // Listeners take the tokens right after the waker, connections the ones after them
+        for (index, listener) in listeners.iter_mut().enumerate() {
+            poll.registry()
+                .register(listener.source(), Token(index + 1), Interest::READABLE)?;
+        }
+
+        let write_timeout = server.read().unwrap().write_timeout;
+        let limits = server.read().unwrap().connection_limits.clone();
+        let heartbeat_interval = server.read().unwrap().heartbeats.interval();
+        Ok(Self {
+            poll,
+            waker,
+            next_token: listeners.len() + 1,
+            listeners,
+            connections: HashMap::new(),
+            timers: BTreeSet::new(),
+            users: HashMap::new(),
+            blocked: HashMap::new(),
+            unread: HashSet::new(),
+            write_timeout,
+            admission: Admission::new(limits),
+            addresses: HashMap::new(),
+            heartbeat_interval,
+            next_heartbeat: heartbeat_interval.map(|interval| Instant::now() + interval),
+            server,
+        })
+    }
+
+    /// Gets the waker of the reactor, other threads wake it when there's something for it to do.
+    pub fn waker(&self) -> Arc<Waker> {
+        self.waker.clone()
+    }
+
+    /// Runs the event loop until the server has shut down.
+    ///
+    /// Once the server shuts down no connection is accepted anymore, every client is told why and the loop ends
+    /// when all of them are closed or the shutdown deadline has passed.
+    pub fn run(mut self) -> std::io::Result<()> {
+        let mut events = Events::with_capacity(EVENTS_CAPACITY);
+        let mut shutdown_deadline = None;
+
+        loop {
+            // Connections with something left to handle don't wait for new events
+            let timeout = match self.unread.is_empty() {
+                true => self
+                    .next_deadline(shutdown_deadline)
+                    .map(|deadline| deadline.saturating_duration_since(Instant::now())),
+                false => Some(Duration::ZERO),
+            };
+            if let Err(err) = self.poll.poll(&mut events, timeout) {
+                // A signal arriving while waiting is handled by its own thread
+                if err.kind() == ErrorKind::Interrupted {
+                    continue;
+                }
+                return Err(err);
+            }
+
+            for event in events.iter() {
+                match event.token() {
+                    // Only wakes the reactor up, the shutdown is checked below
+                    WAKER => (),
+                    Token(index) if index <= self.listeners.len() => {
+                        if shutdown_deadline.is_none() {
+                            self.accept(index - 1);
+                        }
+                    }
+                    token => self.process(token),
+                }
+            }
+            self.process_unread();
+            self.expire_timers();
+            self.check_heartbeats();
+            self.deliver_messages();
+
+            if shutdown_deadline.is_none() {
+                shutdown_deadline = self.server.read().unwrap().shutdown_deadline();
+                if shutdown_deadline.is_some() {
+                    self.shutdown();
+                }
+            }
+
+            if let Some(deadline) = shutdown_deadline {
+                if self.connections.is_empty() {
+                    return Ok(());
+                }
+                if Instant::now() >= deadline {
+                    eprintln!(
+                        "{} connections didn't finish before the shutdown deadline, closing them anyway",
+                        self.connections.len()
+                    );
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    /// Accepts every waiting connection of a listener.
+    ///
+    /// A connection over the limits of the server is told it's busy and closed right away.
+    fn accept(&mut self, index: usize) {
+        loop {
+            let Accepted {
+                socket,
+                tls_config,
+                peer,
+                address,
+            } = match self.listeners[index].accept() {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    if e.kind() != ErrorKind::WouldBlock {
+                        eprintln!("error accepting a connection: {e}");
+                    }
+                    return;
+                }
+            };
+
+            if let Err(refusal) = self.admission.admit(self.connections.len(), address) {
+                eprintln!("refusing a connection: {refusal}");
+                self.server.write().unwrap().record_refused_connection();
+                self.listeners[index].refuse(socket, refusal);
+                continue;
+            }
+
+            let tls = match tls_config.map(ServerConnection::new).transpose() {
+                Ok(tls) => tls,
+                Err(err) => {
+                    eprintln!("error starting a TLS connection: {err}");
+                    if let Some(address) = address {
+                        self.admission.release(address);
+                    }
+                    continue;
+                }
+            };
+
+            let token = Token(self.next_token);
+            self.next_token += 1;
+            if let Some(address) = address {
+                self.addresses.insert(token, address);
+            }
+
+            let mut transport = Transport::new(socket, tls);
+            if let Err(err) = self.poll.registry().register(
+                &mut transport.socket,
+                token,
+                Interest::READABLE | Interest::WRITABLE,
+            ) {
+                eprintln!("error registering a connection: {err}");
+                if let Some(address) = self.addresses.remove(&token) {
+                    self.admission.release(address);
+                }
+                continue;
+            }
+
+            let connection = match self.listeners[index] {
+                Listener::Text(_) => Connection::new_text(transport, peer, self.server.clone()),
+                _ => Connection::new(transport, peer),
+            };
+            println!("accepted a connection");
+            self.finish(token, connection);
+        }
+    }
+
+    /// Handles whatever the socket of a connection is ready for.
+    ///
+    /// A connection isn't read from while it waits for a full queue, or while more than [`MAX_PENDING_OUTPUT`] bytes
+    /// wait to be sent on it. It's read again once that's sorted out.
+    fn process(&mut self, token: Token) {
+        let Some(mut connection) = self.connections.remove(&token) else {
+            return;
+        };
+
+        // Sending first makes room for the replies of what's read next
+        if let Err(err) = connection.transport().send_pending() {
+            eprintln!("error writing to a connection: {err}");
+            self.close(token, connection);
+            return;
+        }
+
+        let connection = if self.blocked.contains_key(&token)
+            || connection.transport().pending_output() > MAX_PENDING_OUTPUT
+        {
+            connection
+        } else {
+            connection.on_ready(self.server.clone())
+        };
+        self.finish(token, connection);
+    }
+
+    /// Handles more of what arrived on the connections that stopped at the limit of their last readiness event.
+    fn process_unread(&mut self) {
+        let unread: Vec<Token> = self.unread.drain().collect();
+        for token in unread {
+            self.process(token);
+        }
+    }
+
+    /// Hands the messages users sent each other to the connections of their recipients.
+    ///
+    /// A recipient gets its messages once everything sent to it before is gone, until then they wait in its queue.
+    /// The senders resumed afterwards may queue more, those are handed over right away as well.
+    fn deliver_messages(&mut self) {
+        loop {
+            self.hand_over_messages();
+            if !self.resume_senders() {
+                return;
+            }
+        }
+    }
+
+    /// Moves the waiting messages to the connections of their recipients.
+    fn hand_over_messages(&mut self) {
+        // Users who couldn't keep up are let go, the messages waiting for them are dropped
+        let slow = self.server.write().unwrap().router.take_slow();
+        for user_id in slow {
+            let Some(&token) = self.users.get(&user_id) else {
+                continue;
+            };
+            if let Some(connection) = self.connections.remove(&token) {
+                eprintln!("closing the connection of user {user_id}, its queue is full");
+                self.close(token, connection);
+            }
+        }
+
+        let waiting = self.server.read().unwrap().router.waiting_users();
+        for user_id in waiting {
+            let Some(&token) = self.users.get(&user_id) else {
+                eprintln!("no connection for user {user_id}, dropping its messages");
+                self.server.write().unwrap().router.take(user_id);
+                continue;
+            };
+            let Some(mut connection) = self.connections.remove(&token) else {
+                continue;
+            };
+            if connection.transport().has_pending_output() {
+                self.connections.insert(token, connection);
+                continue;
+            }
+
+            let messages = self.server.write().unwrap().router.take(user_id);
+            connection.deliver(messages, self.server.clone());
+            self.finish(token, connection);
+        }
+    }
+
+    /// Queues the held back messages that fit, and reads again from the connections whose messages are all queued,
+    /// returning whether any was read.
+    fn resume_senders(&mut self) -> bool {
+        let ready: Vec<Token> = {
+            let mut server = self.server.write().unwrap();
+            server.router.release_held();
+            self.blocked
+                .iter()
+                .filter(|(_, user_id)| !server.router.is_blocked(**user_id))
+                .map(|(token, _)| *token)
+                .collect()
+        };
+
+        let resumed = !ready.is_empty();
+        for token in ready {
+            self.blocked.remove(&token);
+            // Whatever arrived in the meantime won't make the socket readable again
+            self.process(token);
+        }
+        resumed
+    }
+
+    /// Tells every client the server is shutting down, and stops accepting connections.
+    fn shutdown(&mut self) {
+        // Every connection is about to close, there's no point in checking heartbeats anymore
+        self.next_heartbeat = None;
+
+        for listener in &mut self.listeners {
+            if let Err(err) = self.poll.registry().deregister(listener.source()) {
+                eprintln!("error closing a listener: {err}");
+            }
+        }
+
+        let tokens: Vec<Token> = self.connections.keys().copied().collect();
+        for token in tokens {
+            let Some(mut connection) = self.connections.remove(&token) else {
+                continue;
+            };
+            connection.shutdown(self.server.clone());
+            self.finish(token, connection);
+        }
+    }
+
+    /// Handles connections whose deadline has passed.
+    fn expire_timers(&mut self) {
+        let now = Instant::now();
+        while let Some(&(deadline, token)) = self.timers.first() {
+            if deadline > now {
+                return;
+            }
+            self.timers.pop_first();
+
+            let Some(mut connection) = self.connections.remove(&token) else {
+                continue;
+            };
+
+            // A client that doesn't read anymore can't hold on to what's waiting for it
+            let stalled = connection.transport().stalled_since();
+            if stalled.is_some_and(|since| since + self.write_timeout <= now) {
+                eprintln!(
+                    "closing a connection that took nothing sent to it for {:?}",
+                    self.write_timeout
+                );
+                self.close(token, connection);
+                continue;
+            }
+
+            // The connection may have moved on since the timer was set
+            let connection = match connection.deadline() {
+                Some(current) if current <= now => connection.on_deadline(self.server.clone()),
+                _ => connection,
+            };
+            self.finish(token, connection);
+        }
+    }
+
+    /// Pings the users who have been quiet for a whole interval, and disconnects the ones who missed too many heartbeats.
+    fn check_heartbeats(&mut self) {
+        let (Some(interval), Some(due)) = (self.heartbeat_interval, self.next_heartbeat) else {
+            return;
+        };
+        let now = Instant::now();
+        if now < due {
+            return;
+        }
+        self.next_heartbeat = Some(now + interval);
+
+        let (expired, idle, missed_limit) = {
+            let server = self.server.read().unwrap();
+            (
+                server.heartbeats.expired_users(now),
+                server.heartbeats.idle_users(now),
+                server.heartbeats.missed_limit(),
+            )
+        };
+
+        for user_id in expired {
+            eprintln!("user {user_id} missed {missed_limit} heartbeats, disconnecting it");
+            let message = format!(
+                "no heartbeat for {:?}, disconnecting",
+                interval * missed_limit
+            );
+
+            let Some(&token) = self.users.get(&user_id) else {
+                // A user without a connection is only logged out
+                let mut server = self.server.write().unwrap();
+                server.cancel_game(user_id);
+                let _ = server.drop_user(user_id);
+                continue;
+            };
+            if let Some(mut connection) = self.connections.remove(&token) {
+                connection.time_out(message, self.server.clone());
+                self.finish(token, connection);
+            }
+        }
+
+        // A user whose queue is full isn't reading anyway, so no sender is ever held up by a ping
+        let mut server = self.server.write().unwrap();
+        for user_id in idle {
+            if server.router.has_room(user_id) {
+                let ping = BinaryMessage::from(ServerMessageResponse::Ping);
+                if let Err(err) = server.send_message(None, user_id, ping) {
+                    eprintln!("error pinging user {user_id}: {err}");
+                }
+            }
+        }
+    }
+
+    /// Gets the earliest deadline the reactor has to wake up for.
+    fn next_deadline(&self, shutdown_deadline: Option<Instant>) -> Option<Instant> {
+        let timer = self.timers.first().map(|(deadline, _)| *deadline);
+        [timer, self.next_heartbeat, shutdown_deadline]
+            .into_iter()
+            .flatten()
+            .min()
+    }
+
+    /// Sends what a connection has queued and keeps it, or closes it once it's done and everything is sent.
+    fn finish(&mut self, token: Token, mut connection: Connection) {
+        if let Some(user_id) = connection.local_id() {
+            self.users.insert(user_id, token);
+
+            // The user sent to full queues under the block policy, it isn't read until its messages are queued
+            if self.server.read().unwrap().router.is_blocked(user_id) {
+                self.blocked.insert(token, user_id);
+            }
+        }
+
+        if let Err(err) = connection.transport().send_pending() {
+            eprintln!("error writing to a connection: {err}");
+            self.close(token, connection);
+            return;
+        }
+
+        if connection.is_finished() && !connection.transport().has_pending_output() {
+            self.close(token, connection);
+            return;
+        }
+
+        // A blocked connection, or one with too much to send, is read again once that's sorted out
+        if connection.has_unread()
+            && !self.blocked.contains_key(&token)
+            && connection.transport().pending_output() <= MAX_PENDING_OUTPUT
+        {
+            self.unread.insert(token);
+        }
+
+        let write_deadline = connection
+            .transport()
+            .stalled_since()
+            .map(|since| since + self.write_timeout);
+        for deadline in [connection.deadline(), write_deadline]
+            .into_iter()
+            .flatten()
+        {
+            self.timers.insert((deadline, token));
+        }
+        self.connections.insert(token, connection);
+    }
+
+    /// Closes a connection, its user is logged out if it was still logged in.
+    fn close(&mut self, token: Token, mut connection: Connection) {
+        self.blocked.remove(&token);
+        self.unread.remove(&token);
+        if let Some(address) = self.addresses.remove(&token) {
+            self.admission.release(address);
+        }
+        // The ID may belong to someone else by now, if the user left and a new one logged in
+        if let Some(user_id) = connection.user() {
+            if self.users.get(&user_id) == Some(&token) {
+                self.users.remove(&user_id);
+                // The user is usually removed already, when its connection ended cleanly
+                let _ = self.server.write().unwrap().drop_user(user_id);
+            }
+        }
+        if let Err(err) = self
+            .poll
+            .registry()
+            .deregister(&mut connection.transport().socket)
+        {
+            eprintln!("error closing a connection: {err}");
+        }
+    }
+}
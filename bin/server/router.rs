@@ -0,0 +1,385 @@
+use std::collections::{HashMap, VecDeque};
+
+use client_server_tcp::{BinaryMessage, ErrorCode};
+
+use crate::server_commands::ServerCommandError;
+
+/// Messages that may wait for a user before the overflow policy kicks in, unless configured otherwise.
+pub const DEFAULT_QUEUE_LIMIT: usize = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+/// What happens to a message for a user whose outbound queue is full, i.e. a client that doesn't read fast enough.
+pub enum OverflowPolicy {
+    /// The oldest chat message waiting is dropped to make room. Game events are never dropped,
+    /// a user with nothing but game events waiting is disconnected.
+    #[default]
+    DropOldestChat,
+    /// The user is disconnected.
+    Disconnect,
+    /// The message is held back and the sender isn't read from until it's queued. Events of the server itself
+    /// have no sender to hold up, the user is disconnected.
+    BlockSender,
+}
+
+impl std::str::FromStr for OverflowPolicy {
+    type Err = String;
+
+    fn from_str(policy: &str) -> Result<Self, String> {
+        match policy {
+            "drop-oldest" => Ok(OverflowPolicy::DropOldestChat),
+            "disconnect" => Ok(OverflowPolicy::Disconnect),
+            "block" => Ok(OverflowPolicy::BlockSender),
+            _ => Err(format!(
+                "unknown slow consumer policy {policy}, use drop-oldest, disconnect or block"
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+/// How much is waiting in the outbound queues, and how often the overflow policy had to step in.
+pub struct QueueStats {
+    pub queued_messages: usize,
+    pub longest_queue: usize,
+    pub dropped_messages: u64,
+    pub disconnected_users: u64,
+}
+
+#[derive(Debug)]
+/// A message waiting for its recipient.
+struct Queued {
+    message: BinaryMessage,
+    // Chat can be dropped when the queue is full, game events can't
+    chat: bool,
+}
+
+#[derive(Debug)]
+/// Hands messages to the users they're addressed to, by user ID.
+///
+/// Every logged in user has a bounded outbound queue, so a message goes straight to its recipient
+/// however many users are online. The reactor moves the queued messages to the recipients' connections
+/// once they have sent what they had before.
+pub struct Router {
+    queues: HashMap<u64, VecDeque<Queued>>,
+    limit: usize,
+    policy: OverflowPolicy,
+    // Users who couldn't keep up, their connections are closed by the reactor
+    slow: Vec<u64>,
+    // Messages for full queues under the block policy, with their recipients, by sender.
+    // The reactor doesn't read from a sender until all of its messages are queued
+    held: HashMap<u64, VecDeque<(u64, Queued)>>,
+    stats: QueueStats,
+}
+
+impl Default for Router {
+    fn default() -> Self {
+        Self {
+            queues: HashMap::new(),
+            limit: DEFAULT_QUEUE_LIMIT,
+            policy: OverflowPolicy::default(),
+            slow: Vec::new(),
+            held: HashMap::new(),
+            stats: QueueStats::default(),
+        }
+    }
+}
+
+impl Router {
+    /// Sets how many messages may wait for a user.
+    pub fn set_limit(&mut self, limit: usize) {
+        self.limit = limit.max(1);
+    }
+
+    /// Sets what happens when a user's queue is full.
+    pub fn set_policy(&mut self, policy: OverflowPolicy) {
+        self.policy = policy;
+    }
+
+    /// Registers the outbound queue of a user who just logged in.
+    pub fn register(&mut self, user_id: u64) {
+        self.queues.insert(user_id, VecDeque::new());
+    }
+
+    /// Removes the outbound queue of a user who left, messages still waiting for the user are dropped,
+    /// as are the messages the user sent that are still held back.
+    pub fn unregister(&mut self, user_id: u64) {
+        if let Some(queue) = self.queues.remove(&user_id) {
+            if !queue.is_empty() {
+                println!("dropped {} messages for user {user_id}", queue.len());
+            }
+        }
+
+        self.held.remove(&user_id);
+        for held in self.held.values_mut() {
+            held.retain(|(recipient, _)| *recipient != user_id);
+        }
+        self.held.retain(|_, held| !held.is_empty());
+    }
+
+    /// Queues a message for a user, it fails if the user isn't online.
+    ///
+    /// # Arguments
+    ///
+    /// * `sender: Option<u64>` - The ID of the user who sent the message, `None` for events of the server itself.
+    /// * `recipient: u64` - The ID of the user the message is for.
+    /// * `message: BinaryMessage` - The message.
+    /// * `chat: bool` - Whether the message is chat, which may be dropped if the recipient doesn't keep up.
+    pub fn send(
+        &mut self,
+        sender: Option<u64>,
+        recipient: u64,
+        message: BinaryMessage,
+        chat: bool,
+    ) -> Result<(), ServerCommandError> {
+        let queue = self.queues.get_mut(&recipient).ok_or_else(|| {
+            ServerCommandError::ErrorMessage(
+                ErrorCode::UnknownUser,
+                format!("user {recipient} doesn't exist or isn't online"),
+            )
+        })?;
+        let queued = Queued { message, chat };
+
+        // A sender that is held up keeps its messages in the order they were sent
+        if let Some(held) = sender.and_then(|sender| self.held.get_mut(&sender)) {
+            held.push_back((recipient, queued));
+            return Ok(());
+        }
+
+        if queue.len() >= self.limit {
+            let oldest_chat = queue.iter().position(|queued| queued.chat);
+            match (self.policy, oldest_chat, sender) {
+                (OverflowPolicy::DropOldestChat, Some(index), _) => {
+                    queue.remove(index);
+                    self.stats.dropped_messages += 1;
+                }
+                (OverflowPolicy::BlockSender, _, Some(sender)) => {
+                    self.held
+                        .entry(sender)
+                        .or_default()
+                        .push_back((recipient, queued));
+                    return Ok(());
+                }
+                _ => {
+                    self.disconnect(recipient);
+                    return Err(ServerCommandError::ErrorMessage(
+                        ErrorCode::UnknownUser,
+                        format!("user {recipient} couldn't keep up and was disconnected"),
+                    ));
+                }
+            }
+        }
+
+        queue.push_back(queued);
+        Ok(())
+    }
+
+    /// Gets the users with messages waiting.
+    pub fn waiting_users(&self) -> Vec<u64> {
+        self.queues
+            .iter()
+            .filter(|(_, queue)| !queue.is_empty())
+            .map(|(user_id, _)| *user_id)
+            .collect()
+    }
+
+    /// Takes the messages waiting for a user, in the order they were sent.
+    pub fn take(&mut self, user_id: u64) -> Vec<BinaryMessage> {
+        self.queues
+            .get_mut(&user_id)
+            .map(|queue| queue.drain(..).map(|queued| queued.message).collect())
+            .unwrap_or_default()
+    }
+
+    /// Queues the held back messages whose recipients have room again, each sender's in the order it sent them.
+    pub fn release_held(&mut self) {
+        for held in self.held.values_mut() {
+            while let Some((recipient, _)) = held.front() {
+                let Some(queue) = self.queues.get_mut(recipient) else {
+                    held.pop_front();
+                    continue;
+                };
+                if queue.len() >= self.limit {
+                    break;
+                }
+                if let Some((_, queued)) = held.pop_front() {
+                    queue.push_back(queued);
+                }
+            }
+        }
+        self.held.retain(|_, held| !held.is_empty());
+    }
+
+    /// Checks whether a message can be queued for a user without going over the limit.
+    /// A user who isn't online has room, sending to it fails right away.
+    pub fn has_room(&self, user_id: u64) -> bool {
+        self.queues
+            .get(&user_id)
+            .is_none_or(|queue| queue.len() < self.limit)
+    }
+
+    /// Checks whether messages of a user are held back under the block policy, the user isn't read from until
+    /// they're queued.
+    pub fn is_blocked(&self, sender: u64) -> bool {
+        self.held.contains_key(&sender)
+    }
+
+    /// Takes the users who couldn't keep up since the last call, their connections have to be closed.
+    pub fn take_slow(&mut self) -> Vec<u64> {
+        std::mem::take(&mut self.slow)
+    }
+
+    /// Gets the depth of the queues, along with how often the overflow policy had to step in.
+    pub fn stats(&self) -> QueueStats {
+        QueueStats {
+            queued_messages: self.queues.values().map(VecDeque::len).sum(),
+            longest_queue: self.queues.values().map(VecDeque::len).max().unwrap_or(0),
+            ..self.stats.clone()
+        }
+    }
+
+    /// Lets go of a user who doesn't keep up, nothing more is queued for it.
+    fn disconnect(&mut self, user_id: u64) {
+        println!("user {user_id} doesn't read its messages, disconnecting it");
+        self.unregister(user_id);
+        self.slow.push(user_id);
+        self.stats.disconnected_users += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SENDER: u64 = 1;
+    const OTHER_SENDER: u64 = 2;
+    const RECIPIENT: u64 = 3;
+
+    fn router(policy: OverflowPolicy, limit: usize) -> Router {
+        let mut router = Router::default();
+        router.set_policy(policy);
+        router.set_limit(limit);
+        for user_id in [SENDER, OTHER_SENDER, RECIPIENT] {
+            router.register(user_id);
+        }
+        router
+    }
+
+    fn chat(text: &str) -> BinaryMessage {
+        BinaryMessage::new_message(text.to_string())
+    }
+
+    #[test]
+    fn drop_oldest_chat_makes_room_by_dropping_chat() {
+        let mut router = router(OverflowPolicy::DropOldestChat, 2);
+
+        router.send(None, RECIPIENT, chat("event"), false).unwrap();
+        router
+            .send(Some(SENDER), RECIPIENT, chat("first"), true)
+            .unwrap();
+        router
+            .send(Some(SENDER), RECIPIENT, chat("second"), true)
+            .unwrap();
+
+        assert_eq!(router.take(RECIPIENT), vec![chat("event"), chat("second")]);
+        assert_eq!(router.stats().dropped_messages, 1);
+        assert!(router.take_slow().is_empty());
+    }
+
+    #[test]
+    fn drop_oldest_chat_disconnects_a_user_with_only_game_events_waiting() {
+        let mut router = router(OverflowPolicy::DropOldestChat, 1);
+
+        router.send(None, RECIPIENT, chat("event"), false).unwrap();
+        assert!(router
+            .send(Some(SENDER), RECIPIENT, chat("chat"), true)
+            .is_err());
+
+        assert_eq!(router.take_slow(), vec![RECIPIENT]);
+        assert_eq!(router.stats().disconnected_users, 1);
+        assert!(router.send(None, RECIPIENT, chat("late"), false).is_err());
+    }
+
+    #[test]
+    fn disconnect_lets_go_of_a_full_queue() {
+        let mut router = router(OverflowPolicy::Disconnect, 1);
+
+        router
+            .send(Some(SENDER), RECIPIENT, chat("first"), true)
+            .unwrap();
+        assert!(router
+            .send(Some(SENDER), RECIPIENT, chat("second"), true)
+            .is_err());
+
+        assert_eq!(router.take_slow(), vec![RECIPIENT]);
+        assert!(router.take(RECIPIENT).is_empty());
+        assert!(!router.is_blocked(SENDER));
+    }
+
+    #[test]
+    fn block_sender_holds_back_only_the_sender_that_hit_the_limit() {
+        let mut router = router(OverflowPolicy::BlockSender, 1);
+
+        router
+            .send(Some(OTHER_SENDER), RECIPIENT, chat("first"), true)
+            .unwrap();
+        router
+            .send(Some(SENDER), RECIPIENT, chat("second"), true)
+            .unwrap();
+        // Whatever the sender sends next waits behind its held message, even to a user with room
+        router
+            .send(Some(SENDER), OTHER_SENDER, chat("third"), true)
+            .unwrap();
+
+        assert!(router.is_blocked(SENDER));
+        assert!(!router.is_blocked(OTHER_SENDER));
+        assert_eq!(router.stats().longest_queue, 1);
+        assert!(router.take_slow().is_empty());
+
+        // Nothing is queued until the recipient has room
+        router.release_held();
+        assert!(router.is_blocked(SENDER));
+
+        assert_eq!(router.take(RECIPIENT), vec![chat("first")]);
+        router.release_held();
+        assert!(!router.is_blocked(SENDER));
+        assert_eq!(router.take(RECIPIENT), vec![chat("second")]);
+        assert_eq!(router.take(OTHER_SENDER), vec![chat("third")]);
+    }
+
+    #[test]
+    fn block_sender_disconnects_when_the_server_has_no_sender_to_hold_up() {
+        let mut router = router(OverflowPolicy::BlockSender, 1);
+
+        router
+            .send(Some(SENDER), RECIPIENT, chat("first"), true)
+            .unwrap();
+        assert!(router.send(None, RECIPIENT, chat("event"), false).is_err());
+
+        assert_eq!(router.take_slow(), vec![RECIPIENT]);
+    }
+
+    #[test]
+    fn held_messages_are_dropped_with_their_recipient() {
+        let mut router = router(OverflowPolicy::BlockSender, 1);
+
+        router
+            .send(Some(OTHER_SENDER), RECIPIENT, chat("first"), true)
+            .unwrap();
+        router
+            .send(Some(SENDER), RECIPIENT, chat("second"), true)
+            .unwrap();
+        router.unregister(RECIPIENT);
+
+        assert!(!router.is_blocked(SENDER));
+    }
+
+    #[test]
+    fn sending_to_an_unknown_user_fails() {
+        let mut router = router(OverflowPolicy::default(), 1);
+
+        assert!(matches!(
+            router.send(Some(SENDER), 42, chat("hello"), true),
+            Err(ServerCommandError::ErrorMessage(ErrorCode::UnknownUser, _))
+        ));
+    }
+}
@@ -1,102 +1,216 @@
 use std::{
+    collections::HashMap,
     io::{Read, Write},
-    sync::mpsc::{Receiver, Sender},
+    sync::Arc,
+    time::{Duration, Instant},
 };
 
-use binary_message::BinaryMessage;
+use admission::ConnectionLimits;
+use client_server_tcp::{
+    binary_message::LARGE_MAX_FRAME_SIZE, BinaryMessage, ErrorCode, ServerMessageResponse,
+};
 use guess_game::{Game, GameState};
+use heartbeat::Heartbeats;
+use listener::UnixPermissions;
+use peer_auth::{LocalUser, PeerAuth};
+use reactor::DEFAULT_WRITE_TIMEOUT_MS;
+use router::{QueueStats, Router};
 use server_commands::ServerCommandError;
+use shutdown::{Shutdown, DEFAULT_SHUTDOWN_TIMEOUT_MS};
 
-mod binary_message;
+mod accounts;
+mod admission;
 mod guess_game;
+mod heartbeat;
+mod listener;
+mod peer_auth;
+mod protocol_sniffer;
+mod reactor;
+mod router;
 mod server_commands;
 mod server_process;
+mod shutdown;
+mod spectator;
+mod text_gateway;
+mod tls;
+mod websocket;
 
-#[derive(Debug)]
-/// This is basically a custom broadcast due to the limitation of not using tokio's types.
-struct Broadcast {
-    senders: Vec<(Sender<(u64, BinaryMessage)>, u64)>,
+#[derive(Debug, PartialEq, Clone)]
+/// Type of socket a listener accepts connections on.
+pub enum ServerType {
+    TCP,
+    UNIX,
 }
 
-impl Broadcast {
-    /// Creates a new [`Broadcast`].
-    fn new() -> Self {
-        Broadcast {
-            senders: Vec::default(),
+impl std::str::FromStr for ServerType {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, String> {
+        match name {
+            "TCP" => Ok(ServerType::TCP),
+            "UNIX" => Ok(ServerType::UNIX),
+            _ => Err(format!(
+                "invalid server type {name} given (choose UNIX or TCP)"
+            )),
         }
     }
+}
 
-    /// Equivalent of subscribing to a broadcast channel.
-    fn subscribe(&mut self) -> Receiver<(u64, BinaryMessage)> {
-        let (tx, rx) = std::sync::mpsc::channel();
-
-        // Generate a new channelID for each user that subscribes (gets added)
-        let id = self
-            .senders
-            .iter()
-            .map(|(_, item_id)| *item_id)
-            .max()
-            .unwrap_or(0)
-            + 1;
-
-        self.senders.push((tx, id));
-        rx
-    }
+#[derive(Debug, PartialEq, Clone)]
+/// A socket the server listens on, every listener serves the same players and games.
+pub struct ListenerConfig {
+    /// Type of the socket.
+    pub server_type: ServerType,
+    /// The port or addresses of a TCP listener, or the name of a UNIX socket.
+    pub endpoint: String,
+}
 
-    /// Equivalent of unsubscribing from a broadcast channel.
-    fn unsubscribe(&mut self, channels_to_remove: Vec<u64>) {
-        channels_to_remove
-            .iter()
-            .for_each(|remove_id| self.senders.retain(|(_, id)| id != remove_id));
+impl std::str::FromStr for ListenerConfig {
+    type Err = String;
+
+    /// Parses a listener given as `TYPE:ENDPOINT`, e.g. `TCP:0.0.0.0:8080` or `UNIX:luxo_server`.
+    fn from_str(listener: &str) -> Result<Self, String> {
+        let (server_type, endpoint) = listener
+            .split_once(':')
+            .ok_or(format!("invalid listener {listener}, use TYPE:ENDPOINT"))?;
+        Ok(Self {
+            server_type: server_type.parse()?,
+            endpoint: endpoint.to_string(),
+        })
     }
+}
 
-    /// Broadcast the message to all receivers.
-    fn broadcast(&mut self, message: (u64, BinaryMessage)) {
-        let mut channels_to_remove = vec![];
-
-        for (sender, id) in &self.senders {
-            match sender.send(message.clone()) {
-                Ok(_) => {
-                    println!("Sent a message {sender:?} {message:#?}");
-                }
-                Err(e) => {
-                    // If we have dead thread, remove it's old channel.
-                    eprintln!(
-                        "Failed to send message: {e}, {:#?}, removing channel",
-                        sender
-                    );
-                    channels_to_remove.push(*id);
-                }
-            }
-        }
-        self.unsubscribe(channels_to_remove);
-    }
+#[derive(Debug, Default, Clone)]
+/// Counters describing the health of the server's connections, shown to spectators.
+pub struct ServerStats {
+    checksum_failures: u64,
+    queues: QueueStats,
+    refused_connections: u64,
 }
 
-#[derive(Debug, PartialEq, Clone)]
-/// Type of server to run.
-pub enum ServerType {
-    TCP,
-    UNIX,
+impl std::fmt::Display for ServerStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "checksum failures: {}, queued messages: {}, longest queue: {}, dropped chat messages: {}, slow users disconnected: {}, refused connections: {}",
+            self.checksum_failures,
+            self.queues.queued_messages,
+            self.queues.longest_queue,
+            self.queues.dropped_messages,
+            self.queues.disconnected_users,
+            self.refused_connections
+        )
+    }
 }
 
 #[derive(Debug)]
 /// Data for the server to keep track of, mainly list of connected users, ongoing games and password.
 pub struct ServerData {
     connected_users: Vec<u64>,
+    // The identities of the users logged in from their peer credentials, for deciding what they may do
+    local_users: HashMap<u64, LocalUser>,
     password: String,
-    server_type: ServerType,
     ongoing_games: Vec<Game>,
+    max_frame_size: usize,
+    text_port: Option<String>,
+    tls_config: Option<Arc<rustls::ServerConfig>>,
+    unix_permissions: UnixPermissions,
+    peer_auth: PeerAuth,
+    router: Router,
+    connection_limits: ConnectionLimits,
+    heartbeats: Heartbeats,
+    stats: ServerStats,
+    shutdown: Option<Shutdown>,
+    shutdown_timeout: Duration,
+    write_timeout: Duration,
 }
 
 impl ServerData {
     /// Creates a new [`ServerData`].
-    pub fn new(password: String, server_type: ServerType) -> Self {
+    pub fn new(password: String) -> Self {
         Self {
             connected_users: vec![],
+            local_users: HashMap::new(),
             password,
-            server_type,
             ongoing_games: vec![],
+            max_frame_size: LARGE_MAX_FRAME_SIZE,
+            text_port: None,
+            tls_config: None,
+            unix_permissions: UnixPermissions::default(),
+            peer_auth: PeerAuth::default(),
+            router: Router::default(),
+            connection_limits: ConnectionLimits::default(),
+            heartbeats: Heartbeats::default(),
+            stats: ServerStats::default(),
+            shutdown: None,
+            shutdown_timeout: Duration::from_millis(DEFAULT_SHUTDOWN_TIMEOUT_MS),
+            write_timeout: Duration::from_millis(DEFAULT_WRITE_TIMEOUT_MS),
+        }
+    }
+
+    /// Sets the largest frame accepted from clients that negotiated large frames.
+    pub fn set_max_frame_size(&mut self, max_frame_size: usize) {
+        self.max_frame_size = max_frame_size;
+    }
+
+    /// Sets the TCP port of the line-oriented text gateway, which is off unless a port is given.
+    pub fn set_text_port(&mut self, text_port: String) {
+        self.text_port = Some(text_port);
+    }
+
+    /// Sets the certificate and key of the server, so every connection on the TCP listener is encrypted with TLS.
+    pub fn set_tls_config(&mut self, tls_config: Arc<rustls::ServerConfig>) {
+        self.tls_config = Some(tls_config);
+    }
+
+    /// Sets how long the connections get to finish once the server starts shutting down.
+    pub fn set_shutdown_timeout(&mut self, shutdown_timeout: Duration) {
+        self.shutdown_timeout = shutdown_timeout;
+    }
+
+    /// Sets how long a connection may go without taking any of the bytes waiting for it before it's closed.
+    pub fn set_write_timeout(&mut self, write_timeout: Duration) {
+        self.write_timeout = write_timeout;
+    }
+
+    /// Starts shutting the server down, no connections are accepted from now on and every client is told why.
+    /// Only the first reason is kept if this is called more than once.
+    pub fn begin_shutdown(&mut self, reason: String) {
+        if self.shutdown.is_none() {
+            self.shutdown = Some(Shutdown {
+                reason,
+                deadline: Instant::now() + self.shutdown_timeout,
+            });
+        }
+    }
+
+    /// Checks whether the server is shutting down.
+    pub fn is_shutting_down(&self) -> bool {
+        self.shutdown.is_some()
+    }
+
+    /// Gets the time by which every connection has to be closed, if the server is shutting down.
+    pub fn shutdown_deadline(&self) -> Option<Instant> {
+        self.shutdown.as_ref().map(|shutdown| shutdown.deadline)
+    }
+
+    /// Gets the events a user is sent before its connection is closed, if the server is shutting down.
+    /// A player in a game is told it's canceled first, so neither side waits for the other.
+    pub fn shutdown_events(&self, local_id: u64) -> Option<Vec<ServerMessageResponse>> {
+        let shutdown = self.shutdown.as_ref()?;
+
+        let mut events = vec![];
+        if self.get_game_id(local_id).is_some() {
+            events.push(ServerMessageResponse::GameCanceled);
+        }
+        events.push(ServerMessageResponse::Shutdown(shutdown.reason.clone()));
+        Some(events)
+    }
+
+    /// Ends every ongoing game, used once all connections are closed.
+    pub fn end_all_games(&mut self) {
+        for game in self.ongoing_games.drain(..) {
+            println!("ended game id {} on shutdown", game.get_game_id());
         }
     }
 
@@ -123,17 +237,19 @@ impl ServerData {
         secret: String,
     ) -> Result<u128, ServerCommandError> {
         // Check if user matched exists
-        if !self.connected_users.iter().any(|id| id_guest == *id) {
-            return Err(ServerCommandError::ErrorMessage(format!(
-                "ERROR user {id_guest} doesn't exist, cannot begin game"
-            )));
+        if !self.connected_users.contains(&id_guest) {
+            return Err(ServerCommandError::ErrorMessage(
+                ErrorCode::UnknownUser,
+                format!("user {id_guest} doesn't exist, cannot begin game"),
+            ));
         }
 
         // Check if user is trying to match with himself
         if id_guest == id_host {
-            return Err(ServerCommandError::ErrorMessage(format!(
-                "ERROR user {id_guest} cannot match with himself!"
-            )));
+            return Err(ServerCommandError::ErrorMessage(
+                ErrorCode::SelfMatch,
+                format!("user {id_guest} cannot match with himself!"),
+            ));
         }
 
         // Check if user is already in a game
@@ -173,6 +289,7 @@ impl ServerData {
             return Ok(game.get_opponent_id());
         }
         Err(ServerCommandError::ErrorMessage(
+            ErrorCode::NotInGame,
             "game has no oppponent".to_string(),
         ))
     }
@@ -187,6 +304,7 @@ impl ServerData {
             return Ok(game.get_host_id());
         }
         Err(ServerCommandError::ErrorMessage(
+            ErrorCode::NotInGame,
             "game has no host".to_string(),
         ))
     }
@@ -195,11 +313,71 @@ impl ServerData {
         self.ongoing_games.clone()
     }
 
+    /// Gets a snapshot of the server's stats.
+    pub fn get_stats(&self) -> ServerStats {
+        ServerStats {
+            queues: self.router.stats(),
+            ..self.stats.clone()
+        }
+    }
+
+    /// Queues a game event for another user, an error is returned if the user isn't online.
+    /// The sender is `None` for events of the server itself.
+    pub fn send_message(
+        &mut self,
+        sender: Option<u64>,
+        recipient: u64,
+        message: BinaryMessage,
+    ) -> Result<(), ServerCommandError> {
+        self.router.send(sender, recipient, message, false)
+    }
+
+    /// Queues a chat message for another user, it may be dropped if the user doesn't keep up.
+    pub fn send_chat(
+        &mut self,
+        sender: u64,
+        recipient: u64,
+        message: BinaryMessage,
+    ) -> Result<(), ServerCommandError> {
+        self.router.send(Some(sender), recipient, message, true)
+    }
+
+    /// Cancels the game of a user who is leaving, the other player is told the game is over.
+    pub fn cancel_game(&mut self, local_id: u64) {
+        let Some(game_id) = self.get_game_id(local_id) else {
+            return;
+        };
+        let other_player = self
+            .get_opponent_id(local_id)
+            .or_else(|_| self.get_host_id(local_id));
+
+        if let Err(err) = self.terminate_game(game_id) {
+            eprintln!("error canceling game {game_id}: {err}");
+        }
+        if let Ok(other_player) = other_player {
+            let event = BinaryMessage::from(ServerMessageResponse::GameCanceled);
+            if let Err(err) = self.send_message(None, other_player, event) {
+                eprintln!("error telling user {other_player} the game is canceled: {err}");
+            }
+        }
+    }
+
+    /// Counts a frame that was rejected because its checksum didn't match.
+    pub fn record_checksum_failure(&mut self) {
+        self.stats.checksum_failures += 1;
+    }
+
+    /// Counts a connection that was refused because the server had too many.
+    pub fn record_refused_connection(&mut self) {
+        self.stats.refused_connections += 1;
+    }
+
     pub fn terminate_game(&mut self, id: u128) -> Result<(), ServerCommandError> {
         if !self.ongoing_games.iter().any(|x| id == x.get_game_id()) {
-            return Err(ServerCommandError::ErrorMessage(format!(
-                "user {id} doesn't exist"
-            )));
+            return Err(ServerCommandError::ErrorMessage(
+                ErrorCode::NotInGame,
+                format!("game {id} doesn't exist"),
+            ));
         }
 
         self.ongoing_games.retain(|x| x.get_game_id() != id);
@@ -221,7 +399,7 @@ impl ServerData {
                     return Ok(game.get_game_state());
                 }
                 // Otherwise lower the attempts and update last guess for spectators
-                game.set_attempts(game.get_attempts() - 1);
+                game.set_attempts(game.get_attempts().saturating_sub(1));
                 game.set_last_guess(guess);
 
                 // If we are out of attempts, we mark the game as lost for the guesser
@@ -235,6 +413,7 @@ impl ServerData {
             }
         }
         Err(ServerCommandError::ErrorMessage(
+            ErrorCode::NotInGame,
             "Game does not exist, cannot update guess".to_string(),
         ))
     }
@@ -249,6 +428,7 @@ impl ServerData {
             }
         }
         Err(ServerCommandError::ErrorMessage(
+            ErrorCode::NotInGame,
             "game does not exist, cannot update hint".to_string(),
         ))
     }
@@ -260,16 +440,16 @@ impl ServerData {
 
     /// Verifies whether a user with an ID X exists, returning true if yes.
     pub fn user_exists(&self, id: u64) -> bool {
-        self.connected_users
+        self.connected_users
             .iter().copied()
             .any(|x| x == id)
     }
 
     /// Adds a user, generating a new user ID that is always higher than the last highest connected user, returning the ID.
-    /// Also pushes the user to the added users
+    /// Also pushes the user to the added users and registers its route, so other users can send it messages.
     pub fn add_user(&mut self) -> u64 {
-        let id = self
-            .connected_users
+        let id = self
+            .connected_users
             .iter().copied()
             .max()
             .unwrap_or(0)
@@ -277,21 +457,36 @@ impl ServerData {
 
         // Add the user
         self.connected_users.push(id);
+        self.router.register(id);
+        self.heartbeats.register(id);
+        id
+    }
+
+    /// Adds a trusted local user like [`ServerData::add_user`], keeping its identity for as long as it's logged in.
+    pub fn add_local_user(&mut self, user: LocalUser) -> u64 {
+        let id = self.add_user();
+        self.local_users.insert(id, user);
         id
     }
 
     /// Drops the user from registered list. If the user already was removed, remove an error saying he was removed.
     pub fn drop_user(&mut self, id: u64) -> Result<(), ServerCommandError> {
         if !self.user_exists(id) {
-            return Err(ServerCommandError::ErrorMessage(format!(
-                "user {id} doesn't exist"
-            )));
+            return Err(ServerCommandError::ErrorMessage(
+                ErrorCode::UnknownUser,
+                format!("user {id} doesn't exist"),
+            ));
         }
         // Remove the user
         self.connected_users.retain(|list_id| id != *list_id);
+        self.router.unregister(id);
+        self.heartbeats.unregister(id);
 
         // We also log the change in the server's terminal
-        println!("removing user id: {id}");
+        match self.local_users.remove(&id) {
+            Some(user) => println!("removing user id: {id}, local user {user}"),
+            None => println!("removing user id: {id}"),
+        }
 
         // We can't return a message here as it would be sent to a non-existing user, so we return ().
         Ok(())
@@ -303,36 +498,137 @@ pub trait ReadWrite: Read + Write {}
 
 impl<T: Read + Write> ReadWrite for T {}
 
-/// Default sleep time in milliseconds for the server threads.
-const SLEEP_DELAY_MS: u64 = 200;
-
 /// Runs the server, requiring arguments of `TYPE` `PORT` `PASSWORD`.
 /// Type can be `TCP` or `UNIX`
 /// Port depends on the type (either `8080` for TCP, or `/tmp/luxo_server` named pipe recommended)
+/// A UNIX socket is given by a name in `/tmp`, a path such as `/run/luxo/server.sock`, or `@name` for an abstract socket.
+/// A TCP port can also be a full address or a comma separated list of them, e.g. `0.0.0.0:8080,[::1]:8080`,
+/// a bare port listens on `127.0.0.1` and `[::]:8080` listens on every IPv4 and IPv6 address.
 /// Password must be a [`String`].
+/// Optional settings can follow as `--option value` pairs:
+/// * `--listen TYPE:ENDPOINT` - another socket to listen on, e.g. `UNIX:luxo_server` next to a TCP port, can be repeated.
+/// * `--max-frame-size BYTES` - largest frame accepted from clients that support large frames.
+/// * `--text-port PORT` - TCP port or address of the text gateway, for clients without binary framing such as `nc`.
+/// * `--unix-mode MODE` and `--unix-group GROUP` - octal mode and owning group of the UNIX socket files.
+/// * `--unix-auth MODE` - how UNIX clients log in, `password`, `peer` or `peer-or-password`.
+/// * `--trust-user USER` and `--trust-group GROUP` - local users logging in over UNIX sockets without the password, can be repeated.
+/// * `--tls-cert PATH` and `--tls-key PATH` - PEM certificate chain and private key, turning on TLS for the TCP listeners.
+/// * `--shutdown-timeout SECONDS` - how long connections get to close after SIGINT or SIGTERM, 5 seconds by default.
+/// * `--queue-limit MESSAGES` - messages that may wait for a user who doesn't read them, 256 by default.
+/// * `--slow-consumer POLICY` - what happens when they're more, `drop-oldest` chat, `disconnect` or `block` the sender.
+/// * `--write-timeout SECONDS` - how long a client may take nothing that's sent to it before it's disconnected, 30 seconds by default.
+/// * `--max-connections COUNT` - connections served at once, 1024 by default.
+/// * `--max-per-ip COUNT` - connections a single TCP client address may hold, 64 by default.
+/// * `--accept-rate COUNT` - connections accepted per second, 100 by default. Any of the three limits is off when set to 0.
+/// * `--heartbeat-interval SECONDS` - how long a user may be quiet before it's sent a `PING`, 30 seconds by default, 0 turns heartbeats off.
+/// * `--missed-heartbeats COUNT` - heartbeats a user may miss before it's disconnected and its game canceled, 3 by default, at least 2.
 fn main() -> Result<(), String> {
     let args: Vec<String> = std::env::args().collect();
     // We check the length of arguments to ensure they are ok
-    if args.len() != 4 {
+    if args.len() < 4 {
         return Err(
-            "incorrect number of arguments provided. use `server TYPE PORT PASSWORD` where TYPE = TCP|UNIX"
+            "incorrect number of arguments provided. use `server TYPE PORT PASSWORD [--option value]...` where TYPE = TCP|UNIX"
                 .to_string(),
         );
     }
     // If we enter something else, we return an error to the user
-    let server_type = match args[1].as_str() {
-        "TCP" => ServerType::TCP,
-        "UNIX" => ServerType::UNIX,
-        _ => return Err("invalid server type given (choose UNIX or TCP)".to_string()),
-    };
-
-    let port = args[2].as_str();
+    let mut listeners = vec![ListenerConfig {
+        server_type: args[1].parse()?,
+        endpoint: args[2].clone(),
+    }];
     let password = args[3].clone();
 
-    let server_data = ServerData::new(password, server_type);
+    let mut server_data = ServerData::new(password);
+
+    // Optional settings come in `--option value` pairs after the required arguments
+    let mut tls_cert = None;
+    let mut tls_key = None;
+    let mut options = args[4..].iter();
+    while let Some(option) = options.next() {
+        let value = options
+            .next()
+            .ok_or(format!("missing value for option {option}"))?;
+
+        match option.as_str() {
+            "--max-frame-size" => server_data.set_max_frame_size(
+                value
+                    .parse()
+                    .map_err(|_| format!("invalid maximum frame size {value}"))?,
+            ),
+            "--text-port" => server_data.set_text_port(value.clone()),
+            "--listen" => listeners.push(value.parse()?),
+            "--unix-mode" => server_data.unix_permissions.set_mode(value)?,
+            "--unix-group" => server_data.unix_permissions.set_group(value)?,
+            "--unix-auth" => server_data.peer_auth.set_mode(value.parse()?),
+            "--trust-user" => server_data.peer_auth.trust_user(value)?,
+            "--trust-group" => server_data.peer_auth.trust_group(value)?,
+            "--tls-cert" => tls_cert = Some(value),
+            "--tls-key" => tls_key = Some(value),
+            "--shutdown-timeout" => server_data.set_shutdown_timeout(Duration::from_secs(
+                value
+                    .parse()
+                    .map_err(|_| format!("invalid shutdown timeout {value}"))?,
+            )),
+            "--queue-limit" => server_data.router.set_limit(
+                value
+                    .parse()
+                    .map_err(|_| format!("invalid queue limit {value}"))?,
+            ),
+            "--slow-consumer" => server_data.router.set_policy(value.parse()?),
+            "--write-timeout" => server_data.set_write_timeout(Duration::from_secs(
+                value
+                    .parse()
+                    .map_err(|_| format!("invalid write timeout {value}"))?,
+            )),
+            "--max-connections" => server_data.connection_limits.set_max_connections(
+                value
+                    .parse()
+                    .map_err(|_| format!("invalid maximum connections {value}"))?,
+            ),
+            "--max-per-ip" => server_data.connection_limits.set_max_per_ip(
+                value
+                    .parse()
+                    .map_err(|_| format!("invalid connections per IP {value}"))?,
+            ),
+            "--accept-rate" => server_data.connection_limits.set_accept_rate(
+                value
+                    .parse()
+                    .map_err(|_| format!("invalid accept rate {value}"))?,
+            ),
+            "--heartbeat-interval" => server_data.heartbeats.set_interval(Duration::from_secs(
+                value
+                    .parse()
+                    .map_err(|_| format!("invalid heartbeat interval {value}"))?,
+            )),
+            "--missed-heartbeats" => server_data.heartbeats.set_missed_limit(
+                value
+                    .parse()
+                    .map_err(|_| format!("invalid number of missed heartbeats {value}"))?,
+            ),
+            _ => return Err(format!("unknown option {option}")),
+        }
+    }
+
+    server_data.peer_auth.validate()?;
+
+    match (tls_cert, tls_key) {
+        (Some(cert), Some(key)) => {
+            // UNIX sockets never leave the machine, so only TCP listeners are encrypted
+            if !listeners
+                .iter()
+                .any(|listener| listener.server_type == ServerType::TCP)
+            {
+                return Err("TLS is only supported by TCP listeners".to_string());
+            }
+            server_data.set_tls_config(tls::load_tls_config(cert, key)?);
+        }
+        (None, None) => (),
+        _ => return Err("TLS needs both --tls-cert and --tls-key".to_string()),
+    }
 
     // Run the server
-    server_process::run_server(server_data, port).unwrap();
+    server_process::run_server(server_data, listeners)
+        .map_err(|err| format!("error running the server: {err}"))?;
 
     Ok(())
 }
@@ -1,38 +1,18 @@
-use std::{
-    string::FromUtf8Error,
-    sync::{mpsc::Sender, Arc, RwLock},
-};
-
-use crate::{
-    binary_message::{BinaryMessage, MessageType},
-    guess_game::GameState,
-    ServerData,
-};
-
-#[derive(Debug, PartialEq, Clone)]
-/// All the commands that the server can receive and interpret
-pub enum ServerCommandList {
-    Unknown,
-    HeartBeat,
-    Drop,
-    DirectMessage(Vec<u8>),
-    Hint(Vec<u8>),
-    Guess(Vec<u8>),
-    StartGame(Vec<u8>),
-    Message(Vec<u8>),
-    CancelGame,
-    RequestOpponents,
-}
+use std::sync::{Arc, RwLock};
+
+use client_server_tcp::{BinaryMessage, ErrorCode, ServerCommandList, ServerMessageResponse};
+
+use crate::{guess_game::GameState, ServerData};
 
 #[derive(Debug)]
 /// Type of error when a command executes.
 ///
 /// ``Terminate Thread`` is a critical error that should stop the thread.
 ///
-/// ``ErrorMessage`` is a non-critical error that can be handled.
+/// ``ErrorMessage`` is a non-critical error that can be handled, it's sent to the client as an error frame with its code.
 pub enum ServerCommandError {
     TerminateThread(String),
-    ErrorMessage(String),
+    ErrorMessage(ErrorCode, String),
     TerminateUser(String),
 }
 
@@ -41,7 +21,7 @@ impl std::fmt::Display for ServerCommandError {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         match self {
             ServerCommandError::TerminateThread(err) => write!(f, "Terminating thread: {}", err),
-            ServerCommandError::ErrorMessage(err) => write!(f, "ERROR: {}", err),
+            ServerCommandError::ErrorMessage(code, err) => write!(f, "ERROR {}: {}", code, err),
             ServerCommandError::TerminateUser(err) => write!(f, "Terminating user: {}", err),
         }
     }
@@ -50,7 +30,16 @@ impl std::fmt::Display for ServerCommandError {
 // Implement the Error trait for ServerCommandError
 impl std::error::Error for ServerCommandError {}
 
-impl ServerCommandList {
+/// Builds the error for a game that doesn't exist anymore.
+fn game_not_found(game_id: u128) -> ServerCommandError {
+    ServerCommandError::ErrorMessage(
+        ErrorCode::NotInGame,
+        format!("game {game_id} doesn't exist"),
+    )
+}
+
+/// Server-side execution of the commands received from the clients.
+pub trait Execute {
     /// Executes the server command.
     ///
     /// # Arguments
@@ -58,24 +47,39 @@ impl ServerCommandList {
     /// * `self` - A reference to the current instance of ServerCommandList.
     /// * `server` - An Arc wrapped RwLock protected ServerData object that contains the server's state.
     /// * `local_id` - A reference to the local id.
-    /// * `thread_send` - A Sender from the standard mpsc module, used to send messages to other threads.
     ///
     /// # Return
     ///
     /// Returns a [`Result`] with a [`String`] if the command was successful, otherwise a [`ServerCommandError`].
-    pub fn execute(
+    fn execute(
+        &self,
+        server: Arc<RwLock<ServerData>>,
+        local_id: &u64,
+    ) -> Result<BinaryMessage, ServerCommandError>;
+}
+
+impl Execute for ServerCommandList {
+    fn execute(
         &self,
         server: Arc<RwLock<ServerData>>,
         local_id: &u64,
-        thread_send: Sender<(u64, BinaryMessage)>,
     ) -> Result<BinaryMessage, ServerCommandError> {
+        // Any command shows the user is still there, not just a heartbeat
+        server.write().unwrap().heartbeats.seen(*local_id);
+
         match self {
             ServerCommandList::Unknown => {
                 eprintln!("Unknown command received");
-                Ok(BinaryMessage::new_message(
+                Err(ServerCommandError::ErrorMessage(
+                    ErrorCode::UnknownCommand,
                     "Unknown command received".to_string(),
                 ))
             }
+            // The handshake is done once per connection, before the user is validated
+            ServerCommandList::Hello(_) => Err(ServerCommandError::ErrorMessage(
+                ErrorCode::HandshakeCompleted,
+                "handshake was already completed".to_string(),
+            )),
             ServerCommandList::HeartBeat => {
                 println!("ID {local_id}'s heartbeat received");
 
@@ -87,231 +91,143 @@ impl ServerCommandList {
             ServerCommandList::Drop => {
                 let mut server_write_lock = server.write().unwrap();
 
-                if let Some(game_id) = server_write_lock.get_game_id(*local_id) {
-                    let game_clone = server_write_lock.get_game_mut_ref(game_id).unwrap().clone();
+                // The other player is told the game is canceled, the user is dropped either way
+                server_write_lock.cancel_game(*local_id);
 
-                    server_write_lock.terminate_game(game_id)?;
-
-                    let message = BinaryMessage::new_message("MATCH CANCELED".to_string());
-
-                    // If message is valid, send the message to the broadcast channel
-                    thread_send
-                        .send((game_clone.get_opponent_id(), message))
-                        .map_err(|err| {
-                            ServerCommandError::TerminateThread(format!(
-                                "critical error sending a command to proper channel: {}",
-                                err
-                            ))
-                        })?;
-                }
                 println!("DROPPING THE USER");
-                if let Some(game_id) = server_write_lock.get_game_id(*local_id) {
-                    server.write().unwrap().terminate_game(game_id)?;
-
-                    server_write_lock.drop_user(*local_id)?;
-                }
+                server_write_lock.drop_user(*local_id)?;
                 Err(ServerCommandError::TerminateUser(format!(
                     "user {local_id} dropped"
                 )))
             }
 
-            ServerCommandList::DirectMessage(data) => {
-                // Check if the data at least has an ID of the user
-                if data.is_empty() {
-                    return Err(ServerCommandError::ErrorMessage(
-                        "ERROR Direct message must be at least 8 bytes long".to_string(),
-                    ));
-                }
+            ServerCommandList::DirectMessage { recipient, text } => {
+                let message = BinaryMessage::new_message(text.clone());
 
-                // Parse the ID
-                let string_text = String::from_utf8(data.clone()).unwrap();
-                let tokens: Vec<&str> = string_text.split_whitespace().collect();
-
-                let id = match tokens[0].parse::<u64>() {
-                    Ok(value) => value,
-                    Err(err) => {
-                        return Err(ServerCommandError::ErrorMessage(format!(
-                            "ERROR Direct message must start with a valid ID: {err}"
-                        )))
-                    }
-                };
-
-                // Check if message is parseable, if not return error to DM sender
-                let message = BinaryMessage::new_message(tokens[1].to_string());
-
-                // If message is valid, send the message to the broadcast channel
-                thread_send.send((id, message)).map_err(|err| {
-                    ServerCommandError::TerminateThread(format!(
-                        "critical error sending a command to proper channel: {}",
-                        err
-                    ))
-                })?;
+                // The message goes straight to the recipient, the sender is told if it's not online
+                server
+                    .write()
+                    .unwrap()
+                    .send_chat(*local_id, *recipient, message)?;
 
                 // Send OK message to DM sender
                 Ok(BinaryMessage::new_message(format!(
-                    "OK Sent '{}' to ID {id}",
-                    tokens[1]
+                    "OK Sent '{text}' to ID {recipient}"
                 )))
             }
             ServerCommandList::Message(message) => {
-                // Check if message is parseable, if not return error to DM sender
-                let message = match String::from_utf8(message.clone()) {
-                    Ok(value) => value,
-                    Err(err) => {
-                        return Err(ServerCommandError::ErrorMessage(format!("ERROR: {}", err)))
-                    }
-                };
-
-
                 // Send OK back to sender
                 Ok(BinaryMessage::new_message(format!(
                     "Message {message} sent"
                 )))
             }
 
-            ServerCommandList::Hint(message) => {
+            ServerCommandList::Hint(hint) => {
                 let mut server_write_lock = server.write().unwrap();
 
-                let hint = String::from_utf8(message.clone()).unwrap();
-
                 match server_write_lock.get_game_id(*local_id) {
                     Some(game_id) => {
                         server_write_lock.update_game_hint(game_id, hint.clone())?;
 
                         let opponent_id = server_write_lock.get_opponent_id(*local_id)?;
 
-                        thread_send
-                            .send((
-                                opponent_id,
-                                BinaryMessage::new_message(format!("HINT {hint}")),
-                            ))
-                            .map_err(|err| {
-                                ServerCommandError::TerminateThread(format!(
-                                    "critical error sending a command to proper channel: {}",
-                                    err
-                                ))
-                            })?;
+                        server_write_lock.send_message(
+                            Some(*local_id),
+                            opponent_id,
+                            ServerMessageResponse::Hint(hint.clone()).into(),
+                        )?;
 
                         // Send the hint to the host
                         Ok(BinaryMessage::new_message("Hint sent".to_string()))
                     }
-                    None => Err(ServerCommandError::ErrorMessage(format!(
-                        "ERROR no game with id {local_id} found"
-                    ))),
+                    None => Err(ServerCommandError::ErrorMessage(
+                        ErrorCode::NotInGame,
+                        format!("no game with id {local_id} found"),
+                    )),
                 }
             }
 
-            ServerCommandList::Guess(message) => {
+            ServerCommandList::Guess(guess) => {
                 let mut server_write_lock = server.write().unwrap();
 
                 match server_write_lock.get_game_id(*local_id) {
                     Some(game_id) => {
-                        let game = server_write_lock.get_game_mut_ref(game_id).unwrap();
-                        let game_state = game.get_game_state().clone();
+                        // The state and the attempts left are read once the guess is applied
+                        let game_state = server_write_lock
+                            .update_game_guess(game_id, guess.clone())?
+                            .clone();
+                        let game = server_write_lock
+                            .get_game_mut_ref(game_id)
+                            .ok_or_else(|| game_not_found(game_id))?;
                         let game_host_id = game.get_host_id();
-                        let guess = String::from_utf8(message.clone()).map_err(|x| ServerCommandError::ErrorMessage(format!("ERROR: {}", x)))?;
                         let attempts = game.get_attempts();
-                        
-                        server_write_lock.update_game_guess(game_id, guess.clone())?;
-                        
-            
+
                         match game_state {
                             GameState::Victory => {
-                                // If message is valid, send the message to the broadcast channel
-                                thread_send.send((game_host_id, BinaryMessage::new_command("DEFEAT".to_string())))
-                                .map_err(|err| {
-                                    ServerCommandError::TerminateThread(format!(
-                                        "critical error sending a command to proper channel: {}",
-                                        err
-                                    ))
-                                })?;
-            
+                                // The game is over either way, even if the host can't be told
                                 server_write_lock.terminate_game(game_id)?;
-            
-                                Ok(BinaryMessage::new_command("VICTORY".to_string()))
-                            },
+
+                                server_write_lock.send_message(
+                                    Some(*local_id),
+                                    game_host_id,
+                                    ServerMessageResponse::GameDefeat.into(),
+                                )?;
+
+                                Ok(ServerMessageResponse::GameVictory.into())
+                            }
                             GameState::Defeat => {
-                                // If message is valid, send the message to the broadcast channel
-                                thread_send.send((game_host_id, BinaryMessage::new_command("VICTORY".to_string()))).map_err(|err| {
-                                    ServerCommandError::TerminateThread(format!(
-                                        "critical error sending a command to proper channel: {}",
-                                        err
-                                    ))
-                                })?;
-            
-                                Ok(BinaryMessage::new_command("DEFEAT".to_string()))
-                            },
+                                // The game is over either way, even if the host can't be told
+                                server_write_lock.terminate_game(game_id)?;
+
+                                server_write_lock.send_message(
+                                    Some(*local_id),
+                                    game_host_id,
+                                    ServerMessageResponse::GameVictory.into(),
+                                )?;
+
+                                Ok(ServerMessageResponse::GameDefeat.into())
+                            }
                             GameState::Ongoing => {
-                                // If message is valid, send the message to the broadcast channel
-                                thread_send.send((game_host_id, BinaryMessage::new_message(format!("Guess {guess} IS INCORRECT, {} ATTEMPTS LEFT", attempts)))).map_err(|err| {
-                                    ServerCommandError::TerminateThread(format!(
-                                        "critical error sending a command to proper channel: {}",
-                                        err
-                                    ))
-                                })?;
-            
+                                // If message is valid, send the message to the host
+                                let event = ServerMessageResponse::WrongGuess {
+                                    guess: guess.clone(),
+                                    attempts: attempts.into(),
+                                };
+                                server_write_lock.send_message(
+                                    Some(*local_id),
+                                    game_host_id,
+                                    event.into(),
+                                )?;
+
                                 Ok(BinaryMessage::new_message(format!(
-                                    "OK GUESS {guess} IS INCORRECT, {} ATTEMPTS LEFT",
-                                    attempts
+                                    "OK GUESS {guess} IS INCORRECT, {attempts} ATTEMPTS LEFT"
                                 )))
-                            },
+                            }
                         }
-                    },
-                    None => {
-                        Err(ServerCommandError::ErrorMessage(format!(
-                            "CANCELED Game where local player ID {local_id} is trying to guess, does not exist. cancelling match"
-                        )))
-                    },
+                    }
+                    None => Err(ServerCommandError::ErrorMessage(
+                        ErrorCode::NotInGame,
+                        format!("user {local_id} doesn't participate in a game, cannot guess"),
+                    )),
                 }
             }
 
             // Args: ID, Secret
-            ServerCommandList::StartGame(data) => {
-                // Check if the data at least has an ID of the user
-                if data.is_empty() {
-                    return Err(ServerCommandError::ErrorMessage(
-                        "ERROR Direct message must be at least 8 bytes long".to_string(),
-                    ));
-                }
+            ServerCommandList::StartGame { opponent, secret } => {
+                let mut server_write_lock = server.write().unwrap();
 
-                // Parse the ID
-                let string_text = String::from_utf8(data.clone()).unwrap();
-                let tokens: Vec<&str> = string_text.split_whitespace().collect();
+                let game_id = server_write_lock.start_game(*local_id, *opponent, secret.clone())?;
 
-                if tokens.len() != 2 {
-                    return Err(ServerCommandError::ErrorMessage(
-                        "ERROR Direct message must have 2 arguments".to_string(),
-                    ));
+                // The game is only kept if the opponent can be told it started
+                let command = ServerMessageResponse::RequestedGame.into();
+                if let Err(err) =
+                    server_write_lock.send_message(Some(*local_id), *opponent, command)
+                {
+                    server_write_lock.terminate_game(game_id)?;
+                    return Err(err);
                 }
 
-                let opponent_id = match tokens[0].parse::<u64>() {
-                    Ok(value) => value,
-                    Err(err) => {
-                        return Err(ServerCommandError::ErrorMessage(format!(
-                            "ERROR Direct message must start with a valid ID: {err}"
-                        )))
-                    }
-                };
-
-                // Check if message is parseable, if not return error to DM sender
-                let secret = tokens[1].to_string();
-
-                server
-                    .write()
-                    .unwrap()
-                    .start_game(*local_id, opponent_id, secret.clone())?;
-
-                let command = BinaryMessage::new_command("REQUESTEDGAME".to_string());
-
-                // If message is valid, send the message to the broadcast channel
-                thread_send.send((opponent_id, command)).map_err(|err| {
-                    ServerCommandError::TerminateThread(format!(
-                        "critical error sending a command to proper channel: {}",
-                        err
-                    ))
-                })?;
-
-                Ok(BinaryMessage::new_command("REQUESTACK".to_string()))
+                Ok(ServerMessageResponse::RequestAck.into())
             }
 
             ServerCommandList::CancelGame => {
@@ -319,30 +235,33 @@ impl ServerCommandList {
 
                 match server_write_lock.get_game_id(*local_id) {
                     Some(game_id) => {
-                        // Again, the game must exist at this point and we have a mut lock so we can safely unwrap here.
-                        let game_clone =
-                            server_write_lock.get_game_mut_ref(game_id).unwrap().clone();
+                        let game_clone = server_write_lock
+                            .get_game_mut_ref(game_id)
+                            .ok_or_else(|| game_not_found(game_id))?
+                            .clone();
 
                         // Terminate the game
                         server_write_lock.terminate_game(game_id)?;
 
-                        let message = BinaryMessage::new_message("MATCH CANCELED".to_string());
+                        // Either player may cancel, the other one is told
+                        let other_player = if game_clone.get_host_id() == *local_id {
+                            game_clone.get_opponent_id()
+                        } else {
+                            game_clone.get_host_id()
+                        };
 
-                        // If message is valid, send the message to the broadcast channel
-                        thread_send
-                            .send((game_clone.get_opponent_id(), message))
-                            .map_err(|err| {
-                                ServerCommandError::TerminateThread(format!(
-                                    "critical error sending a command to proper channel: {}",
-                                    err
-                                ))
-                            })?;
+                        server_write_lock.send_message(
+                            Some(*local_id),
+                            other_player,
+                            ServerMessageResponse::GameCanceled.into(),
+                        )?;
 
-                        Ok(BinaryMessage::new_command("CANCELED".to_string()))
+                        Ok(ServerMessageResponse::GameCanceled.into())
                     }
-                    None => Err(ServerCommandError::ErrorMessage(format!(
-                        "user {local_id} doesn't participate in a game, cannot terminate"
-                    ))),
+                    None => Err(ServerCommandError::ErrorMessage(
+                        ErrorCode::NotInGame,
+                        format!("user {local_id} doesn't participate in a game, cannot terminate"),
+                    )),
                 }
             }
             ServerCommandList::RequestOpponents => {
@@ -352,39 +271,227 @@ impl ServerCommandList {
                         opponents
                     ))),
                     None => Err(ServerCommandError::ErrorMessage(
-                        "ERROR No opponents found".to_string(),
+                        ErrorCode::NoOpponents,
+                        "No opponents found".to_string(),
                     )),
                 }
-            }  
-        
+            }
         }
     }
 }
 
-impl TryFrom<BinaryMessage> for ServerCommandList {
-    type Error = FromUtf8Error;
-
-    fn try_from(binary_message: BinaryMessage) -> Result<Self, Self::Error> {
-        Ok(match binary_message.get_type() {
-            MessageType::Command => {
-                // Split data to command and the binary part
-                let (command, binary) = binary_message.split()?;
-
-                match command.to_ascii_uppercase().as_str() {
-                    "DM" => Self::DirectMessage(binary),
-                    "HEARTBEAT" => Self::HeartBeat,
-                    "DROP" => Self::Drop,
-                    "HINT" => Self::Hint(binary),
-                    "GUESS" => Self::Guess(binary),
-                    "STARTGAME" => Self::StartGame(binary),
-                    "CANCEL" => Self::CancelGame,
-                    "REQUEST" => Self::RequestOpponents,
-                    _ => Self::Unknown,
-                }
-            }
-            MessageType::Message => Self::Message(binary_message.get_message().clone()),
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A server with three logged in users, with IDs 1, 2 and 3.
+    fn server() -> Arc<RwLock<ServerData>> {
+        let mut server = ServerData::new("password".to_string());
+        for _ in 0..3 {
+            server.add_user();
+        }
+        Arc::new(RwLock::new(server))
+    }
+
+    #[test]
+    fn direct_message_reaches_only_its_recipient() {
+        let server = server();
+        let command = ServerCommandList::DirectMessage {
+            recipient: 2,
+            text: "good luck".to_string(),
+        };
+
+        assert!(command.execute(server.clone(), &1).is_ok());
+
+        let mut server = server.write().unwrap();
+        assert_eq!(
+            server.router.take(2),
+            vec![BinaryMessage::new_message("good luck".to_string())]
+        );
+        assert!(server.router.take(1).is_empty());
+        assert!(server.router.take(3).is_empty());
+    }
+
+    #[test]
+    fn direct_message_to_an_unknown_user_fails() {
+        let server = server();
+        let command = ServerCommandList::DirectMessage {
+            recipient: 42,
+            text: "anyone there?".to_string(),
+        };
+
+        assert!(matches!(
+            command.execute(server.clone(), &1),
+            Err(ServerCommandError::ErrorMessage(ErrorCode::UnknownUser, _))
+        ));
+        assert!(server.read().unwrap().router.waiting_users().is_empty());
+    }
+
+    /// Starts a game hosted by user 1 against user 2, and clears the queues it filled.
+    fn start_game(server: &Arc<RwLock<ServerData>>) {
+        let command = ServerCommandList::StartGame {
+            opponent: 2,
+            secret: "apple".to_string(),
+        };
+        assert!(command.execute(server.clone(), &1).is_ok());
+        server.write().unwrap().router.take(2);
+    }
+
+    #[test]
+    fn game_isnt_started_if_the_opponent_cant_be_told() {
+        let server = server();
+        {
+            let mut server = server.write().unwrap();
+            server.router.set_limit(1);
+            let event = ServerMessageResponse::Ping.into();
+            assert!(server.send_message(None, 2, event).is_ok());
+        }
+
+        let command = ServerCommandList::StartGame {
+            opponent: 2,
+            secret: "apple".to_string(),
+        };
+        assert!(matches!(
+            command.execute(server.clone(), &1),
+            Err(ServerCommandError::ErrorMessage(ErrorCode::UnknownUser, _))
+        ));
+        assert!(server.read().unwrap().get_game_id(1).is_none());
+    }
+
+    #[test]
+    fn hint_reaches_the_opponent_as_an_event() {
+        let server = server();
+        start_game(&server);
+
+        assert!(ServerCommandList::Hint("a fruit".to_string())
+            .execute(server.clone(), &1)
+            .is_ok());
+
+        assert_eq!(
+            server.write().unwrap().router.take(2),
+            vec![BinaryMessage::from(ServerMessageResponse::Hint(
+                "a fruit".to_string()
+            ))]
+        );
+    }
+
+    #[test]
+    fn wrong_guess_reaches_the_host_as_an_event() {
+        let server = server();
+        start_game(&server);
+
+        assert!(ServerCommandList::Guess("pear".to_string())
+            .execute(server.clone(), &2)
+            .is_ok());
+
+        assert_eq!(
+            server.write().unwrap().router.take(1),
+            vec![BinaryMessage::from(ServerMessageResponse::WrongGuess {
+                guess: "pear".to_string(),
+                attempts: 2,
+            })]
+        );
+    }
+
+    #[test]
+    fn correct_guess_wins_the_game() {
+        let server = server();
+        start_game(&server);
+
+        assert_eq!(
+            ServerCommandList::Guess("Apple".to_string())
+                .execute(server.clone(), &2)
+                .unwrap(),
+            BinaryMessage::from(ServerMessageResponse::GameVictory)
+        );
+
+        let mut server = server.write().unwrap();
+        assert_eq!(
+            server.router.take(1),
+            vec![BinaryMessage::from(ServerMessageResponse::GameDefeat)]
+        );
+        assert!(server.get_game_id(2).is_none());
+    }
+
+    #[test]
+    fn last_wrong_guess_loses_the_game() {
+        let server = server();
+        start_game(&server);
+
+        for guess in ["pear", "plum"] {
+            assert!(ServerCommandList::Guess(guess.to_string())
+                .execute(server.clone(), &2)
+                .is_ok());
+        }
+        server.write().unwrap().router.take(1);
+
+        assert_eq!(
+            ServerCommandList::Guess("kiwi".to_string())
+                .execute(server.clone(), &2)
+                .unwrap(),
+            BinaryMessage::from(ServerMessageResponse::GameDefeat)
+        );
+
+        let mut server = server.write().unwrap();
+        assert_eq!(
+            server.router.take(1),
+            vec![BinaryMessage::from(ServerMessageResponse::GameVictory)]
+        );
+        assert!(server.get_game_id(2).is_none());
+    }
+
+    #[test]
+    fn guessing_after_the_game_is_lost_fails() {
+        let server = server();
+        start_game(&server);
+
+        for guess in ["pear", "plum", "kiwi"] {
+            assert!(ServerCommandList::Guess(guess.to_string())
+                .execute(server.clone(), &2)
+                .is_ok());
+        }
+
+        assert!(matches!(
+            ServerCommandList::Guess("apple".to_string()).execute(server.clone(), &2),
+            Err(ServerCommandError::ErrorMessage(ErrorCode::NotInGame, _))
+        ));
+    }
+
+    #[test]
+    fn canceling_tells_the_other_player() {
+        let server = server();
+        start_game(&server);
+
+        assert_eq!(
+            ServerCommandList::CancelGame
+                .execute(server.clone(), &2)
+                .unwrap(),
+            BinaryMessage::from(ServerMessageResponse::GameCanceled)
+        );
+
+        let mut server = server.write().unwrap();
+        assert_eq!(
+            server.router.take(1),
+            vec![BinaryMessage::from(ServerMessageResponse::GameCanceled)]
+        );
+        assert!(server.router.take(2).is_empty());
+    }
 
-            _ => ServerCommandList::Unknown,
-        })
+    #[test]
+    fn dropping_tells_the_other_player() {
+        let server = server();
+        start_game(&server);
+
+        assert!(matches!(
+            ServerCommandList::Drop.execute(server.clone(), &2),
+            Err(ServerCommandError::TerminateUser(_))
+        ));
+
+        let mut server = server.write().unwrap();
+        assert_eq!(
+            server.router.take(1),
+            vec![BinaryMessage::from(ServerMessageResponse::GameCanceled)]
+        );
+        assert!(server.get_game_id(1).is_none());
     }
 }
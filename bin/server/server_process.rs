@@ -1,105 +1,678 @@
-use std::net::TcpListener;
-use std::os::unix::net::UnixListener;
-use std::sync::mpsc::{Receiver, Sender, TryRecvError};
+use std::io::{Read, Write};
 use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+
+use client_server_tcp::address::tcp_address;
+use client_server_tcp::handshake::MIN_PROTOCOL_VERSION;
+use client_server_tcp::{
+    BinaryMessage, Capabilities, Codec, CodecKind, ErrorCode, ProtocolInfo, ServerCommandList,
+    ServerMessageResponse,
+};
+
+use crate::listener::{bind_listener, BoundListener};
+use crate::peer_auth::{PeerCredentials, PeerLogin};
+use crate::protocol_sniffer::{sniff_protocol, ConnectionProtocol, SNIFF_TIMEOUT_MS};
+use crate::reactor::{Listener, Reactor, Transport};
+use crate::server_commands::{Execute, ServerCommandError};
+use crate::shutdown::handle_signals;
+use crate::spectator::serve_spectator;
+use crate::text_gateway::{bind_text_gateway, TextSession};
+use crate::tls::TLS_HANDSHAKE_TIMEOUT_MS;
+use crate::websocket::{accept_websocket, WebSocketStream};
+use crate::{ListenerConfig, ReadWrite, ServerData};
+
+/// Frames or lines handled per readiness event of a connection, a client that keeps sending can't hold up the others.
+/// Whatever is left is handled on the next turn of the reactor.
+pub const MAX_REQUESTS_PER_EVENT: usize = 64;
+
+/// The stream a connection's handler reads and writes, a WebSocket client's frames are unwrapped into lines of text.
+enum ConnectionStream {
+    Plain(Transport),
+    WebSocket(WebSocketStream<Transport>),
+}
 
-use crate::binary_message::BinaryMessage;
+impl ConnectionStream {
+    /// Gets the transport under the stream, which the reactor sends the queued bytes from.
+    fn transport(&mut self) -> &mut Transport {
+        match self {
+            ConnectionStream::Plain(transport) => transport,
+            ConnectionStream::WebSocket(stream) => stream.get_mut(),
+        }
+    }
+}
 
-use crate::server_commands::{ServerCommandError, ServerCommandList};
-use crate::{Broadcast, ReadWrite, ServerData, ServerType, SLEEP_DELAY_MS};
+impl Read for ConnectionStream {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self {
+            ConnectionStream::Plain(transport) => transport.read(buf),
+            ConnectionStream::WebSocket(stream) => stream.read(buf),
+        }
+    }
+}
 
-/// This function handles data communication for a server.
-///
-/// # Arguments
-///
-/// * `server` - An Arc wrapped RwLock protected ServerData object that contains the server's state.
-/// * `stream` - A mutable reference to an object implementing the ReadWrite trait, typically a network stream.
-/// * `thread_recv` - A Receiver from the standard mpsc module, used to receive messages from other threads.
-/// * `thread_send` - A Sender from the standard mpsc module, used to send messages to other threads.
-///
-/// # Behavior
+impl Write for ConnectionStream {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            ConnectionStream::Plain(transport) => transport.write(buf),
+            ConnectionStream::WebSocket(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            ConnectionStream::Plain(transport) => transport.flush(),
+            ConnectionStream::WebSocket(stream) => stream.flush(),
+        }
+    }
+}
+
+/// What a connection is doing, it moves on as data arrives.
+enum Session {
+    /// Waiting for the first bytes, which tell the protocol of the connection, until the deadline.
+    /// A TLS connection finishes its handshake first.
+    Detecting {
+        prefix: Vec<u8>,
+        deadline: Instant,
+        handshaking: bool,
+    },
+    /// The game protocol, in one of the codecs.
+    Framed(FramedSession),
+    /// Lines of text, from `nc`, a WebSocket or the text gateway.
+    Text(TextSession),
+    /// Done, the connection is closed once everything queued for it is sent.
+    Closing,
+}
+
+/// A single connection served by the [`Reactor`].
 ///
-/// The function first validates the user. If the validation fails, it prints an error message and returns.
-/// Then it enters a loop where it tries to receive a message from `thread_recv`. If a message is received and the id matches `local_id`,
-/// it sends a response. If an error occurs during receiving, it checks if the error is because the receiver is empty. If it's not, it prints an error message and returns.
-/// Then it tries to process the stream. If the processing is successful, it checks if a command is returned. If a command is returned, it executes the command.
-/// If the execution is successful, it sends a response. If an error occurs during execution, it checks the type of the error and acts accordingly.
-/// If no command is returned, it continues to the next iteration. If an error occurs during processing, it prints an error message and returns.
-/// At the end of each iteration, it sleeps for [SLEEP_DELAY_MS] milliseconds.
-fn client_connection(
-    server: Arc<RwLock<ServerData>>,
-    mut stream: impl ReadWrite,
-    thread_recv: Receiver<(u64, BinaryMessage)>,
-    thread_send: Sender<(u64, BinaryMessage)>,
-) {
-    // Validate the user and get the local id
-    let mut local_id = match validate_user(&mut stream, server.clone()) {
-        Ok(id) => id,
-        Err(error) => {
-            // If unsuccessful, print an error message and terminate the stream for the client.
-            eprintln!("error validating user: {error}");
-            return;
+/// The connection never blocks, every call handles whatever has arrived and queues the replies on its [`Transport`].
+pub struct Connection {
+    stream: ConnectionStream,
+    session: Session,
+    peer: Option<PeerCredentials>,
+    // The user logged in on this connection, kept once the session is over so the user can still be logged out
+    user: Option<u64>,
+    // The last readiness event stopped at MAX_REQUESTS_PER_EVENT, more may be waiting
+    unread: bool,
+}
+
+impl Connection {
+    /// Creates a connection that detects its protocol from its first bytes,
+    /// so binary clients, browsers, WebSocket clients and `nc` users can all use the same port.
+    ///
+    /// # Arguments
+    ///
+    /// * `transport` - The freshly accepted connection.
+    /// * `peer` - The credentials of the local process on the other end, only known for UNIX sockets.
+    pub fn new(transport: Transport, peer: Option<PeerCredentials>) -> Self {
+        // A TLS client gets the time of a handshake first, the protocol is only detected once it's done
+        let handshaking = transport.is_handshaking();
+        let timeout = match handshaking {
+            true => TLS_HANDSHAKE_TIMEOUT_MS,
+            false => SNIFF_TIMEOUT_MS,
+        };
+
+        Self {
+            stream: ConnectionStream::Plain(transport),
+            session: Session::Detecting {
+                prefix: Vec::new(),
+                deadline: Instant::now() + Duration::from_millis(timeout),
+                handshaking,
+            },
+            peer,
+            user: None,
+            unread: false,
         }
-    };
+    }
 
-    loop {
-        // Try to receive any messages that could have arrived on the broadcast channel.
-        // If yes, send a response to the client the id of the message matches the current client.
-        match thread_recv.try_recv() {
-            Ok((id, value)) => {
-                if id == local_id {
-                    println!("got a message with id: {id}, value: {value:?}");
-
-                    send_response(&mut stream, &value, server.clone(), local_id)
+    /// Creates a connection of the text gateway, it's asked for the password right away.
+    pub fn new_text(
+        transport: Transport,
+        peer: Option<PeerCredentials>,
+        server: Arc<RwLock<ServerData>>,
+    ) -> Self {
+        let mut connection = Self {
+            stream: ConnectionStream::Plain(transport),
+            session: Session::Closing,
+            peer,
+            user: None,
+            unread: false,
+        };
+        connection.session = connection.start_text(Vec::new(), server);
+        connection.user = connection.local_id();
+        connection
+    }
+
+    /// Handles what arrived on the connection, up to [`MAX_REQUESTS_PER_EVENT`] frames or lines.
+    ///
+    /// # Arguments
+    ///
+    /// * `server` - An Arc wrapped RwLock protected ServerData object that contains the server's state.
+    pub fn on_ready(mut self, server: Arc<RwLock<ServerData>>) -> Self {
+        self.unread = false;
+        let result = match &mut self.session {
+            Session::Detecting {
+                prefix,
+                deadline,
+                handshaking,
+            } => match sniff_protocol(&mut self.stream, prefix) {
+                Ok(Some(protocol)) => {
+                    let prefix = std::mem::take(prefix);
+                    return self.start(protocol, prefix, server);
                 }
-            }
+                Ok(None) => {
+                    // The protocol is only waited for once the TLS handshake is done
+                    if *handshaking && !self.stream.transport().is_handshaking() {
+                        *handshaking = false;
+                        *deadline = Instant::now() + Duration::from_millis(SNIFF_TIMEOUT_MS);
+                    }
+                    Ok(false)
+                }
+                Err(err) => {
+                    eprintln!("error detecting the protocol of a connection: {err}");
+                    self.session = Session::Closing;
+                    Ok(false)
+                }
+            },
+            Session::Framed(session) => session.on_ready(&mut self.stream, server),
+            Session::Text(session) => session.on_ready(&mut self.stream, server),
+            // Whatever a closing connection still sends is ignored
+            Session::Closing => Ok(false),
+        };
+
+        self.user = self.user.or(self.local_id());
+        match result {
+            Ok(unread) => self.unread = unread,
             Err(err) => {
-                if err == TryRecvError::Disconnected {
-                    eprintln!("error {err}");
-                    return;
+                eprintln!("closing a connection: {err}");
+                self.session = Session::Closing;
+            }
+        }
+        self
+    }
+
+    /// Handles the deadline of the connection passing.
+    ///
+    /// A client that stalls its TLS handshake is dropped, otherwise nothing conclusive arrived in time
+    /// and only a person typing is this slow, so the connection speaks text.
+    pub fn on_deadline(mut self, server: Arc<RwLock<ServerData>>) -> Self {
+        if let Session::Detecting {
+            prefix,
+            handshaking,
+            ..
+        } = &mut self.session
+        {
+            if *handshaking {
+                eprintln!("the TLS handshake timed out, closing the connection");
+                self.session = Session::Closing;
+            } else {
+                let prefix = std::mem::take(prefix);
+                return self.start(ConnectionProtocol::Text, prefix, server);
+            }
+        }
+        self
+    }
+
+    /// Sends the user of this connection the messages other users sent it.
+    pub fn deliver(&mut self, messages: Vec<BinaryMessage>, server: Arc<RwLock<ServerData>>) {
+        for value in messages {
+            match &mut self.session {
+                Session::Framed(session) => {
+                    session.deliver(&mut self.stream, value, server.clone())
+                }
+                Session::Text(session) => session.deliver(&mut self.stream, value, server.clone()),
+                _ => (),
+            }
+        }
+    }
+
+    /// Tells the client the server is shutting down, the connection is closed once that's sent.
+    pub fn shutdown(&mut self, server: Arc<RwLock<ServerData>>) {
+        match &mut self.session {
+            Session::Framed(session) => session.shutdown(&mut self.stream, server),
+            Session::Text(session) => session.shutdown(&mut self.stream, server),
+            _ => (),
+        }
+        self.session = Session::Closing;
+    }
+
+    /// Disconnects a user who stopped answering heartbeats, the client is told why if it's still there.
+    /// The game of the user is canceled, so the other player doesn't wait for it.
+    pub fn time_out(&mut self, message: String, server: Arc<RwLock<ServerData>>) {
+        if let Some(local_id) = self.local_id() {
+            server.write().unwrap().cancel_game(local_id);
+
+            let error = BinaryMessage::from(ServerMessageResponse::Error {
+                code: ErrorCode::IdleTimeout,
+                message,
+            });
+            self.deliver(vec![error], server.clone());
+
+            if let Err(err) = server.write().unwrap().drop_user(local_id) {
+                eprintln!("error removing user: {err}");
+            }
+        }
+        self.session = Session::Closing;
+    }
+
+    /// Gets the time by which the connection has to move on, while its protocol isn't known yet.
+    pub fn deadline(&self) -> Option<Instant> {
+        match self.session {
+            Session::Detecting { deadline, .. } => Some(deadline),
+            _ => None,
+        }
+    }
+
+    /// Checks whether the connection stopped handling what arrived at [`MAX_REQUESTS_PER_EVENT`],
+    /// the socket won't be readable again for what's already there.
+    pub fn has_unread(&self) -> bool {
+        self.unread && !self.is_finished()
+    }
+
+    /// Checks whether the connection is done and can be closed once its output is sent.
+    pub fn is_finished(&self) -> bool {
+        matches!(self.session, Session::Closing)
+    }
+
+    /// Gets the ID of the user logged in on this connection, even once the connection is closing.
+    pub fn user(&self) -> Option<u64> {
+        self.user
+    }
+
+    /// Gets the ID of the logged in user of this connection.
+    pub fn local_id(&self) -> Option<u64> {
+        match &self.session {
+            Session::Framed(session) => session.local_id(),
+            Session::Text(session) => session.local_id(),
+            _ => None,
+        }
+    }
+
+    /// Gets the transport of the connection.
+    pub fn transport(&mut self) -> &mut Transport {
+        self.stream.transport()
+    }
+
+    /// Hands the connection to the handler of the detected protocol, starting with the bytes read while detecting it.
+    fn start(
+        mut self,
+        protocol: ConnectionProtocol,
+        prefix: Vec<u8>,
+        server: Arc<RwLock<ServerData>>,
+    ) -> Self {
+        println!("accepted a {protocol:?} connection");
+
+        match protocol {
+            ConnectionProtocol::Framed(codec) => {
+                self.session = Session::Framed(FramedSession::new(codec, &prefix, self.peer));
+            }
+            ConnectionProtocol::Text => self.session = self.start_text(prefix, server.clone()),
+            ConnectionProtocol::Http => {
+                if let Err(err) = serve_spectator(&mut self.stream, &prefix, server.clone()) {
+                    eprintln!("error serving the spectator page: {err}");
+                }
+                self.session = Session::Closing;
+            }
+            ConnectionProtocol::WebSocket => match accept_websocket(&mut self.stream, &prefix) {
+                // WebSocket clients speak the text protocol, one line per message
+                Ok(rest) => {
+                    self.stream = match self.stream {
+                        ConnectionStream::Plain(transport) => {
+                            ConnectionStream::WebSocket(WebSocketStream::new(transport, rest))
+                        }
+                        stream => stream,
+                    };
+                    self.session = self.start_text(Vec::new(), server.clone());
+                }
+                Err(err) => {
+                    eprintln!("error upgrading to a websocket: {err}");
+                    self.session = Session::Closing;
                 }
+            },
+        }
+
+        // Frames or lines that came with the first bytes won't make the socket readable again
+        self.on_ready(server)
+    }
+
+    /// Starts the text protocol, trusted local users are logged in and everyone else is asked for the password.
+    fn start_text(&mut self, prefix: Vec<u8>, server: Arc<RwLock<ServerData>>) -> Session {
+        match TextSession::new(&mut self.stream, prefix, self.peer, server) {
+            Ok(session) => Session::Text(session),
+            Err(error) => {
+                eprintln!("error validating text user: {error}");
+                Session::Closing
             }
         }
+    }
+}
+
+/// Where a client of the game protocol is, from connecting to being logged in.
+enum FramedPhase {
+    /// Waiting for the `HELLO` of the client.
+    Handshake,
+    /// Waiting for the password.
+    Login,
+    /// Logged in as the user with this ID.
+    Ready(u64),
+}
+
+/// A connection speaking the game protocol.
+struct FramedSession {
+    // Bytes read from the stream are kept here until they form whole frames, in the format the client picked.
+    codec: Box<dyn Codec>,
+    peer: Option<PeerCredentials>,
+    phase: FramedPhase,
+}
+
+impl FramedSession {
+    /// Creates a new [`FramedSession`] in the detected codec, starting with the bytes read while detecting it.
+    fn new(codec: CodecKind, prefix: &[u8], peer: Option<PeerCredentials>) -> Self {
+        let mut codec = codec.new_codec();
+        codec.push(prefix);
+        Self {
+            codec,
+            peer,
+            phase: FramedPhase::Handshake,
+        }
+    }
 
-        // Check whether we got a message from the client
-        match process_stream(&mut stream, server.clone(), &mut local_id) {
-            Ok(value) => {
-                if let Some(command) = value {
-                    match command.execute(server.clone(), &local_id, thread_send.clone()) {
+    /// Gets the ID of the user, once it's logged in.
+    fn local_id(&self) -> Option<u64> {
+        match self.phase {
+            FramedPhase::Ready(local_id) => Some(local_id),
+            _ => None,
+        }
+    }
+
+    /// Handles the frames that have arrived, up to [`MAX_REQUESTS_PER_EVENT`] of them,
+    /// returning whether it stopped before all of them were handled.
+    ///
+    /// # Arguments
+    ///
+    /// * `stream` - A mutable reference to an object implementing the ReadWrite trait, typically a network stream.
+    /// * `server` - An Arc wrapped RwLock protected ServerData object that contains the server's state.
+    ///
+    /// # Behavior
+    ///
+    /// The client first performs the protocol handshake and then logs in. If either fails, an error is returned and the connection is closed.
+    /// After that every frame is a command. If the execution is successful, it sends a response. If an error occurs during execution,
+    /// it checks the type of the error and acts accordingly. Once every frame that arrived is handled, the function returns.
+    fn on_ready(
+        &mut self,
+        stream: &mut impl ReadWrite,
+        server: Arc<RwLock<ServerData>>,
+    ) -> Result<bool, ServerCommandError> {
+        for _ in 0..MAX_REQUESTS_PER_EVENT {
+            let mut local_id = self.local_id().unwrap_or(0);
+
+            // A message of this user went to a full queue under the block policy, the rest waits until it's queued
+            if server.read().unwrap().router.is_blocked(local_id) {
+                return Ok(false);
+            }
+
+            let value = process_stream(stream, self.codec.as_mut(), server.clone(), &mut local_id);
+
+            let (request_id, command) = match (&self.phase, value) {
+                (_, Ok(Some(value))) => value,
+                (_, Ok(None)) => return Ok(false),
+                // A rejected frame is reported to the client, the connection itself stays usable
+                (
+                    FramedPhase::Ready(local_id),
+                    Err(ServerCommandError::ErrorMessage(code, message)),
+                ) => {
+                    send_response(
+                        stream,
+                        self.codec.as_mut(),
+                        &BinaryMessage::from(ServerMessageResponse::Error { code, message }),
+                        server.clone(),
+                        *local_id,
+                    );
+                    continue;
+                }
+                (_, Err(err)) => {
+                    eprint!("critical error in parsing stream or command parsing: {err}");
+                    return Err(ServerCommandError::TerminateThread(
+                        "Critical error in parsing stream or command parsing".to_string(),
+                    ));
+                }
+            };
+
+            match self.phase {
+                FramedPhase::Handshake => {
+                    // Agree on the protocol version and capabilities before anything else
+                    let capabilities = negotiate_protocol(
+                        stream,
+                        self.codec.as_mut(),
+                        server.clone(),
+                        request_id,
+                        command,
+                    )?;
+
+                    // Frames over 64 KiB are only allowed if the client said it can handle them
+                    if capabilities.contains(Capabilities::LARGE_FRAMES) {
+                        self.codec
+                            .set_max_frame_size(server.read().unwrap().max_frame_size);
+                    }
+
+                    self.phase =
+                        match login_peer(stream, self.codec.as_mut(), server.clone(), self.peer)? {
+                            Some(local_id) => FramedPhase::Ready(local_id),
+                            None => FramedPhase::Login,
+                        };
+                }
+                FramedPhase::Login => {
+                    let local_id = validate_user(
+                        stream,
+                        self.codec.as_mut(),
+                        server.clone(),
+                        request_id,
+                        command,
+                    )?;
+                    self.phase = FramedPhase::Ready(local_id);
+                }
+                FramedPhase::Ready(local_id) => {
+                    // Every direct reply echoes the request id, so the client knows what it answers
+                    match command.execute(server.clone(), &local_id) {
                         Ok(value) => {
-                            send_response(&mut stream, &value, server.clone(), local_id);
+                            send_response(
+                                stream,
+                                self.codec.as_mut(),
+                                &value.with_request_id(request_id),
+                                server.clone(),
+                                local_id,
+                            );
                         }
-                        Err(err) => {
-                            match err {
-                                // If we encounter a critical error, we need to terminate server-side
-                                ServerCommandError::TerminateThread(message) => {
-                                    eprintln!("a critical error has occured: {message}, terminating thread");
-                                    return;
-                                }
-                                // Otherwise, print the error to the user so he acknowledges
-                                ServerCommandError::ErrorMessage(message) => {
-                                    send_response(
-                                        &mut stream,
-                                        &BinaryMessage::new_message(message),
-                                        server.clone(),
-                                        local_id,
-                                    );
-                                }
-                                ServerCommandError::TerminateUser(message) => {
-                                    eprintln!("Terminating thread: {message}");
-                                    return;
-                                }
-                            }
+                        // Otherwise, print the error to the user so he acknowledges
+                        Err(ServerCommandError::ErrorMessage(code, message)) => {
+                            send_response(
+                                stream,
+                                self.codec.as_mut(),
+                                &BinaryMessage::from(ServerMessageResponse::Error {
+                                    code,
+                                    message,
+                                })
+                                .with_request_id(request_id),
+                                server.clone(),
+                                local_id,
+                            );
                         }
+                        // If we encounter a critical error, we need to close the connection server-side
+                        Err(err) => return Err(err),
                     }
                 }
             }
-            Err(err) => {
-                eprint!("critical error in parsing stream or command parsing: {err}");
-                return;
+        }
+        Ok(true)
+    }
+
+    /// Sends the user a message from another user, it's never a reply to this client's requests.
+    fn deliver(
+        &mut self,
+        stream: &mut impl ReadWrite,
+        value: BinaryMessage,
+        server: Arc<RwLock<ServerData>>,
+    ) {
+        if let Some(local_id) = self.local_id() {
+            send_response(
+                stream,
+                self.codec.as_mut(),
+                &value.into_push(),
+                server,
+                local_id,
+            );
+        }
+    }
+
+    /// Tells the client why the server is shutting down and logs the user out.
+    fn shutdown(&mut self, stream: &mut impl ReadWrite, server: Arc<RwLock<ServerData>>) {
+        let local_id = self.local_id().unwrap_or(0);
+        notify_shutdown(stream, self.codec.as_mut(), server.clone(), local_id);
+
+        if let Some(local_id) = self.local_id() {
+            if let Err(err) = server.write().unwrap().drop_user(local_id) {
+                eprintln!("error removing user: {err}");
             }
+            println!("closed the connection of user {local_id}, the server is shutting down");
+        }
+    }
+}
+
+/// Performs the `HELLO`/`WELCOME` handshake with the client.
+///
+/// The first frame of every connection must be a `HELLO` with the client's protocol version and capabilities.
+/// Clients that are too old, or that skip the handshake, get an error explaining why and are disconnected.
+///
+/// # Arguments
+///
+/// * `stream: &mut impl ReadWrite` - A mutable reference to an object that implements the `ReadWrite` trait. This is the stream the reply is written to.
+/// * `codec: &mut dyn Codec` - The connection's codec, kept so that frames following the `HELLO` are not lost.
+/// * `server: Arc<RwLock<ServerData>>` - An `Arc<RwLock<ServerData>>` that allows multiple threads to safely share and modify the `ServerData` object.
+/// * `request_id: Option<u32>` - The request id of the first frame.
+/// * `command: ServerCommandList` - The first frame of the client.
+///
+/// # Returns
+///
+/// * [`Capabilities`] - the capabilities supported by both the client and the server.
+fn negotiate_protocol(
+    stream: &mut impl ReadWrite,
+    codec: &mut dyn Codec,
+    server: Arc<RwLock<ServerData>>,
+    request_id: Option<u32>,
+    command: ServerCommandList,
+) -> Result<Capabilities, ServerCommandError> {
+    let ServerCommandList::Hello(client_info) = command else {
+        send_response(
+            stream,
+            codec,
+            &BinaryMessage::from(ServerMessageResponse::Error {
+                code: ErrorCode::HandshakeRequired,
+                message: "handshake required, send HELLO before authenticating".to_string(),
+            })
+            .with_request_id(request_id),
+            server.clone(),
+            0,
+        );
+        return Err(ServerCommandError::TerminateThread(
+            "Client skipped the handshake".to_string(),
+        ));
+    };
+
+    let server_info = ProtocolInfo::current();
+    println!("received HELLO {client_info:?}");
+
+    if !client_info.is_compatible() {
+        let message = format!(
+            "protocol version {} is not supported, the server speaks version {} and requires at least {}",
+            client_info.version,
+            server_info.version,
+            MIN_PROTOCOL_VERSION
+        );
+        send_response(
+            stream,
+            codec,
+            &BinaryMessage::from(ServerMessageResponse::Error {
+                code: ErrorCode::UnsupportedVersion,
+                message: message.clone(),
+            })
+            .with_request_id(request_id),
+            server.clone(),
+            0,
+        );
+        return Err(ServerCommandError::TerminateThread(message));
+    }
+
+    // Only the capabilities both sides support, and the codec can carry, can be used on this connection
+    let capabilities = server_info
+        .capabilities
+        .intersection(client_info.capabilities)
+        .intersection(codec.kind().capabilities());
+    codec.set_capabilities(capabilities);
+
+    send_response(
+        stream,
+        codec,
+        &BinaryMessage::from(ServerMessageResponse::Welcome(ProtocolInfo {
+            version: server_info.version,
+            capabilities,
+        }))
+        .with_request_id(request_id),
+        server.clone(),
+        0,
+    );
+    Ok(capabilities)
+}
+
+/// Logs in a trusted local client right after the handshake, without a password.
+///
+/// # Arguments
+///
+/// * `stream: &mut impl ReadWrite` - A mutable reference to an object that implements the `ReadWrite` trait. This is the stream the reply is written to.
+/// * `codec: &mut dyn Codec` - The connection's codec.
+/// * `server: Arc<RwLock<ServerData>>` - An `Arc<RwLock<ServerData>>` that allows multiple threads to safely share and modify the `ServerData` object.
+/// * `peer: Option<PeerCredentials>` - The credentials of a local client, a trusted local user gets an id without sending the password.
+///
+/// # Returns
+///
+/// * [`Option<u64>`] - the id of a trusted user, or `None` if the client has to send the password.
+fn login_peer(
+    stream: &mut impl ReadWrite,
+    codec: &mut dyn Codec,
+    server: Arc<RwLock<ServerData>>,
+    peer: Option<PeerCredentials>,
+) -> Result<Option<u64>, ServerCommandError> {
+    // The kernel already told us who a local client is, so the allowed ones never send a password
+    let login = server.read().unwrap().peer_auth.login(peer);
+    match login {
+        PeerLogin::Trusted(user) => {
+            let local_id = server.write().unwrap().add_local_user(user.clone());
+            println!("logged in local user {user} as id {local_id}");
+
+            send_response(
+                stream,
+                codec,
+                &BinaryMessage::from(ServerMessageResponse::ID(local_id)),
+                server.clone(),
+                local_id,
+            );
+            Ok(Some(local_id))
+        }
+        PeerLogin::Refused(peer) => {
+            send_response(
+                stream,
+                codec,
+                &BinaryMessage::from(ServerMessageResponse::Error {
+                    code: ErrorCode::AuthFailed,
+                    message: "local user not allowed".to_string(),
+                }),
+                server.clone(),
+                0,
+            );
+            Err(ServerCommandError::TerminateThread(format!(
+                "Local user {peer} not allowed"
+            )))
         }
-        std::thread::sleep(std::time::Duration::from_millis(SLEEP_DELAY_MS));
+        PeerLogin::Password => Ok(None),
     }
 }
 
@@ -107,368 +680,315 @@ fn client_connection(
 ///
 /// # Arguments
 ///
-/// * `stream: &mut impl ReadWrite` - A mutable reference to an object that implements the `ReadWrite` trait. This is the stream from which data is read.
+/// * `stream: &mut impl ReadWrite` - A mutable reference to an object that implements the `ReadWrite` trait. This is the stream the reply is written to.
+/// * `codec: &mut dyn Codec` - The connection's codec, kept so that frames following the password are not lost.
 /// * `server: Arc<RwLock<ServerData>>` - An `Arc<RwLock<ServerData>>` that allows multiple threads to safely share and modify the `ServerData` object.
-/// * `local_id: &mut u64` - A mutable reference to the ID of the client from which data is being read.
+/// * `request_id: Option<u32>` - The request id of the frame.
+/// * `command: ServerCommandList` - The first frame after the handshake, it has to be the password.
 ///
 /// # Returns
 ///
-/// * [`bool`] - if the user is validated, i.e. correct password received, `true` is returned. If the user is not validated, `false` is returned.
+/// * [`u64`] - if the user is validated, i.e. correct password received, the new id of the user is returned.
 fn validate_user(
     stream: &mut impl ReadWrite,
+    codec: &mut dyn Codec,
     server: Arc<RwLock<ServerData>>,
+    request_id: Option<u32>,
+    command: ServerCommandList,
 ) -> Result<u64, ServerCommandError> {
-    loop {
-        match process_stream(stream, server.clone(), &mut 0) {
-            Ok(value) => match value {
-                Some(command) => match command {
-                    ServerCommandList::Message(pass) => {
-                        let message = match String::from_utf8(pass.clone()) {
-                            Ok(value) => value,
-                            Err(err) => {
-                                return Err(ServerCommandError::ErrorMessage(format!(
-                                    "ERROR: {}",
-                                    err
-                                )))
-                            }
-                        };
+    match command {
+        ServerCommandList::Message(message) => {
+            let validated = server.read().unwrap().validate_password(&message);
+            println!("password received, validated: {validated}");
 
-                        let validated = server.read().unwrap().validate_password(&message);
-                        println!("received password {message}, validated: {validated}");
-
-                        match validated {
-                            true => {
-                                let local_id = server.write().unwrap().add_user();
-                                println!("sending localId: {}", local_id);
-
-                                send_response(
-                                    stream,
-                                    &BinaryMessage::new_message(format!("ID {local_id}")),
-                                    server.clone(),
-                                    local_id,
-                                );
-                                println!(
-                                    "Added id {local_id} - Server data: {:?}",
-                                    server.read().unwrap().connected_users
-                                );
-                                return Ok(local_id);
-                            }
-                            false => {
-                                send_response(
-                                    stream,
-                                    &BinaryMessage::new_message(
-                                        "ERROR password incorrect".to_string(),
-                                    ),
-                                    server.clone(),
-                                    0,
-                                );
-                                return Err(ServerCommandError::TerminateThread(
-                                    "Password incorrect".to_string(),
-                                ));
-                            }
-                        };
-                    }
-                    _ => {
-                        send_response(
-                            stream,
-                            &BinaryMessage::new_message("ERROR command incorrect".to_string()),
-                            server.clone(),
-                            0,
-                        );
-                        return Err(ServerCommandError::TerminateThread(
-                            "Command incorrect".to_string(),
-                        ));
-                    }
-                },
-                None => {
-                    continue;
+            match validated {
+                true => {
+                    let local_id = server.write().unwrap().add_user();
+                    println!("sending localId: {}", local_id);
+
+                    send_response(
+                        stream,
+                        codec,
+                        &BinaryMessage::from(ServerMessageResponse::ID(local_id))
+                            .with_request_id(request_id),
+                        server.clone(),
+                        local_id,
+                    );
+                    println!(
+                        "Added id {local_id} - Server data: {:?}",
+                        server.read().unwrap().connected_users
+                    );
+                    Ok(local_id)
+                }
+                false => {
+                    send_response(
+                        stream,
+                        codec,
+                        &BinaryMessage::from(ServerMessageResponse::Error {
+                            code: ErrorCode::AuthFailed,
+                            message: "password incorrect".to_string(),
+                        })
+                        .with_request_id(request_id),
+                        server.clone(),
+                        0,
+                    );
+                    Err(ServerCommandError::TerminateThread(
+                        "Password incorrect".to_string(),
+                    ))
                 }
-            },
-            Err(err) => {
-                eprint!("critical error in parsing stream or command parsing: {err}");
-                return Err(ServerCommandError::TerminateThread(
-                    "Critical error in parsing stream or command parsing".to_string(),
-                ));
             }
         }
+        _ => {
+            send_response(
+                stream,
+                codec,
+                &BinaryMessage::from(ServerMessageResponse::Error {
+                    code: ErrorCode::AuthRequired,
+                    message: "command incorrect, send the password first".to_string(),
+                })
+                .with_request_id(request_id),
+                server.clone(),
+                0,
+            );
+            Err(ServerCommandError::TerminateThread(
+                "Command incorrect".to_string(),
+            ))
+        }
     }
 }
 
-/// Send data to TCP stream.
+/// Sends a response to the client, the user is dropped if it can't be written.
 ///
 /// # Arguments
 ///
-/// * `stream: &mut impl ReadWrite` - A mutable reference to an object that implements the `ReadWrite` trait. This is the stream from which data is read.
-/// * `response: &Message` - A reference to the data format to be sent to the TCP stream.
+/// * `stream: &mut impl ReadWrite` - A mutable reference to an object that implements the `ReadWrite` trait. This is the stream the response is written to.
+/// * `codec: &mut dyn Codec` - The connection's codec, it splits large responses into chunks and replaces responses that are too large by an error.
+/// * `response: &BinaryMessage` - The response to send.
 /// * `server: Arc<RwLock<ServerData>>` - An `Arc<RwLock<ServerData>>` that allows multiple threads to safely share and modify the `ServerData` object.
-/// * `local_id: &mut u64` - A mutable reference to the ID of the client from which data is being read.
-///
-/// # Returns
-///
-/// * [`Result<Option<ServerCommandList>, String>`] - If data is successfully read and processed, an `Option<ServerCommandList>` is returned. If an error occurs, a [`String`] error message is returned.
+/// * `local_id: u64` - The ID of the client the response is sent to.
 fn send_response(
     stream: &mut impl ReadWrite,
+    codec: &mut dyn Codec,
     response: &BinaryMessage,
     server: Arc<RwLock<ServerData>>,
     local_id: u64,
 ) {
-    println!("writing a response to client: {:?}", response);
-    println!(
-        "writing a response to client: {:#?}",
-        String::from_utf8(response.get_message().clone()).unwrap()
-    );
-    if let Err(err) = stream.write_all(&response.serialize()) {
-        if let Err(error) = server.write().unwrap().drop_user(local_id) {
-            eprintln!("critical error writing a response: {err} and {error}");
+    // A response the client can't accept is never truncated, the client gets an error instead.
+    let frames = match codec.encode(response) {
+        Ok(frames) => frames,
+        Err(err) => {
+            eprintln!("error encoding a response: {err}");
+            // The error itself is tiny, so it always fits
+            codec
+                .encode(&BinaryMessage::from(ServerMessageResponse::Error {
+                    code: ErrorCode::from(&err),
+                    message: format!("response dropped, {err}"),
+                }))
+                .unwrap_or_default()
         }
+    };
+
+    for frame in frames {
+        if let Err(err) = stream.write_all(&frame) {
+            if let Err(error) = server.write().unwrap().drop_user(local_id) {
+                eprintln!("critical error writing a response: {err} and {error}");
+            }
+            return;
+        }
+    }
+}
+
+/// Sends a client the shutdown events, if the server is shutting down.
+fn notify_shutdown(
+    stream: &mut impl ReadWrite,
+    codec: &mut dyn Codec,
+    server: Arc<RwLock<ServerData>>,
+    local_id: u64,
+) {
+    // The read lock has to be released before sending, a failed write takes the write lock
+    let events = server.read().unwrap().shutdown_events(local_id);
+    for event in events.into_iter().flatten() {
+        send_response(
+            stream,
+            codec,
+            &BinaryMessage::from(event).into_push(),
+            server.clone(),
+            local_id,
+        );
     }
 }
 
 /// Processes a stream of data from a client.
 ///
-/// This function reads data from a client, processes it, and returns a `ServerCommandList` or an error. It runs in an infinite loop, constantly trying to read data from the stream. If data is successfully read, it is processed and a `ServerCommandList` is returned. If an error occurs while reading the data, the error is handled and returned.
+/// This function hands out the next complete frame buffered in the [`Codec`] as a `ServerCommandList`.
+/// If no complete frame is buffered, it reads once from the stream and tries again, so frames split across reads are reassembled
+/// and frames arriving together are handed out one per call. If an error occurs while reading the data, the error is handled and returned.
 ///
 /// # Arguments
 ///
 /// * `stream: &mut impl ReadWrite` - A mutable reference to an object that implements the `ReadWrite` trait. This is the stream from which data is read.
+/// * `codec: &mut dyn Codec` - The connection's codec that holds bytes which are not yet a complete frame.
 /// * `server: Arc<RwLock<ServerData>>` - An `Arc<RwLock<ServerData>>` that allows multiple threads to safely share and modify the `ServerData` object.
 /// * `local_id: &mut u64` - A mutable reference to the ID of the client from which data is being read.
 ///
 /// # Returns
 ///
-/// * [`Result<Option<ServerCommandList>, String>`] - If data is successfully read and processed, an `Option<ServerCommandList>` is returned. If an error occurs, a [`String`] error message is returned.
+/// * [`Result<Option<(Option<u32>, ServerCommandList)>, String>`] - If a complete frame is available, the request id of the frame and its `ServerCommandList` are returned. If an error occurs, a [`String`] error message is returned.
 fn process_stream(
     stream: &mut impl ReadWrite,
+    codec: &mut dyn Codec,
     server: Arc<RwLock<ServerData>>,
     local_id: &mut u64,
-) -> Result<Option<ServerCommandList>, ServerCommandError> {
-    // The buffer could be extended if needed,
-    // but this should be enough for most cases, unless you spectate a 1000 games.
-    let mut temp_buffer: Vec<u8> = vec![0u8; 4096];
-
-    // Read the stream and try to parse the message
-    match stream.read(&mut temp_buffer) {
-        Ok(size) => {
-            if size == 0 {
-                if let Err(err) = server.write().unwrap().drop_user(*local_id) {
-                    return Err(ServerCommandError::TerminateThread(format!(
-                        "error removing user: {err}"
-                    )));
-                }
-                return Err(ServerCommandError::TerminateThread(
-                    "connection closed by peer".to_string(),
-                ));
-            }
+) -> Result<Option<(Option<u32>, ServerCommandList)>, ServerCommandError> {
+    // A previous read could have carried more than one frame, so hand those out before reading again.
+    if let Some(frame) = next_frame(codec, server.clone())? {
+        return parse_frame(frame).map(Some);
+    }
 
-            println!(
-                "Buffer contents: {:#?}",
-                String::from_utf8(temp_buffer.clone()).unwrap()
-            );
+    // Read the stream and try to parse the message.
+    // We keep reading until a whole frame arrives or nothing more has arrived, so large frames don't wait a loop per read.
+    loop {
+        match codec.read_from(stream) {
+            Ok(size) => {
+                if size == 0 {
+                    if let Err(err) = server.write().unwrap().drop_user(*local_id) {
+                        return Err(ServerCommandError::TerminateThread(format!(
+                            "error removing user: {err}"
+                        )));
+                    }
+                    return Err(ServerCommandError::TerminateThread(
+                        "connection closed by peer".to_string(),
+                    ));
+                }
 
-            if String::from_utf8(temp_buffer.clone())
-                .unwrap()
-                .starts_with("GET / HTTP/1.1")
-            {
-                println!("GOT A BROwOSER");
-                let spectator = server.read().unwrap().get_spectator_data();
-                let spectator = format!("{:#?}", spectator);
-                let response = format!("HTTP/1.1 200 OK\r\nContent-Type: text/html; charset=UTF-8\r\n\r\n<!DOCTYPE html><html><head><title>Server</title></head><body><h1>Server</h1><p>{spectator}</p></body></html>");
-                stream.write_all(response.as_bytes()).unwrap();
-                return Ok(None);
+                // Parse the message if a whole frame has arrived, otherwise read more data
+                if let Some(frame) = next_frame(codec, server.clone())? {
+                    return parse_frame(frame).map(Some);
+                }
             }
-
-            // Parse the message or return an empty one
-            let message: ServerCommandList =
-                match BinaryMessage::deserialize(&temp_buffer).try_into() {
-                    Ok(value) => value,
-                    Err(err) => {
-                        eprintln!("error deserializing message: {err}");
-                        return Ok(None);
+            Err(e) => {
+                // If the error is due to the stream being empty, return None
+                if let std::io::ErrorKind::WouldBlock = e.kind() {
+                    return Ok(None);
+                } else {
+                    if let Err(err) = server.write().unwrap().drop_user(*local_id) {
+                        eprintln!("error removing user: {err}");
                     }
-                };
-            Ok(Some(message))
-        }
-        Err(e) => {
-            // If the error is due to the stream being empty, return None
-            if let std::io::ErrorKind::WouldBlock = e.kind() {
-                Ok(None)
-            } else {
-                if let Err(err) = server.write().unwrap().drop_user(*local_id) {
-                    eprintln!("error removing user: {err}");
+                    return Err(ServerCommandError::TerminateThread(format!(
+                        "critical error in stream: {e}"
+                    )));
                 }
-                Err(ServerCommandError::TerminateThread(format!(
-                    "critical error in stream: {e}"
-                )))
             }
         }
     }
 }
 
-/// This server estabilishes the type of the server and runs the thread pool of data processing.
-pub fn run_server(server_data: ServerData, connection_endpoint: &str) -> std::io::Result<()> {
-    // address can be either UNIX pipe path or IP address
-    let address;
+/// Takes the next complete frame from the codec, turning a rejected frame into an error for the client.
+///
+/// Corrupted frames are also counted in the server's stats.
+fn next_frame(
+    codec: &mut dyn Codec,
+    server: Arc<RwLock<ServerData>>,
+) -> Result<Option<BinaryMessage>, ServerCommandError> {
+    codec.next_frame().map_err(|err| {
+        let code = ErrorCode::from(&err);
+        if code == ErrorCode::ChecksumMismatch {
+            server.write().unwrap().record_checksum_failure();
+        }
+        ServerCommandError::ErrorMessage(code, err.to_string())
+    })
+}
 
-    // prevent a deadlock by caching the value
-    let server_type = server_data.server_type.clone();
+/// Parses a complete frame into its request id and a [`ServerCommandList`], turning malformed commands into an error for the client.
+fn parse_frame(
+    frame: BinaryMessage,
+) -> Result<(Option<u32>, ServerCommandList), ServerCommandError> {
+    let request_id = frame.get_request_id();
+    match frame.try_into() {
+        Ok(value) => Ok((request_id, value)),
+        Err(err) => {
+            eprintln!("error deserializing message: {err}");
+            Err(ServerCommandError::ErrorMessage(
+                ErrorCode::from(&err),
+                err.to_string(),
+            ))
+        }
+    }
+}
 
-    // Create an Arc RWLock for multithreaded use of server data
+/// This server listens on every given TCP and UNIX socket and serves all their connections from one reactor.
+///
+/// All listeners share one router and one game state, so players on different sockets can play each other.
+/// The server runs until SIGINT or SIGTERM, then it stops accepting, tells every client it's shutting down
+/// and waits for the connections to close until the shutdown deadline.
+pub fn run_server(server_data: ServerData, listeners: Vec<ListenerConfig>) -> std::io::Result<()> {
+    // Create an Arc RWLock for server data, shared by the reactor and the signal handler
     let server_data = Arc::new(RwLock::from(server_data));
 
-    // Create a channel to communicate from threads to dispatcher.
-    let (thread_send, thread_recv) = std::sync::mpsc::channel();
-
-    // Create a broadcast from the dispatcher to all threads to catch messages.
-    let broadcast = Arc::new(RwLock::from(Broadcast::new()));
-
-    // Clone the broadcast
-    let broadcast_clone = broadcast.clone();
-
-    // Thread handles, creating the list of handles to await for join (finish) of all of them so this function will block.
-    let mut thread_handles = vec![];
-
-    // Launch the dispatcher with the appropriate channels as a separate thread.
-    // Push the handle to the thread_handles.
-    let handle = std::thread::spawn(move || {
-        dispatch(thread_recv, broadcast_clone.clone());
-    });
-    thread_handles.push(handle);
-
     // Print the server password to the console.
     println!(
         "Current server password is set to: {}",
         server_data.read().unwrap().password
     );
 
-    // Check the type of the server
-    match server_type {
-        ServerType::TCP => {
-            // Address is hardcoded for localhost, but could be changed with an argument. Port is based on the argument.
-            address = format!("127.0.0.1:{connection_endpoint}");
-            println!("Starting TCP server on address {address}");
-
-            // Start listening on the address.
-            let listener = TcpListener::bind(address)?;
-
-            // Accept connections and process them serially - creating a thread for each one.
-            // Also set a read timeout in case a client drops connection
-            for stream in listener.incoming() {
-                let stream = stream.unwrap();
-                stream
-                    .set_read_timeout(Some(std::time::Duration::from_millis(SLEEP_DELAY_MS)))
-                    .unwrap();
-
-                // Rust needs that we clone the ARCs here to send them to the thread.
-                let server_clone = server_data.clone();
-                let broadcast_clone = broadcast.clone();
-                let thread_send_clone = thread_send.clone();
-
-                // Launch each connection in a separate thread,
-                // giving them the communication channels to the dispatcher and from dispatcher.
-                let handle = std::thread::spawn(move || {
-                    let subscription;
-                    // We need to drop the broadcast write lock, so we scope it.
-                    {
-                        let mut broadcast = broadcast_clone.write().unwrap();
-                        subscription = broadcast.subscribe();
-                    }
-                    client_connection(
-                        server_clone,
-                        stream,
-                        subscription,
-                        thread_send_clone.clone(),
-                    );
-                });
-                // Push each TCP stream to the pool
-                thread_handles.push(handle);
-            }
-        }
-        ServerType::UNIX => {
-            address = format!("/tmp/{connection_endpoint}");
-            println!("starting server on UNIX socket {address}");
-
-            // The UNIX pipe doesn't get removed automatically, so we remove it here if it exists.
-            std::fs::remove_file(&address).unwrap_or_else(|err| match err.kind() {
-                std::io::ErrorKind::NotFound => (),
-                _ => eprintln!("error removing socket: {}", err),
-            });
-
-            // We bind to the UNIX pipe
-            let listener = UnixListener::bind(address)?;
-
-            // And then add each concurrent stream to a separate thread
-            for stream in listener.incoming() {
-                // We have to unwrap here due to rust needing a proper binding
-                // and not an .unwrap() later that would move the value.
-                let stream = stream.unwrap();
-
-                // Set a read timeout for users that drop out
-                stream
-                    .set_read_timeout(Some(std::time::Duration::from_millis(SLEEP_DELAY_MS)))
-                    .unwrap();
-
-                // Internal clones of ARCs for cross-thread send
-                let server_clone = server_data.clone();
-                let broadcast_clone = broadcast.clone();
-                let thread_send_clone = thread_send.clone();
-                let handle = std::thread::spawn(move || {
-                    // Dropping the write lock by scoping the lock
-                    let subscription;
-                    {
-                        let mut broadcast = broadcast_clone.write().unwrap();
-                        subscription = broadcast.subscribe();
-                    }
-                    client_connection(
-                        server_clone,
-                        stream,
-                        subscription,
-                        thread_send_clone.clone(),
-                    );
-                });
-
-                // Push the thread to a pool
-                thread_handles.push(handle);
-            }
-        }
+    // Every listener is bound before any connection is accepted, so a wrong address stops the server right away.
+    let unix_permissions = server_data.read().unwrap().unix_permissions.clone();
+    let mut bound_listeners = vec![];
+    for listener in &listeners {
+        bound_listeners.extend(bind_listener(listener, &unix_permissions)?);
     }
 
-    // Wait for all threads to join (finish)
-    for handle in thread_handles {
-        handle.join().unwrap();
+    let tls_config = server_data.read().unwrap().tls_config.clone();
+    if tls_config.is_some() {
+        println!("TCP connections are encrypted with TLS");
     }
-    Ok(())
-}
 
-/// Continuously dispatches messages received from threads to a broadcast channel.
-///
-/// This function runs in an infinite loop, constantly trying to receive messages from the `thread_dispatch_recv` channel. When a message is received, it is sent to the `broadcast` channel. If an error occurs while trying to receive a message, the error is handled and logged.
-///
-/// # Arguments
-///
-/// * `thread_dispatch_recv: Receiver<(u64, String)>` - The receiving end of a channel from which messages sent by threads are received. Each message is a tuple where the first element is the ID of the thread that sent the message and the second element is the message itself.
-/// * `broadcast: Arc<RwLock<Broadcast>>` - An `Arc<RwLock<Broadcast>>` that allows multiple threads to safely share and modify the `Broadcast` object. Messages received from `thread_dispatch_recv` are sent to this broadcast channel.
-pub fn dispatch(
-    thread_dispatch_recv: Receiver<(u64, BinaryMessage)>,
-    broadcast: Arc<RwLock<Broadcast>>,
-) {
-    loop {
-        match thread_dispatch_recv.try_recv() {
-            Ok(message) => {
-                println!(
-                    "received a message from thread ID {}, contains: {:?}",
-                    message.0, message.1
-                );
-                // When we get a message to send to another thread, broadcast it to all with the id of the receiver.
-                broadcast.write().unwrap().broadcast(message);
-                println!("sent the message to broadcast channel");
+    // Socket files are removed once the server has shut down
+    let mut socket_files = vec![];
+
+    // Every socket is served by the same reactor, sharing the router and the games.
+    let mut reactor_listeners = vec![];
+    for listener in bound_listeners {
+        match listener {
+            BoundListener::Tcp(listener) => {
+                println!("Starting TCP server on address {}", listener.local_addr()?);
+                listener.set_nonblocking(true)?;
+                reactor_listeners.push(Listener::Tcp(
+                    mio::net::TcpListener::from_std(listener),
+                    tls_config.clone(),
+                ));
             }
-            Err(err) => {
-                if let TryRecvError::Disconnected = err {
-                    eprint!("error on dispatch recv: {err}");
-                }
+            BoundListener::Unix {
+                listener,
+                address,
+                socket_file,
+            } => {
+                println!("starting server on UNIX socket {address}");
+                socket_files.push(socket_file);
+                listener.set_nonblocking(true)?;
+                reactor_listeners.push(Listener::Unix(mio::net::UnixListener::from_std(listener)));
             }
         }
-        // We wait a bit to not hog the CPU
-        std::thread::sleep(std::time::Duration::from_millis(SLEEP_DELAY_MS));
     }
+
+    // The text gateway listens on its own TCP port, next to the TCP and UNIX listeners of the binary protocol.
+    let text_port = server_data.read().unwrap().text_port.clone();
+    if let Some(text_port) = text_port {
+        let listener = bind_text_gateway(&tcp_address(&text_port))?;
+        reactor_listeners.push(Listener::Text(listener));
+    }
+
+    let reactor = Reactor::new(server_data.clone(), reactor_listeners)?;
+    handle_signals(server_data.clone(), reactor.waker())?;
+
+    // Serve every connection until the server has shut down
+    reactor.run()?;
+
+    server_data.write().unwrap().end_all_games();
+    drop(socket_files);
+
+    println!("server shut down");
+    let _ = std::io::stdout().flush();
+    let _ = std::io::stderr().flush();
+    Ok(())
 }
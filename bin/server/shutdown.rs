@@ -0,0 +1,49 @@
+use std::sync::{Arc, RwLock};
+use std::time::Instant;
+
+use mio::Waker;
+use signal_hook::consts::{SIGINT, SIGTERM};
+use signal_hook::iterator::Signals;
+
+use crate::ServerData;
+
+/// Time the connections get to close once the server starts shutting down, unless configured otherwise.
+pub const DEFAULT_SHUTDOWN_TIMEOUT_MS: u64 = 5000;
+
+#[derive(Debug, Clone)]
+/// Why the server is shutting down, and until when its connections may take to close.
+pub struct Shutdown {
+    pub reason: String,
+    pub deadline: Instant,
+}
+
+/// Starts a thread shutting the server down gracefully on SIGINT or SIGTERM, the reactor is woken to start it.
+///
+/// A second signal stops the server right away, in case a graceful shutdown takes too long.
+pub fn handle_signals(server: Arc<RwLock<ServerData>>, waker: Arc<Waker>) -> std::io::Result<()> {
+    let mut signals = Signals::new([SIGINT, SIGTERM])?;
+
+    std::thread::spawn(move || {
+        for signal in signals.forever() {
+            let name = match signal {
+                SIGINT => "SIGINT",
+                _ => "SIGTERM",
+            };
+
+            if server.read().unwrap().is_shutting_down() {
+                eprintln!("received {name} again, stopping right away");
+                std::process::exit(1);
+            }
+
+            println!("received {name}, shutting down");
+            server
+                .write()
+                .unwrap()
+                .begin_shutdown(format!("the server received {name}"));
+            if let Err(err) = waker.wake() {
+                eprintln!("error waking the reactor: {err}");
+            }
+        }
+    });
+    Ok(())
+}
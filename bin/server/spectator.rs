@@ -0,0 +1,93 @@
+use std::sync::{Arc, RwLock};
+
+use crate::{guess_game::Game, ReadWrite, ServerData};
+
+/// Answers a single HTTP/1.1 request of a browser and closes the connection.
+///
+/// `GET /` returns the spectator page with the ongoing games and the server's stats, every other request gets an error status.
+///
+/// # Arguments
+///
+/// * `stream: &mut impl ReadWrite` - The stream the request came from.
+/// * `request: &[u8]` - The bytes read while detecting the protocol, starting with the request line.
+/// * `server: Arc<RwLock<ServerData>>` - The server's state, the games shown to spectators are taken from it.
+pub fn serve_spectator(
+    stream: &mut impl ReadWrite,
+    request: &[u8],
+    server: Arc<RwLock<ServerData>>,
+) -> std::io::Result<()> {
+    let request = String::from_utf8_lossy(request);
+    let mut request_line = request.lines().next().unwrap_or_default().split(' ');
+    let method = request_line.next().unwrap_or_default();
+    let target = request_line.next().unwrap_or_default();
+    println!("spectator request {method} {target}");
+
+    let (status, body) = match (method, target) {
+        ("GET", "/") => {
+            let games = games_table(&server.read().unwrap().get_spectator_data());
+            let stats = escape_html(&server.read().unwrap().get_stats().to_string());
+            ("200 OK", format!("<!DOCTYPE html><html><head><title>Server</title></head><body><h1>Server</h1>{games}<h2>Stats</h2><p>{stats}</p></body></html>"))
+        }
+        ("GET", _) => ("404 Not Found", "Not found".to_string()),
+        _ => ("405 Method Not Allowed", "Method not allowed".to_string()),
+    };
+
+    let response = format!(
+        "HTTP/1.1 {status}\r\nContent-Type: text/html; charset=UTF-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    );
+    stream.write_all(response.as_bytes())
+}
+
+/// Renders the ongoing games as an HTML table. The secrets are left out, anyone can open the page.
+fn games_table(games: &[Game]) -> String {
+    let rows: String = games
+        .iter()
+        .map(|game| {
+            format!(
+                "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{:?}</td></tr>",
+                game.get_game_id(),
+                game.get_host_id(),
+                game.get_opponent_id(),
+                game.get_attempts(),
+                escape_html(game.get_last_hint()),
+                escape_html(game.get_last_guess()),
+                game.get_game_state(),
+            )
+        })
+        .collect();
+
+    format!("<table><tr><th>Game</th><th>Host</th><th>Opponent</th><th>Attempts left</th><th>Last hint</th><th>Last guess</th><th>State</th></tr>{rows}</table>")
+}
+
+/// Escapes text sent by the players, so it's shown as it is instead of being read as HTML.
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn games_are_escaped_and_keep_their_secret() {
+        let mut game = Game::new(1, 2, "apple".to_string());
+        game.set_last_hint("<script>alert('red')</script>".to_string());
+
+        let table = games_table(&[game]);
+        assert!(table.contains("&lt;script&gt;alert(&#39;red&#39;)&lt;/script&gt;"));
+        assert!(!table.contains("<script>"));
+        assert!(!table.contains("apple"));
+    }
+}
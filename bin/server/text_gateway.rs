@@ -0,0 +1,335 @@
+use std::net::TcpListener;
+use std::sync::{Arc, RwLock};
+
+use client_server_tcp::text_protocol::{format_response, parse_line};
+use client_server_tcp::{
+    BinaryMessage, ErrorCode, LineFramer, ServerCommandList, ServerMessageResponse,
+};
+
+use crate::peer_auth::{PeerCredentials, PeerLogin};
+use crate::server_commands::{Execute, ServerCommandError};
+use crate::server_process::MAX_REQUESTS_PER_EVENT;
+use crate::{ReadWrite, ServerData};
+
+/// Binds the line-oriented text gateway, so the server can be driven with `nc` or `socat`.
+///
+/// Its connections are served by the reactor like every other one and share the router with them,
+/// so text users and binary users can message and play against each other.
+///
+/// # Arguments
+///
+/// * `address: &str` - The TCP address to listen on.
+pub fn bind_text_gateway(address: &str) -> std::io::Result<mio::net::TcpListener> {
+    println!("Starting text gateway on address {address}");
+    let listener = TcpListener::bind(address).map_err(|err| {
+        std::io::Error::new(err.kind(), format!("text gateway on {address}: {err}"))
+    })?;
+    listener.set_nonblocking(true)?;
+    Ok(mio::net::TcpListener::from_std(listener))
+}
+
+/// Where a text user is, from connecting to being logged in.
+enum TextPhase {
+    /// Waiting for the password.
+    Login,
+    /// Logged in as the user with this ID.
+    Ready(u64),
+}
+
+/// A connection speaking the text protocol.
+///
+/// The first line is the password, after that lines starting with a `/` are commands, e.g. `/GUESS apple`,
+/// and any other line is a chat message. Commands are executed exactly as for binary clients,
+/// replies come back as one line each and events from other users are prefixed with `EVENT`.
+pub struct TextSession {
+    lines: LineFramer,
+    phase: TextPhase,
+}
+
+impl TextSession {
+    /// Starts a text session, a trusted local user is logged in and everyone else is asked for the password.
+    ///
+    /// # Arguments
+    ///
+    /// * `stream` - A mutable reference to an object implementing the ReadWrite trait, typically a network stream.
+    /// * `prefix` - Bytes already read from the stream while detecting its protocol.
+    /// * `peer` - The credentials of the local process on the other end, trusted local users skip the password.
+    /// * `server` - An Arc wrapped RwLock protected ServerData object that contains the server's state.
+    pub fn new(
+        stream: &mut impl ReadWrite,
+        prefix: Vec<u8>,
+        peer: Option<PeerCredentials>,
+        server: Arc<RwLock<ServerData>>,
+    ) -> Result<Self, ServerCommandError> {
+        let mut lines = LineFramer::new();
+        lines.push(&prefix);
+
+        // Trusted local users are known from their credentials and never asked for the password
+        let login = server.read().unwrap().peer_auth.login(peer);
+        let phase = match login {
+            PeerLogin::Trusted(user) => {
+                let local_id = server.write().unwrap().add_local_user(user.clone());
+                println!("logged in local text user {user} as id {local_id}");
+
+                let line = format_response(&ServerMessageResponse::ID(local_id));
+                send_line(stream, &line, server.clone(), local_id);
+                TextPhase::Ready(local_id)
+            }
+            PeerLogin::Refused(peer) => {
+                let line = format_response(&ServerMessageResponse::Error {
+                    code: ErrorCode::AuthFailed,
+                    message: "local user not allowed".to_string(),
+                });
+                send_line(stream, &line, server.clone(), 0);
+                return Err(ServerCommandError::TerminateThread(format!(
+                    "Local user {peer} not allowed"
+                )));
+            }
+            PeerLogin::Password => {
+                send_line(stream, "Enter the password", server.clone(), 0);
+                TextPhase::Login
+            }
+        };
+
+        Ok(Self { lines, phase })
+    }
+
+    /// Gets the ID of the user, once it's logged in.
+    pub fn local_id(&self) -> Option<u64> {
+        match self.phase {
+            TextPhase::Ready(local_id) => Some(local_id),
+            TextPhase::Login => None,
+        }
+    }
+
+    /// Handles the lines that have arrived, up to [`MAX_REQUESTS_PER_EVENT`] of them,
+    /// returning whether it stopped before all of them were handled.
+    ///
+    /// # Arguments
+    ///
+    /// * `stream` - A mutable reference to an object implementing the ReadWrite trait, typically a network stream.
+    /// * `server` - An Arc wrapped RwLock protected ServerData object that contains the server's state.
+    pub fn on_ready(
+        &mut self,
+        stream: &mut impl ReadWrite,
+        server: Arc<RwLock<ServerData>>,
+    ) -> Result<bool, ServerCommandError> {
+        for _ in 0..MAX_REQUESTS_PER_EVENT {
+            let local_id = self.local_id().unwrap_or(0);
+
+            // A message of this user went to a full queue under the block policy, the rest waits until it's queued
+            if server.read().unwrap().router.is_blocked(local_id) {
+                return Ok(false);
+            }
+
+            let line = match read_line(stream, &mut self.lines, server.clone(), local_id) {
+                Ok(Some(line)) => line,
+                // Everything that arrived is handled
+                Ok(None) => return Ok(false),
+                Err(ServerCommandError::ErrorMessage(code, message)) => {
+                    let line = format_response(&ServerMessageResponse::Error { code, message });
+                    send_line(stream, &line, server.clone(), local_id);
+                    continue;
+                }
+                Err(err) => return Err(err),
+            };
+
+            let result = match self.phase {
+                TextPhase::Login => {
+                    let local_id = validate_text_user(stream, &line, server.clone())?;
+                    self.phase = TextPhase::Ready(local_id);
+                    continue;
+                }
+                TextPhase::Ready(local_id) => parse_text_command(&line).and_then(|command| {
+                    command
+                        .map(|command| command.execute(server.clone(), &local_id))
+                        .transpose()
+                }),
+            };
+
+            match result {
+                Ok(Some(value)) => {
+                    send_line(stream, &format_message(value), server.clone(), local_id);
+                }
+                Ok(None) => (),
+                Err(ServerCommandError::ErrorMessage(code, message)) => {
+                    let line = format_response(&ServerMessageResponse::Error { code, message });
+                    send_line(stream, &line, server.clone(), local_id);
+                }
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(true)
+    }
+
+    /// Sends the user a message from another user as an event, it's not a reply to this user's commands.
+    pub fn deliver(
+        &mut self,
+        stream: &mut impl ReadWrite,
+        value: BinaryMessage,
+        server: Arc<RwLock<ServerData>>,
+    ) {
+        if let Some(local_id) = self.local_id() {
+            let line = format!("EVENT {}", format_message(value));
+            send_line(stream, &line, server, local_id);
+        }
+    }
+
+    /// Tells the client why the server is shutting down and logs the user out.
+    pub fn shutdown(&mut self, stream: &mut impl ReadWrite, server: Arc<RwLock<ServerData>>) {
+        let local_id = self.local_id().unwrap_or(0);
+        notify_text_shutdown(stream, server.clone(), local_id);
+
+        if let Some(local_id) = self.local_id() {
+            if let Err(err) = server.write().unwrap().drop_user(local_id) {
+                eprintln!("error removing user: {err}");
+            }
+            println!("closed the connection of text user {local_id}, the server is shutting down");
+        }
+    }
+}
+
+/// Validates the password sent as the first line and assigns the user an id.
+///
+/// # Arguments
+///
+/// * `stream: &mut impl ReadWrite` - The stream the reply is written to.
+/// * `line: &str` - The first line the user sent.
+/// * `server: Arc<RwLock<ServerData>>` - The server's state.
+///
+/// # Returns
+///
+/// * [`u64`] - the id of the validated user.
+fn validate_text_user(
+    stream: &mut impl ReadWrite,
+    line: &str,
+    server: Arc<RwLock<ServerData>>,
+) -> Result<u64, ServerCommandError> {
+    let (code, message) = match parse_line(line) {
+        Ok(ServerCommandList::Message(password)) => {
+            if server.read().unwrap().validate_password(&password) {
+                let local_id = server.write().unwrap().add_user();
+                println!("added text user id {local_id}");
+
+                let line = format_response(&ServerMessageResponse::ID(local_id));
+                send_line(stream, &line, server.clone(), local_id);
+                return Ok(local_id);
+            }
+            (ErrorCode::AuthFailed, "password incorrect".to_string())
+        }
+        _ => (
+            ErrorCode::AuthRequired,
+            "command incorrect, send the password first".to_string(),
+        ),
+    };
+
+    let line = format_response(&ServerMessageResponse::Error {
+        code,
+        message: message.clone(),
+    });
+    send_line(stream, &line, server.clone(), 0);
+    Err(ServerCommandError::TerminateThread(message))
+}
+
+/// Translates a line into a command, empty lines are ignored.
+fn parse_text_command(line: &str) -> Result<Option<ServerCommandList>, ServerCommandError> {
+    if line.trim().is_empty() {
+        return Ok(None);
+    }
+
+    parse_line(line)
+        .map(Some)
+        .map_err(|err| ServerCommandError::ErrorMessage(ErrorCode::from(&err), err.to_string()))
+}
+
+/// Reads the next complete line from the stream.
+///
+/// This function hands out a line already buffered in the [`LineFramer`], otherwise it keeps reading
+/// until a whole line arrives or nothing more has arrived. If the client closes the connection, the user is dropped.
+///
+/// # Arguments
+///
+/// * `stream: &mut impl ReadWrite` - The stream from which data is read.
+/// * `lines: &mut LineFramer` - The connection's line buffer.
+/// * `server: Arc<RwLock<ServerData>>` - The server's state.
+/// * `local_id: u64` - The ID of the user, `0` before the password is validated.
+fn read_line(
+    stream: &mut impl ReadWrite,
+    lines: &mut LineFramer,
+    server: Arc<RwLock<ServerData>>,
+    local_id: u64,
+) -> Result<Option<String>, ServerCommandError> {
+    loop {
+        match lines.next_line() {
+            Ok(Some(line)) => return Ok(Some(line)),
+            Ok(None) => (),
+            Err(err) => {
+                return Err(ServerCommandError::ErrorMessage(
+                    ErrorCode::from(&err),
+                    err.to_string(),
+                ))
+            }
+        }
+
+        match lines.read_from(stream) {
+            Ok(0) => {
+                if let Err(err) = server.write().unwrap().drop_user(local_id) {
+                    eprintln!("error removing user: {err}");
+                }
+                return Err(ServerCommandError::TerminateThread(
+                    "connection closed by peer".to_string(),
+                ));
+            }
+            // Whatever was read is split into lines on the next pass of the loop
+            Ok(_) => (),
+            Err(e) => {
+                if let std::io::ErrorKind::WouldBlock = e.kind() {
+                    return Ok(None);
+                }
+                if let Err(err) = server.write().unwrap().drop_user(local_id) {
+                    eprintln!("error removing user: {err}");
+                }
+                return Err(ServerCommandError::TerminateThread(format!(
+                    "critical error in stream: {e}"
+                )));
+            }
+        }
+    }
+}
+
+/// Renders a message produced by the command executor as a line of text.
+fn format_message(message: BinaryMessage) -> String {
+    format_response(
+        &ServerMessageResponse::try_from(message).unwrap_or(ServerMessageResponse::Unknown),
+    )
+}
+
+/// Sends a text client the shutdown events, one `EVENT` line each, if the server is shutting down.
+fn notify_text_shutdown(
+    stream: &mut impl ReadWrite,
+    server: Arc<RwLock<ServerData>>,
+    local_id: u64,
+) {
+    // The read lock has to be released before sending, a failed write takes the write lock
+    let events = server.read().unwrap().shutdown_events(local_id);
+    for event in events.into_iter().flatten() {
+        let line = format!("EVENT {}", format_response(&event));
+        send_line(stream, &line, server.clone(), local_id);
+    }
+}
+
+/// Writes a single line to the client, dropping the user if the connection is gone.
+fn send_line(
+    stream: &mut impl ReadWrite,
+    line: &str,
+    server: Arc<RwLock<ServerData>>,
+    local_id: u64,
+) {
+    println!("writing a line to text client: {line}");
+
+    if let Err(err) = stream.write_all(format!("{line}\n").as_bytes()) {
+        if let Err(error) = server.write().unwrap().drop_user(local_id) {
+            eprintln!("critical error writing a line: {err} and {error}");
+        }
+    }
+}
@@ -0,0 +1,31 @@
+use std::sync::Arc;
+
+use rustls::pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer};
+use rustls::ServerConfig;
+
+/// Longest time a client may take to finish the TLS handshake.
+pub const TLS_HANDSHAKE_TIMEOUT_MS: u64 = 5000;
+
+/// Loads the certificate chain and the private key of the server from PEM files.
+///
+/// # Arguments
+///
+/// * `cert_path: &str` - The certificate chain, starting with the server's own certificate.
+/// * `key_path: &str` - The private key of the server's certificate.
+pub fn load_tls_config(cert_path: &str, key_path: &str) -> Result<Arc<ServerConfig>, String> {
+    let certificates = CertificateDer::pem_file_iter(cert_path)
+        .and_then(|certificates| certificates.collect::<Result<Vec<_>, _>>())
+        .map_err(|err| format!("error reading certificates from {cert_path}: {err}"))?;
+    if certificates.is_empty() {
+        return Err(format!("no certificates found in {cert_path}"));
+    }
+
+    let key = PrivateKeyDer::from_pem_file(key_path)
+        .map_err(|err| format!("error reading the private key from {key_path}: {err}"))?;
+
+    let config = ServerConfig::builder()
+        .with_no_client_auth()
+        .with_single_cert(certificates, key)
+        .map_err(|err| format!("invalid certificate or private key: {err}"))?;
+    Ok(Arc::new(config))
+}
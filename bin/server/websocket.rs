@@ -0,0 +1,303 @@
+use std::io::{Read, Write};
+
+use client_server_tcp::binary_message::{DEFAULT_MAX_FRAME_SIZE, READ_BUFFER_SIZE};
+
+use crate::protocol_sniffer::{http_header, split_http_head};
+use crate::ReadWrite;
+
+/// Fixed key appended to the client's key when computing `Sec-WebSocket-Accept`, from RFC 6455.
+const WEBSOCKET_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+const OPCODE_CONTINUATION: u8 = 0x0;
+const OPCODE_TEXT: u8 = 0x1;
+const OPCODE_BINARY: u8 = 0x2;
+const OPCODE_CLOSE: u8 = 0x8;
+const OPCODE_PING: u8 = 0x9;
+const OPCODE_PONG: u8 = 0xA;
+
+/// Answers the WebSocket upgrade request of a browser.
+///
+/// # Arguments
+///
+/// * `stream: &mut impl ReadWrite` - The stream the upgrade request came from.
+/// * `request: &[u8]` - The bytes read while detecting the protocol, starting with the request head.
+///
+/// # Returns
+///
+/// * [`Vec<u8>`] - Bytes that followed the request head, they already belong to the first WebSocket frames.
+pub fn accept_websocket(stream: &mut impl ReadWrite, request: &[u8]) -> std::io::Result<Vec<u8>> {
+    let invalid =
+        |message: &str| std::io::Error::new(std::io::ErrorKind::InvalidData, message.to_string());
+
+    let (head, rest) = split_http_head(request).ok_or(invalid("incomplete upgrade request"))?;
+
+    let Some(key) = http_header(head, "sec-websocket-key") else {
+        stream.write_all(
+            b"HTTP/1.1 400 Bad Request\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+        )?;
+        return Err(invalid("upgrade request without a Sec-WebSocket-Key"));
+    };
+
+    let accept = base64_encode(&sha1(format!("{key}{WEBSOCKET_GUID}").as_bytes()));
+    let response = format!("HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {accept}\r\n\r\n");
+    stream.write_all(response.as_bytes())?;
+
+    Ok(rest.to_vec())
+}
+
+/// A stream speaking WebSocket frames on the outside and plain lines of text on the inside.
+///
+/// Every text or binary message received is read as one line, and every line written is sent as one text message,
+/// so the text protocol handler serves WebSocket clients unchanged. Pings are answered and a close frame ends the stream.
+pub struct WebSocketStream<S: ReadWrite> {
+    inner: S,
+    // Raw bytes that are not a complete frame yet
+    raw: Vec<u8>,
+    // Payload of a fragmented message, waiting for its last fragment
+    message: Vec<u8>,
+    // Lines decoded from complete messages, not yet read
+    decoded: Vec<u8>,
+    closed: bool,
+}
+
+impl<S: ReadWrite> WebSocketStream<S> {
+    /// Creates a new [`WebSocketStream`] over an upgraded connection, starting with bytes that were already read from it.
+    pub fn new(inner: S, prefix: Vec<u8>) -> Self {
+        Self {
+            inner,
+            raw: prefix,
+            message: Vec::new(),
+            decoded: Vec::new(),
+            closed: false,
+        }
+    }
+
+    /// Gets the stream under the WebSocket frames.
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.inner
+    }
+
+    /// Decodes one frame from the raw bytes, returning false if the frame isn't complete yet.
+    fn next_frame(&mut self) -> std::io::Result<bool> {
+        if self.raw.len() < 2 {
+            return Ok(false);
+        }
+
+        let fin = self.raw[0] & 0x80 != 0;
+        let opcode = self.raw[0] & 0x0F;
+        let masked = self.raw[1] & 0x80 != 0;
+
+        let (length, mut offset) = match self.raw[1] & 0x7F {
+            126 if self.raw.len() >= 4 => {
+                (u16::from_be_bytes([self.raw[2], self.raw[3]]) as u64, 4)
+            }
+            127 if self.raw.len() >= 10 => {
+                let mut length = [0u8; 8];
+                length.copy_from_slice(&self.raw[2..10]);
+                (u64::from_be_bytes(length), 10)
+            }
+            126 | 127 => return Ok(false),
+            length => (length as u64, 2),
+        };
+
+        if length > DEFAULT_MAX_FRAME_SIZE as u64
+            || self.message.len() as u64 + length > DEFAULT_MAX_FRAME_SIZE as u64
+        {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("websocket message of {length} bytes is too large"),
+            ));
+        }
+        let length = length as usize;
+
+        let mask = if masked {
+            if self.raw.len() < offset + 4 {
+                return Ok(false);
+            }
+            offset += 4;
+            Some([
+                self.raw[offset - 4],
+                self.raw[offset - 3],
+                self.raw[offset - 2],
+                self.raw[offset - 1],
+            ])
+        } else {
+            None
+        };
+
+        if self.raw.len() < offset + length {
+            return Ok(false);
+        }
+
+        let mut payload: Vec<u8> = self.raw.drain(..offset + length).skip(offset).collect();
+        if let Some(mask) = mask {
+            payload
+                .iter_mut()
+                .enumerate()
+                .for_each(|(index, byte)| *byte ^= mask[index % 4]);
+        }
+
+        match opcode {
+            OPCODE_CONTINUATION | OPCODE_TEXT | OPCODE_BINARY => {
+                self.message.extend_from_slice(&payload);
+                if fin {
+                    self.decoded.append(&mut self.message);
+                    self.decoded.push(b'\n');
+                }
+            }
+            OPCODE_CLOSE => {
+                // Echo the close frame, as the protocol asks, and stop reading
+                self.write_frame(OPCODE_CLOSE, &payload)?;
+                self.closed = true;
+            }
+            OPCODE_PING => self.write_frame(OPCODE_PONG, &payload)?,
+            OPCODE_PONG => (),
+            _ => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!("unknown websocket opcode {opcode}"),
+                ))
+            }
+        }
+        Ok(true)
+    }
+
+    /// Writes a single unmasked frame, as sent from the server to the client.
+    fn write_frame(&mut self, opcode: u8, payload: &[u8]) -> std::io::Result<()> {
+        let mut frame = vec![0x80 | opcode];
+        match payload.len() {
+            length if length < 126 => frame.push(length as u8),
+            length if length <= u16::MAX as usize => {
+                frame.push(126);
+                frame.extend((length as u16).to_be_bytes());
+            }
+            length => {
+                frame.push(127);
+                frame.extend((length as u64).to_be_bytes());
+            }
+        }
+        frame.extend_from_slice(payload);
+        self.inner.write_all(&frame)
+    }
+}
+
+impl<S: ReadWrite> Read for WebSocketStream<S> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        loop {
+            if !self.decoded.is_empty() {
+                let size = buf.len().min(self.decoded.len());
+                buf[..size].copy_from_slice(&self.decoded[..size]);
+                self.decoded.drain(..size);
+                return Ok(size);
+            }
+
+            if self.closed {
+                return Ok(0);
+            }
+
+            if self.next_frame()? {
+                continue;
+            }
+
+            // The inner stream running out of data is passed on, the caller reads again once more arrives
+            let mut temp_buffer = vec![0u8; READ_BUFFER_SIZE];
+            let size = self.inner.read(&mut temp_buffer)?;
+            if size == 0 {
+                return Ok(0);
+            }
+            self.raw.extend_from_slice(&temp_buffer[..size]);
+        }
+    }
+}
+
+impl<S: ReadWrite> Write for WebSocketStream<S> {
+    /// Sends the buffer as one text message, without its line ending.
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let text = buf.strip_suffix(b"\n").unwrap_or(buf);
+        self.write_frame(OPCODE_TEXT, text)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Computes the SHA-1 digest of the data, only used for the WebSocket handshake.
+fn sha1(data: &[u8]) -> [u8; 20] {
+    let mut state: [u32; 5] = [0x67452301, 0xEFCDAB89, 0x98BADCFE, 0x10325476, 0xC3D2E1F0];
+
+    let mut message = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend((data.len() as u64 * 8).to_be_bytes());
+
+    for block in message.chunks(64) {
+        let mut words = [0u32; 80];
+        for (word, bytes) in words.iter_mut().zip(block.chunks(4)) {
+            *word = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        }
+        for index in 16..80 {
+            words[index] =
+                (words[index - 3] ^ words[index - 8] ^ words[index - 14] ^ words[index - 16])
+                    .rotate_left(1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e] = state;
+        for (index, word) in words.iter().enumerate() {
+            let (f, k) = match index {
+                0..=19 => ((b & c) | (!b & d), 0x5A827999),
+                20..=39 => (b ^ c ^ d, 0x6ED9EBA1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8F1BBCDC),
+                _ => (b ^ c ^ d, 0xCA62C1D6),
+            };
+            let temp = a
+                .rotate_left(5)
+                .wrapping_add(f)
+                .wrapping_add(e)
+                .wrapping_add(k)
+                .wrapping_add(*word);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = temp;
+        }
+
+        for (value, add) in state.iter_mut().zip([a, b, c, d, e]) {
+            *value = value.wrapping_add(add);
+        }
+    }
+
+    let mut digest = [0u8; 20];
+    for (bytes, value) in digest.chunks_mut(4).zip(state) {
+        bytes.copy_from_slice(&value.to_be_bytes());
+    }
+    digest
+}
+
+/// Encodes the data as standard padded base64.
+fn base64_encode(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut encoded = String::new();
+    for chunk in data.chunks(3) {
+        let bytes = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let value = u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]);
+
+        for index in 0..4 {
+            if index <= chunk.len() {
+                encoded.push(ALPHABET[(value >> (18 - index * 6)) as usize & 0x3F] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
@@ -0,0 +1,31 @@
+#![no_main]
+
+use client_server_tcp::{BinaryMessage, Framer};
+use libfuzzer_sys::fuzz_target;
+
+// Feeds arbitrary bytes through the decoder and the framer, neither of them may panic.
+// Every frame that decodes must also survive a serialize/deserialize round-trip unchanged.
+fuzz_target!(|data: &[u8]| {
+    if let Ok(message) = BinaryMessage::deserialize(data) {
+        assert_eq!(BinaryMessage::deserialize(&message.serialize()), Ok(message));
+    }
+
+    // Split the input in two reads, as a stream would, and take out every frame
+    let mut framer = Framer::new();
+    let (first, second) = data.split_at(data.len() / 2);
+    for chunk in [first, second] {
+        framer.push(chunk);
+        loop {
+            match framer.next_frame() {
+                Ok(Some(message)) => {
+                    assert_eq!(
+                        BinaryMessage::deserialize(&message.serialize()),
+                        Ok(message)
+                    );
+                }
+                Ok(None) => break,
+                Err(_) => continue,
+            }
+        }
+    }
+});
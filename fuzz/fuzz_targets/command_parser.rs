@@ -0,0 +1,23 @@
+#![no_main]
+
+use client_server_tcp::{BinaryMessage, ServerCommandList, ServerMessageResponse};
+use libfuzzer_sys::fuzz_target;
+
+// Parses arbitrary frames as client requests and server events, neither parser may panic.
+// Every command that parses must come back the same after being encoded again.
+fuzz_target!(|data: &[u8]| {
+    let Ok(message) = BinaryMessage::deserialize(data) else {
+        return;
+    };
+
+    if let Ok(command) = ServerCommandList::try_from(message.clone()) {
+        if command != ServerCommandList::Unknown {
+            assert_eq!(
+                ServerCommandList::try_from(BinaryMessage::from(command.clone())),
+                Ok(command)
+            );
+        }
+    }
+
+    let _ = ServerMessageResponse::try_from(message);
+});
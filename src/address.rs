@@ -0,0 +1,87 @@
+use std::os::linux::net::SocketAddrExt;
+use std::os::unix::net::{SocketAddr, UnixStream};
+use std::path::PathBuf;
+
+/// Host used when a TCP endpoint only names a port.
+pub const DEFAULT_HOST: &str = "127.0.0.1";
+
+/// Directory of the UNIX sockets given only by their name.
+pub const UNIX_SOCKET_DIR: &str = "/tmp";
+
+/// Turns a TCP endpoint given on the command line into an address to connect to or listen on.
+///
+/// An endpoint is either a bare port, which means [`DEFAULT_HOST`], or a full address with a host name,
+/// an IPv4 address or a bracketed IPv6 address, e.g. `8080`, `example.com:8080`, `0.0.0.0:8080` or `[::1]:8080`.
+pub fn tcp_address(endpoint: &str) -> String {
+    match endpoint.parse::<u16>() {
+        Ok(port) => format!("{DEFAULT_HOST}:{port}"),
+        Err(_) => endpoint.to_string(),
+    }
+}
+
+/// Splits a comma separated list of TCP endpoints into their addresses, see [`tcp_address`].
+pub fn tcp_addresses(endpoints: &str) -> Vec<String> {
+    endpoints
+        .split(',')
+        .map(str::trim)
+        .filter(|endpoint| !endpoint.is_empty())
+        .map(tcp_address)
+        .collect()
+}
+
+/// Gets the host of a TCP endpoint without the brackets of an IPv6 address, or `None` for a bare port.
+pub fn host(endpoint: &str) -> Option<&str> {
+    if endpoint.parse::<u16>().is_ok() {
+        return None;
+    }
+
+    let host = endpoint
+        .rsplit_once(':')
+        .map_or(endpoint, |(host, _port)| host);
+    Some(host.trim_start_matches('[').trim_end_matches(']'))
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// Where a UNIX socket lives.
+pub enum UnixAddress {
+    /// A socket file on the file system.
+    Path(PathBuf),
+    /// A Linux abstract-namespace socket, it has no file and disappears together with its listener.
+    Abstract(String),
+}
+
+impl UnixAddress {
+    /// Reads a UNIX endpoint given on the command line.
+    ///
+    /// A name starting with `@` is an abstract socket, e.g. `@luxo_server`, and a name containing a `/` is a path.
+    /// Any other name is a socket file in [`UNIX_SOCKET_DIR`], e.g. `luxo_server` is `/tmp/luxo_server`.
+    pub fn parse(endpoint: &str) -> Self {
+        match endpoint.strip_prefix('@') {
+            Some(name) => UnixAddress::Abstract(name.to_string()),
+            None if endpoint.contains('/') => UnixAddress::Path(PathBuf::from(endpoint)),
+            None => UnixAddress::Path(PathBuf::from(UNIX_SOCKET_DIR).join(endpoint)),
+        }
+    }
+
+    /// Gets the socket address to bind or connect to.
+    pub fn socket_addr(&self) -> std::io::Result<SocketAddr> {
+        match self {
+            UnixAddress::Path(path) => SocketAddr::from_pathname(path),
+            UnixAddress::Abstract(name) => SocketAddr::from_abstract_name(name),
+        }
+    }
+
+    /// Connects to the socket.
+    pub fn connect(&self) -> std::io::Result<UnixStream> {
+        UnixStream::connect_addr(&self.socket_addr()?)
+    }
+}
+
+impl std::fmt::Display for UnixAddress {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            UnixAddress::Path(path) => write!(f, "{}", path.display()),
+            UnixAddress::Abstract(name) => write!(f, "@{name}"),
+        }
+    }
+}
@@ -0,0 +1,1039 @@
+use std::{
+    io::{Read, Write},
+    mem::size_of,
+};
+
+use flate2::{read::DeflateDecoder, write::DeflateEncoder, Compression};
+
+use crate::{
+    checksum::crc32, chunking::ChunkAssembler, error_code::ErrorCode, handshake::Capabilities,
+    protocol_spec::ERROR,
+};
+
+/// Size of the fixed part of the frame header, 4 bytes of length, 1 byte of message type and 1 byte of flags.
+///
+/// The full frame is laid out as
+/// `| length u32 | type u8 | flags u8 | request id u32 | message id u32 | chunk index u32 | payload | crc32 u32 |`,
+/// where the request id is only present if [`FLAG_REQUEST_ID`] is set, the message id and chunk index only if [`FLAG_CHUNKED`] is set
+/// and the checksum only if [`FLAG_CHECKSUM`] is set. The length counts only the payload.
+pub const HEADER_SIZE: usize = 6;
+
+/// The frame carries a request id, either assigned by the client or echoed back by the server.
+pub const FLAG_REQUEST_ID: u8 = 1 << 0;
+
+/// The frame is an unsolicited event pushed by the server, not a reply to a request.
+pub const FLAG_PUSH: u8 = 1 << 1;
+
+/// The frame ends with a CRC32 of all of its preceding bytes, header included.
+pub const FLAG_CHECKSUM: u8 = 1 << 2;
+
+/// Size of the checksum trailer.
+pub const CHECKSUM_SIZE: usize = size_of::<u32>();
+
+/// The payload is compressed with raw deflate, the length in the header is the compressed length.
+pub const FLAG_COMPRESSED: u8 = 1 << 3;
+
+/// Payloads smaller than this are never compressed, it wouldn't pay off.
+pub const COMPRESSION_THRESHOLD: usize = 1024;
+
+/// The frame is one chunk of a larger message, its message id and chunk index follow the request id.
+pub const FLAG_CHUNKED: u8 = 1 << 4;
+
+/// The frame is the last chunk of its message.
+pub const FLAG_LAST_CHUNK: u8 = 1 << 5;
+
+/// Size of the chunk header, 4 bytes of message id and 4 bytes of chunk index.
+pub const CHUNK_HEADER_SIZE: usize = 8;
+
+/// Payloads larger than this are split into chunks, once both sides agreed on `CHUNKING`.
+pub const CHUNK_SIZE: usize = u16::MAX as usize;
+
+/// Default limit for a message reassembled from chunks.
+pub const DEFAULT_MAX_MESSAGE_SIZE: usize = 64 * 1024 * 1024;
+
+/// Largest payload a peer has to accept when `LARGE_FRAMES` wasn't negotiated.
+pub const DEFAULT_MAX_FRAME_SIZE: usize = u16::MAX as usize;
+
+/// Size of the chunk read from the stream at once.
+pub const READ_BUFFER_SIZE: usize = 64 * 1024;
+
+/// Default limit for payloads once both sides agreed on `LARGE_FRAMES`.
+pub const LARGE_MAX_FRAME_SIZE: usize = 16 * 1024 * 1024;
+
+#[derive(Debug, Clone, PartialEq)]
+/// Errors raised while decoding a [`BinaryMessage`] or the command it carries.
+///
+/// Every malformed input ends up as one of these, the decoder never panics on bytes received from a peer.
+pub enum DecodeError {
+    /// There are fewer bytes than the frame header needs.
+    Truncated { needed: usize, available: usize },
+    /// The length in the header doesn't match the size of the frame.
+    LengthMismatch { expected: usize, actual: usize },
+    /// The payload is larger than the maximum frame size of the connection.
+    TooLarge { length: usize, max: usize },
+    /// The message type byte is not a known [`MessageType`].
+    UnknownMessageType(u8),
+    /// The header has flags set that this build doesn't know about.
+    UnknownFlags(u8),
+    /// The compressed payload isn't valid deflate data.
+    InvalidCompression,
+    /// A chunk doesn't fit into the message it belongs to.
+    InvalidChunk { message_id: u32, reason: String },
+    /// The checksum in the trailer doesn't match the frame, it was corrupted on the way.
+    ChecksumMismatch { expected: u32, actual: u32 },
+    /// The connection negotiated checksums, but the frame has none.
+    MissingChecksum,
+    /// The frame is a chunk, but the connection didn't negotiate chunking.
+    UnexpectedChunk,
+    /// Text that should be UTF-8 isn't.
+    InvalidUtf8,
+    /// A line of the JSON codec isn't a valid message object.
+    InvalidJson(String),
+    /// The arguments of a command are missing or malformed.
+    InvalidArguments { command: String, reason: String },
+}
+
+impl std::fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            DecodeError::Truncated { needed, available } => write!(
+                f,
+                "frame header needs {needed} bytes, only {available} available"
+            ),
+            DecodeError::LengthMismatch { expected, actual } => write!(
+                f,
+                "frame length says {expected} bytes, but the frame has {actual} bytes"
+            ),
+            DecodeError::TooLarge { length, max } => write!(
+                f,
+                "frame of {length} bytes exceeds the maximum frame size of {max} bytes"
+            ),
+            DecodeError::UnknownMessageType(value) => write!(f, "unknown message type {value}"),
+            DecodeError::UnknownFlags(flags) => write!(f, "unknown frame flags {flags:#010b}"),
+            DecodeError::ChecksumMismatch { expected, actual } => write!(
+                f,
+                "frame checksum {expected:#010x} doesn't match the computed checksum {actual:#010x}"
+            ),
+            DecodeError::MissingChecksum => write!(f, "frame has no checksum"),
+            DecodeError::UnexpectedChunk => write!(f, "frame is a chunk, but chunking is off"),
+            DecodeError::InvalidCompression => write!(f, "frame payload can't be decompressed"),
+            DecodeError::InvalidChunk { message_id, reason } => {
+                write!(f, "invalid chunk of message {message_id}: {reason}")
+            }
+            DecodeError::InvalidUtf8 => write!(f, "text is not valid UTF-8"),
+            DecodeError::InvalidJson(reason) => write!(f, "invalid JSON message: {reason}"),
+            DecodeError::InvalidArguments { command, reason } => {
+                write!(f, "invalid arguments for {command}: {reason}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+impl From<std::string::FromUtf8Error> for DecodeError {
+    fn from(_: std::string::FromUtf8Error) -> Self {
+        DecodeError::InvalidUtf8
+    }
+}
+
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// Type of message to send
+///
+/// An `Error` frame carries a 2 byte big-endian [`ErrorCode`] followed by a UTF-8 message.
+pub enum MessageType {
+    Unknown,
+    Command,
+    Message,
+    Error,
+}
+
+impl MessageType {
+    /// Gets the lowercase name of the type, used by the JSON codec and the protocol description.
+    pub fn name(self) -> &'static str {
+        match self {
+            MessageType::Unknown => "unknown",
+            MessageType::Command => "command",
+            MessageType::Message => "message",
+            MessageType::Error => "error",
+        }
+    }
+}
+
+impl TryFrom<u8> for MessageType {
+    type Error = DecodeError;
+
+    fn try_from(value: u8) -> Result<Self, DecodeError> {
+        match value {
+            0 => Ok(MessageType::Unknown),
+            1 => Ok(MessageType::Command),
+            2 => Ok(MessageType::Message),
+            3 => Ok(MessageType::Error),
+            _ => Err(DecodeError::UnknownMessageType(value)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// Position of a frame within a message that was split into chunks.
+pub struct ChunkInfo {
+    pub message_id: u32,
+    pub index: u32,
+    pub last: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// Message struct to communicate between server and client
+pub struct BinaryMessage {
+    length: u32,
+    message_type: MessageType,
+    request_id: Option<u32>,
+    push: bool,
+    chunk: Option<ChunkInfo>,
+    message: Vec<u8>,
+}
+
+impl Default for BinaryMessage {
+    fn default() -> Self {
+        Self {
+            length: Default::default(),
+            message_type: MessageType::Unknown,
+            request_id: None,
+            push: false,
+            chunk: None,
+            message: Default::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+/// Optional encodings applied to a frame when it's serialized, as negotiated for the connection.
+pub struct FrameOptions {
+    /// Append a CRC32 trailer.
+    pub checksum: bool,
+    /// Compress the payload, if it's at least [`COMPRESSION_THRESHOLD`] bytes and gets smaller.
+    pub compress: bool,
+}
+
+/// Compresses the payload with raw deflate.
+fn deflate(payload: &[u8]) -> Vec<u8> {
+    let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+    // Writing into a Vec can't fail
+    encoder.write_all(payload).unwrap();
+    encoder.finish().unwrap()
+}
+
+/// Decompresses a raw deflate payload, refusing to inflate it past `max_size` bytes.
+fn inflate(payload: &[u8], max_size: usize) -> Result<Vec<u8>, DecodeError> {
+    let mut decompressed = Vec::new();
+    DeflateDecoder::new(payload)
+        .take(max_size as u64 + 1)
+        .read_to_end(&mut decompressed)
+        .map_err(|_| DecodeError::InvalidCompression)?;
+
+    // Reading one byte past the limit is enough to know the payload is too large, without inflating all of it
+    if decompressed.len() > max_size {
+        return Err(DecodeError::TooLarge {
+            length: decompressed.len(),
+            max: max_size,
+        });
+    }
+    Ok(decompressed)
+}
+
+/// Returns the size of the whole header for a frame with the given flags.
+fn header_size(flags: u8) -> usize {
+    let mut size = HEADER_SIZE;
+    if flags & FLAG_REQUEST_ID != 0 {
+        size += size_of::<u32>();
+    }
+    if flags & FLAG_CHUNKED != 0 {
+        size += CHUNK_HEADER_SIZE;
+    }
+    size
+}
+
+/// Reads a big-endian u32 at the offset, the caller checks that the bytes are there.
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes([
+        bytes[offset],
+        bytes[offset + 1],
+        bytes[offset + 2],
+        bytes[offset + 3],
+    ])
+}
+
+/// Returns the size of a whole frame with the given flags and payload length, trailer included.
+fn frame_size(flags: u8, length: usize) -> usize {
+    if flags & FLAG_CHECKSUM != 0 {
+        header_size(flags) + length + CHECKSUM_SIZE
+    } else {
+        header_size(flags) + length
+    }
+}
+
+impl BinaryMessage {
+    /// Creates a new [`BinaryMessage`] of any type from its raw payload.
+    pub fn new(message_type: MessageType, message: Vec<u8>) -> Self {
+        Self {
+            length: message.len() as u32,
+            message_type,
+            message,
+            ..Default::default()
+        }
+    }
+
+    /// Creates a new [`BinaryMessage`] message
+    pub fn new_message(text: String) -> Self {
+        Self {
+            length: text.len() as u32,
+            message_type: MessageType::Message,
+            message: text.into_bytes(),
+            ..Default::default()
+        }
+    }
+
+    /// Creates a new [`BinaryMessage`] command.
+    pub fn new_command(text: String) -> Self {
+        Self {
+            length: text.len() as u32,
+            message_type: MessageType::Command,
+            message: text.into_bytes(),
+            ..Default::default()
+        }
+    }
+
+    /// Creates a new [`BinaryMessage`] command with binary arguments, separated from the command by a space.
+    pub fn new_binary_command(command: &str, arguments: &[u8]) -> Self {
+        let mut message = command.as_bytes().to_vec();
+        message.push(b' ');
+        message.extend_from_slice(arguments);
+
+        Self {
+            length: message.len() as u32,
+            message_type: MessageType::Command,
+            message,
+            ..Default::default()
+        }
+    }
+
+    /// Creates a new [`BinaryMessage`] error frame with an error code and a human-readable message.
+    pub fn new_error(code: ErrorCode, text: String) -> Self {
+        let mut message = code.code().to_be_bytes().to_vec();
+        message.extend_from_slice(text.as_bytes());
+
+        Self {
+            length: message.len() as u32,
+            message_type: MessageType::Error,
+            message,
+            ..Default::default()
+        }
+    }
+
+    /// Turns an error frame into the plain `ERROR` command understood by peers without `TYPED_ERRORS`, dropping its code.
+    pub(crate) fn untyped_error(&self) -> Self {
+        let text = self.message.get(2..).unwrap_or_default();
+        let legacy =
+            BinaryMessage::new_binary_command(ERROR, text).with_request_id(self.request_id);
+        Self {
+            push: self.push,
+            ..legacy
+        }
+    }
+
+    /// Tags the message with a request id, used to match a reply to the request it answers.
+    pub fn with_request_id(mut self, request_id: Option<u32>) -> Self {
+        self.request_id = request_id;
+        self
+    }
+
+    /// Marks the message as an unsolicited event, not a reply to any request.
+    pub fn into_push(mut self) -> Self {
+        self.push = true;
+        self.request_id = None;
+        self
+    }
+
+    /// Gets the request id of the message, if it has one.
+    pub fn get_request_id(&self) -> Option<u32> {
+        self.request_id
+    }
+
+    /// Returns true if the message is an unsolicited event pushed by the server.
+    pub fn is_push(&self) -> bool {
+        self.push
+    }
+
+    /// Gets the position of the frame within its message, if the message was split into chunks.
+    pub fn get_chunk(&self) -> Option<ChunkInfo> {
+        self.chunk
+    }
+
+    /// Splits the message into chunks of at most `chunk_size` bytes of payload, all tagged with the message id.
+    ///
+    /// Every chunk keeps the type, request id and push flag of the message, so it can be routed on its own.
+    pub fn to_chunks(&self, message_id: u32, chunk_size: usize) -> Vec<BinaryMessage> {
+        let count = self.message.len().div_ceil(chunk_size.max(1)).max(1);
+
+        (0..count)
+            .map(|index| {
+                let start = (index * chunk_size).min(self.message.len());
+                let end = (start + chunk_size).min(self.message.len());
+                Self {
+                    length: (end - start) as u32,
+                    message_type: self.message_type,
+                    request_id: self.request_id,
+                    push: self.push,
+                    chunk: Some(ChunkInfo {
+                        message_id,
+                        index: index as u32,
+                        last: index + 1 == count,
+                    }),
+                    message: self.message[start..end].to_vec(),
+                }
+            })
+            .collect()
+    }
+
+    /// Creates the message that was reassembled from its chunks, taking the header of its last chunk.
+    pub(crate) fn reassembled(self, message: Vec<u8>) -> Self {
+        Self {
+            length: message.len() as u32,
+            chunk: None,
+            message,
+            ..self
+        }
+    }
+
+    /// Geths the type of the message
+    pub fn get_type(&self) -> MessageType {
+        self.message_type
+    }
+
+    /// Geths the message
+    pub fn get_message(&self) -> &Vec<u8> {
+        &self.message
+    }
+
+    /// Serializes the data into bytes
+    pub fn serialize(&self) -> Vec<u8> {
+        self.serialize_with(FrameOptions::default())
+    }
+
+    /// Serializes the data into bytes, applying the optional encodings, e.g. a CRC32 trailer so the receiver can detect corruption.
+    pub fn serialize_with(&self, options: FrameOptions) -> Vec<u8> {
+        let mut bytes = Vec::default();
+
+        // Compression is only kept if it actually made the payload smaller
+        let compressed = match options.compress && self.message.len() >= COMPRESSION_THRESHOLD {
+            true => Some(deflate(&self.message)).filter(|data| data.len() < self.message.len()),
+            false => None,
+        };
+
+        let mut flags = 0;
+        if self.request_id.is_some() {
+            flags |= FLAG_REQUEST_ID;
+        }
+        if self.push {
+            flags |= FLAG_PUSH;
+        }
+        if options.checksum {
+            flags |= FLAG_CHECKSUM;
+        }
+        if compressed.is_some() {
+            flags |= FLAG_COMPRESSED;
+        }
+        if let Some(chunk) = self.chunk {
+            flags |= FLAG_CHUNKED;
+            if chunk.last {
+                flags |= FLAG_LAST_CHUNK;
+            }
+        }
+
+        let payload = compressed.as_ref().unwrap_or(&self.message);
+
+        bytes.extend((payload.len() as u32).to_be_bytes());
+        bytes.push(self.message_type as u8);
+        bytes.push(flags);
+        if let Some(request_id) = self.request_id {
+            bytes.extend(request_id.to_be_bytes());
+        }
+        if let Some(chunk) = self.chunk {
+            bytes.extend(chunk.message_id.to_be_bytes());
+            bytes.extend(chunk.index.to_be_bytes());
+        }
+        bytes.extend_from_slice(payload);
+        if options.checksum {
+            bytes.extend(crc32(&bytes).to_be_bytes());
+        }
+        bytes
+    }
+
+    /// Deserializes the bytes of exactly one frame into a [`BinaryMessage`].
+    ///
+    /// Malformed or truncated input is reported as a [`DecodeError`], never sliced past its end.
+    /// Compressed payloads are inflated up to [`LARGE_MAX_FRAME_SIZE`] bytes.
+    pub fn deserialize(message: &[u8]) -> Result<Self, DecodeError> {
+        Self::deserialize_with_limit(message, LARGE_MAX_FRAME_SIZE)
+    }
+
+    /// Deserializes the bytes of exactly one frame, refusing to inflate a compressed payload past `max_size` bytes.
+    pub fn deserialize_with_limit(message: &[u8], max_size: usize) -> Result<Self, DecodeError> {
+        if message.len() < HEADER_SIZE {
+            return Err(DecodeError::Truncated {
+                needed: HEADER_SIZE,
+                available: message.len(),
+            });
+        }
+
+        // Length is 4 bytes
+        let length = u32::from_be_bytes([message[0], message[1], message[2], message[3]]);
+
+        // Match the message type
+        let message_type = MessageType::try_from(message[4])?;
+
+        // The flags say which optional header fields follow
+        let flags = message[5];
+        let known_flags = FLAG_REQUEST_ID
+            | FLAG_PUSH
+            | FLAG_CHECKSUM
+            | FLAG_COMPRESSED
+            | FLAG_CHUNKED
+            | FLAG_LAST_CHUNK;
+        // Only a chunk can be the last chunk
+        if flags & !known_flags != 0 || (flags & FLAG_LAST_CHUNK != 0 && flags & FLAG_CHUNKED == 0)
+        {
+            return Err(DecodeError::UnknownFlags(flags));
+        }
+
+        let header_size = header_size(flags);
+        let trailer_size = frame_size(flags, 0) - header_size;
+        if message.len() < header_size + trailer_size {
+            return Err(DecodeError::Truncated {
+                needed: header_size + trailer_size,
+                available: message.len(),
+            });
+        }
+
+        // The checksum covers everything before it, so it's verified before anything else is trusted
+        let (message, trailer) = message.split_at(message.len() - trailer_size);
+        if let Ok(trailer) = <[u8; CHECKSUM_SIZE]>::try_from(trailer) {
+            let expected = u32::from_be_bytes(trailer);
+            let actual = crc32(message);
+            if expected != actual {
+                return Err(DecodeError::ChecksumMismatch { expected, actual });
+            }
+        }
+
+        // The optional header fields follow each other in the order of their flags
+        let mut offset = HEADER_SIZE;
+        let request_id = if flags & FLAG_REQUEST_ID != 0 {
+            offset += size_of::<u32>();
+            Some(read_u32(message, offset - size_of::<u32>()))
+        } else {
+            None
+        };
+
+        let chunk = if flags & FLAG_CHUNKED != 0 {
+            Some(ChunkInfo {
+                message_id: read_u32(message, offset),
+                index: read_u32(message, offset + size_of::<u32>()),
+                last: flags & FLAG_LAST_CHUNK != 0,
+            })
+        } else {
+            None
+        };
+
+        // The message is right after the header up to the length of the message, to prevent an exploit
+        let payload = &message[header_size..];
+        if payload.len() != length as usize {
+            return Err(DecodeError::LengthMismatch {
+                expected: length as usize,
+                actual: payload.len(),
+            });
+        }
+
+        let payload = if flags & FLAG_COMPRESSED != 0 {
+            inflate(payload, max_size)?
+        } else {
+            payload.to_vec()
+        };
+
+        Ok(Self {
+            length: payload.len() as u32,
+            message_type,
+            request_id,
+            push: flags & FLAG_PUSH != 0,
+            chunk,
+            message: payload,
+        })
+    }
+
+    /// Function to split the message into a command and arguments
+    pub fn split(&self) -> Result<(String, Vec<u8>), std::string::FromUtf8Error> {
+        let space_index = self.message.iter().position(|&x| x == 32);
+
+        match space_index {
+            Some(space_index) => {
+                let command = String::from_utf8(self.message[0..space_index].to_vec())?;
+                // The arguments are everything after the space, which may be nothing at all
+                let arguments = self.message[(space_index + 1)..].to_vec();
+                Ok((command, arguments))
+            }
+            None => {
+                let command = String::from_utf8(self.message.clone())?;
+                Ok((command, vec![]))
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+/// Per-connection buffer that reassembles [`BinaryMessage`] frames from a byte stream.
+///
+/// A single read can return only a part of a frame, or several frames at once,
+/// so the bytes are kept here until the length header says that a whole frame has arrived.
+/// The framer also enforces the connection's maximum frame size in both directions.
+pub struct Framer {
+    buffer: Vec<u8>,
+    max_frame_size: usize,
+    capabilities: Capabilities,
+    assembler: ChunkAssembler,
+    next_message_id: u32,
+    // Bytes of a rejected frame that still have to be thrown away
+    skip: usize,
+}
+
+impl Default for Framer {
+    fn default() -> Self {
+        Self {
+            buffer: Vec::default(),
+            max_frame_size: DEFAULT_MAX_FRAME_SIZE,
+            capabilities: Capabilities::NONE,
+            assembler: ChunkAssembler::new(DEFAULT_MAX_MESSAGE_SIZE),
+            next_message_id: 0,
+            skip: 0,
+        }
+    }
+}
+
+impl Framer {
+    /// Creates a new, empty [`Framer`] accepting frames up to [`DEFAULT_MAX_FRAME_SIZE`].
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a new, empty [`Framer`] accepting frames up to `max_frame_size` bytes.
+    pub fn with_max_frame_size(max_frame_size: usize) -> Self {
+        let mut framer = Self::default();
+        framer.set_max_frame_size(max_frame_size);
+        framer
+    }
+
+    /// Sets the largest payload accepted or sent on this connection, capped by what the length header can hold.
+    pub fn set_max_frame_size(&mut self, max_frame_size: usize) {
+        self.max_frame_size = max_frame_size.min(u32::MAX as usize);
+    }
+
+    /// Gets the largest payload accepted or sent on this connection.
+    pub fn get_max_frame_size(&self) -> usize {
+        self.max_frame_size
+    }
+
+    /// Sets the largest message accepted or sent in chunks on this connection.
+    pub fn set_max_message_size(&mut self, max_message_size: usize) {
+        self.assembler.set_max_message_size(max_message_size);
+    }
+
+    /// Sets the capabilities negotiated for this connection, they decide how messages are encoded.
+    pub fn set_capabilities(&mut self, capabilities: Capabilities) {
+        self.capabilities = capabilities;
+    }
+
+    /// Gets the capabilities negotiated for this connection.
+    pub fn get_capabilities(&self) -> Capabilities {
+        self.capabilities
+    }
+
+    /// Appends freshly read bytes to the end of the buffer.
+    pub fn push(&mut self, data: &[u8]) {
+        self.buffer.extend_from_slice(data);
+    }
+
+    /// Returns the bytes that were not yet taken out as a frame.
+    pub fn buffered(&self) -> &[u8] {
+        &self.buffer
+    }
+
+    /// Throws away all of the buffered bytes.
+    pub fn clear(&mut self) {
+        self.buffer.clear();
+    }
+
+    /// Reads once from the stream and appends the data to the buffer, returning the number of bytes read.
+    pub fn read_from(&mut self, stream: &mut impl Read) -> std::io::Result<usize> {
+        let mut temp_buffer = vec![0u8; READ_BUFFER_SIZE];
+        let size = stream.read(&mut temp_buffer)?;
+        self.push(&temp_buffer[..size]);
+        Ok(size)
+    }
+
+    /// Takes the next complete message out of the buffer, or returns `None` if more bytes are needed.
+    ///
+    /// Chunks are collected until their message is complete, while frames that aren't chunked,
+    /// such as a `HEARTBEAT` sent in the middle of a large message, are handed out right away.
+    pub fn next_frame(&mut self) -> Result<Option<BinaryMessage>, DecodeError> {
+        while let Some(frame) = self.next_raw_frame()? {
+            if let Some(message) = self.assembler.push(frame)? {
+                return Ok(Some(message));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Takes the next complete frame out of the buffer, or returns `None` if more bytes are needed.
+    ///
+    /// A frame over the maximum frame size is rejected with [`DecodeError::TooLarge`],
+    /// and its bytes are skipped as they arrive so the frames after it can still be read.
+    fn next_raw_frame(&mut self) -> Result<Option<BinaryMessage>, DecodeError> {
+        self.discard_skipped();
+
+        if self.skip > 0 || self.buffer.len() < HEADER_SIZE {
+            return Ok(None);
+        }
+
+        let length = u32::from_be_bytes([
+            self.buffer[0],
+            self.buffer[1],
+            self.buffer[2],
+            self.buffer[3],
+        ]) as usize;
+
+        let flags = self.buffer[5];
+        let frame_size = frame_size(flags, length);
+
+        if length > self.max_frame_size {
+            self.skip = frame_size;
+            self.discard_skipped();
+            return Err(DecodeError::TooLarge {
+                length,
+                max: self.max_frame_size,
+            });
+        }
+
+        if self.buffer.len() < frame_size {
+            return Ok(None);
+        }
+
+        let frame: Vec<u8> = self.buffer.drain(..frame_size).collect();
+
+        // Once checksums are negotiated, a frame without one can't be trusted either
+        if self.capabilities.contains(Capabilities::CHECKSUM) && flags & FLAG_CHECKSUM == 0 {
+            return Err(DecodeError::MissingChecksum);
+        }
+
+        // Without chunking, nothing would limit how many partial messages a peer can leave behind
+        if !self.capabilities.contains(Capabilities::CHUNKING) && flags & FLAG_CHUNKED != 0 {
+            return Err(DecodeError::UnexpectedChunk);
+        }
+
+        BinaryMessage::deserialize_with_limit(&frame, self.max_frame_size).map(Some)
+    }
+
+    /// Serializes a message to be sent into one or more frames, rejecting it if it's too large.
+    ///
+    /// Peers that didn't negotiate `TYPED_ERRORS` get error frames as a plain `ERROR` command instead,
+    /// peers that negotiated `CHECKSUM` get a CRC32 trailer on every frame,
+    /// peers that negotiated `COMPRESSION` get large payloads compressed
+    /// and peers that negotiated `CHUNKING` get payloads over [`CHUNK_SIZE`] split into chunks,
+    /// which are limited by the maximum message size instead of the maximum frame size.
+    pub fn encode(&mut self, message: &BinaryMessage) -> Result<Vec<Vec<u8>>, DecodeError> {
+        if message.get_type() == MessageType::Error
+            && !self.capabilities.contains(Capabilities::TYPED_ERRORS)
+        {
+            return self.encode(&message.untyped_error());
+        }
+
+        let options = FrameOptions {
+            checksum: self.capabilities.contains(Capabilities::CHECKSUM),
+            compress: self.capabilities.contains(Capabilities::COMPRESSION),
+        };
+
+        let length = message.get_message().len();
+        if self.capabilities.contains(Capabilities::CHUNKING) && length > CHUNK_SIZE {
+            let max = self.assembler.get_max_message_size();
+            if length > max {
+                return Err(DecodeError::TooLarge { length, max });
+            }
+
+            let message_id = self.next_message_id;
+            self.next_message_id = self.next_message_id.wrapping_add(1);
+
+            return Ok(message
+                .to_chunks(message_id, CHUNK_SIZE)
+                .iter()
+                .map(|chunk| chunk.serialize_with(options))
+                .collect());
+        }
+
+        if length > self.max_frame_size {
+            return Err(DecodeError::TooLarge {
+                length,
+                max: self.max_frame_size,
+            });
+        }
+
+        Ok(vec![message.serialize_with(options)])
+    }
+
+    /// Drops as much of a rejected frame as is currently buffered.
+    fn discard_skipped(&mut self) {
+        let size = self.skip.min(self.buffer.len());
+        self.buffer.drain(..size);
+        self.skip -= size;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(text: &str) -> BinaryMessage {
+        BinaryMessage::new_message(text.to_string())
+    }
+
+    #[test]
+    fn framer_waits_for_the_rest_of_a_partial_frame() {
+        let bytes = message("hello there").serialize();
+        let mut framer = Framer::new();
+
+        // Neither a partial header nor a partial payload is a frame yet
+        framer.push(&bytes[..3]);
+        assert_eq!(framer.next_frame(), Ok(None));
+        framer.push(&bytes[3..HEADER_SIZE + 2]);
+        assert_eq!(framer.next_frame(), Ok(None));
+
+        framer.push(&bytes[HEADER_SIZE + 2..]);
+        assert_eq!(framer.next_frame(), Ok(Some(message("hello there"))));
+        assert!(framer.buffered().is_empty());
+    }
+
+    #[test]
+    fn framer_splits_coalesced_frames() {
+        let mut bytes = message("first").serialize();
+        bytes.extend(message("second").serialize());
+        let third = message("third").serialize();
+        bytes.extend(&third[..4]);
+
+        let mut framer = Framer::new();
+        framer.push(&bytes);
+        assert_eq!(framer.next_frame(), Ok(Some(message("first"))));
+        assert_eq!(framer.next_frame(), Ok(Some(message("second"))));
+        assert_eq!(framer.next_frame(), Ok(None));
+
+        framer.push(&third[4..]);
+        assert_eq!(framer.next_frame(), Ok(Some(message("third"))));
+        assert_eq!(framer.next_frame(), Ok(None));
+    }
+
+    #[test]
+    fn framer_reads_frames_arriving_a_byte_at_a_time() {
+        let bytes = message("slow").serialize();
+        let mut framer = Framer::new();
+
+        for byte in &bytes[..bytes.len() - 1] {
+            framer.push(&[*byte]);
+            assert_eq!(framer.next_frame(), Ok(None));
+        }
+        framer.push(&bytes[bytes.len() - 1..]);
+        assert_eq!(framer.next_frame(), Ok(Some(message("slow"))));
+    }
+
+    #[test]
+    fn rejects_frames_shorter_than_their_header() {
+        assert_eq!(
+            BinaryMessage::deserialize(&[0, 0, 0]),
+            Err(DecodeError::Truncated {
+                needed: HEADER_SIZE,
+                available: 3
+            })
+        );
+
+        // The flags promise a request id that isn't there
+        assert_eq!(
+            BinaryMessage::deserialize(&[0, 0, 0, 0, 1, FLAG_REQUEST_ID, 0]),
+            Err(DecodeError::Truncated {
+                needed: HEADER_SIZE + 4,
+                available: 7
+            })
+        );
+    }
+
+    #[test]
+    fn rejects_frames_whose_length_doesnt_match() {
+        let mut bytes = message("hello").serialize();
+        bytes[3] = 9;
+        assert_eq!(
+            BinaryMessage::deserialize(&bytes),
+            Err(DecodeError::LengthMismatch {
+                expected: 9,
+                actual: 5
+            })
+        );
+
+        bytes[3] = 2;
+        assert_eq!(
+            BinaryMessage::deserialize(&bytes),
+            Err(DecodeError::LengthMismatch {
+                expected: 2,
+                actual: 5
+            })
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_message_types_and_flags() {
+        let mut bytes = message("hello").serialize();
+        bytes[4] = 42;
+        assert_eq!(
+            BinaryMessage::deserialize(&bytes),
+            Err(DecodeError::UnknownMessageType(42))
+        );
+
+        let mut bytes = message("hello").serialize();
+        bytes[5] = 1 << 7;
+        assert_eq!(
+            BinaryMessage::deserialize(&bytes),
+            Err(DecodeError::UnknownFlags(1 << 7))
+        );
+
+        // Only a chunk can be the last chunk
+        bytes[5] = FLAG_LAST_CHUNK;
+        assert_eq!(
+            BinaryMessage::deserialize(&bytes),
+            Err(DecodeError::UnknownFlags(FLAG_LAST_CHUNK))
+        );
+    }
+
+    #[test]
+    fn framer_rejects_oversized_frames_and_reads_on() {
+        let mut framer = Framer::with_max_frame_size(8);
+        framer.push(&message("far too long for the limit").serialize());
+        framer.push(&message("short").serialize());
+
+        assert_eq!(
+            framer.next_frame(),
+            Err(DecodeError::TooLarge { length: 26, max: 8 })
+        );
+        assert_eq!(framer.next_frame(), Ok(Some(message("short"))));
+    }
+
+    #[test]
+    fn checksum_detects_a_corrupted_frame() {
+        let options = FrameOptions {
+            checksum: true,
+            ..Default::default()
+        };
+        let bytes = message("hello").serialize_with(options);
+        assert_eq!(BinaryMessage::deserialize(&bytes), Ok(message("hello")));
+
+        let mut corrupted = bytes.clone();
+        corrupted[HEADER_SIZE] ^= 0x20;
+        assert!(matches!(
+            BinaryMessage::deserialize(&corrupted),
+            Err(DecodeError::ChecksumMismatch { .. })
+        ));
+
+        let mut corrupted = bytes;
+        let last = corrupted.len() - 1;
+        corrupted[last] ^= 0x01;
+        assert!(matches!(
+            BinaryMessage::deserialize(&corrupted),
+            Err(DecodeError::ChecksumMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn framer_requires_a_checksum_once_negotiated() {
+        let mut framer = Framer::new();
+        framer.set_capabilities(Capabilities::CHECKSUM);
+
+        framer.push(&message("unchecked").serialize());
+        assert_eq!(framer.next_frame(), Err(DecodeError::MissingChecksum));
+
+        // The rejected frame is gone, the next one is read as usual
+        let options = FrameOptions {
+            checksum: true,
+            ..Default::default()
+        };
+        framer.push(&message("checked").serialize_with(options));
+        assert_eq!(framer.next_frame(), Ok(Some(message("checked"))));
+    }
+
+    #[test]
+    fn compression_round_trips_large_payloads() {
+        let options = FrameOptions {
+            compress: true,
+            ..Default::default()
+        };
+        let large = message(&"guess the word ".repeat(200));
+        let bytes = large.serialize_with(options);
+
+        assert_ne!(bytes[5] & FLAG_COMPRESSED, 0);
+        assert!(bytes.len() < large.get_message().len());
+        assert_eq!(BinaryMessage::deserialize(&bytes), Ok(large));
+
+        // Small payloads are sent as they are
+        let bytes = message("hello").serialize_with(options);
+        assert_eq!(bytes[5] & FLAG_COMPRESSED, 0);
+    }
+
+    #[test]
+    fn decompression_stops_at_the_size_limit() {
+        let bomb = message(&"a".repeat(8 * 1024 * 1024));
+        let options = FrameOptions {
+            compress: true,
+            ..Default::default()
+        };
+        let bytes = bomb.serialize_with(options);
+        assert!(bytes.len() < 64 * 1024);
+
+        assert_eq!(
+            BinaryMessage::deserialize_with_limit(&bytes, 1024 * 1024),
+            Err(DecodeError::TooLarge {
+                length: 1024 * 1024 + 1,
+                max: 1024 * 1024
+            })
+        );
+    }
+
+    #[test]
+    fn rejects_payloads_that_arent_deflate() {
+        let mut bytes = message("not deflate at all").serialize();
+        bytes[5] |= FLAG_COMPRESSED;
+        assert_eq!(
+            BinaryMessage::deserialize(&bytes),
+            Err(DecodeError::InvalidCompression)
+        );
+    }
+    #[test]
+    fn framer_rejects_chunks_unless_chunking_was_negotiated() {
+        let large = message(&"guess the word ".repeat(10));
+        let chunks = large.to_chunks(1, 64);
+        assert!(chunks.len() > 1);
+
+        let mut framer = Framer::new();
+        framer.push(&chunks[0].serialize());
+        framer.push(&message("unchunked").serialize());
+        assert_eq!(framer.next_frame(), Err(DecodeError::UnexpectedChunk));
+        assert_eq!(framer.next_frame(), Ok(Some(message("unchunked"))));
+
+        let mut framer = Framer::new();
+        framer.set_capabilities(Capabilities::CHUNKING);
+        for chunk in &chunks {
+            framer.push(&chunk.serialize());
+        }
+        assert_eq!(framer.next_frame(), Ok(Some(large)));
+    }
+}
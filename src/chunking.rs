@@ -0,0 +1,196 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::binary_message::{BinaryMessage, DecodeError};
+
+/// Most messages a peer may be sending in chunks at the same time.
+const MAX_PENDING_MESSAGES: usize = 16;
+
+#[derive(Debug, Default)]
+/// A message whose chunks are still arriving.
+struct PendingMessage {
+    next_index: u32,
+    payload: Vec<u8>,
+}
+
+#[derive(Debug)]
+/// Collects the chunks of messages split by [`BinaryMessage::to_chunks`] until each message is complete.
+///
+/// Chunks of a message must arrive in order, but chunks of different messages and frames that aren't chunked may be mixed between them.
+/// When a message is rejected, the rest of its chunks are dropped quietly, so the peer only gets one error for it.
+pub struct ChunkAssembler {
+    pending: HashMap<u32, PendingMessage>,
+    discarded: HashSet<u32>,
+    max_message_size: usize,
+}
+
+impl ChunkAssembler {
+    /// Creates a new [`ChunkAssembler`] accepting messages up to `max_message_size` bytes.
+    pub fn new(max_message_size: usize) -> Self {
+        Self {
+            pending: HashMap::new(),
+            discarded: HashSet::new(),
+            max_message_size,
+        }
+    }
+
+    /// Sets the largest message that can be reassembled.
+    pub fn set_max_message_size(&mut self, max_message_size: usize) {
+        self.max_message_size = max_message_size;
+    }
+
+    /// Gets the largest message that can be reassembled.
+    pub fn get_max_message_size(&self) -> usize {
+        self.max_message_size
+    }
+
+    /// Adds a frame, returning a message once it's complete.
+    ///
+    /// Frames that aren't chunked are complete messages and are returned right away.
+    pub fn push(&mut self, frame: BinaryMessage) -> Result<Option<BinaryMessage>, DecodeError> {
+        let Some(chunk) = frame.get_chunk() else {
+            return Ok(Some(frame));
+        };
+        let message_id = chunk.message_id;
+
+        if self.discarded.contains(&message_id) {
+            if chunk.last {
+                self.discarded.remove(&message_id);
+            }
+            return Ok(None);
+        }
+
+        let expected = self
+            .pending
+            .get(&message_id)
+            .map_or(0, |pending| pending.next_index);
+        if chunk.index != expected {
+            self.discard(message_id, chunk.last);
+            return Err(DecodeError::InvalidChunk {
+                message_id,
+                reason: format!("expected chunk {expected}, got chunk {}", chunk.index),
+            });
+        }
+
+        if expected == 0 && self.pending.len() >= MAX_PENDING_MESSAGES {
+            self.discard(message_id, chunk.last);
+            return Err(DecodeError::InvalidChunk {
+                message_id,
+                reason: "too many messages are being sent in chunks at once".to_string(),
+            });
+        }
+
+        let pending = self.pending.entry(message_id).or_default();
+        let length = pending.payload.len() + frame.get_message().len();
+        if length > self.max_message_size {
+            self.discard(message_id, chunk.last);
+            return Err(DecodeError::TooLarge {
+                length,
+                max: self.max_message_size,
+            });
+        }
+
+        pending.payload.extend_from_slice(frame.get_message());
+        pending.next_index += 1;
+
+        if !chunk.last {
+            return Ok(None);
+        }
+
+        let payload = self
+            .pending
+            .remove(&message_id)
+            .map(|pending| pending.payload)
+            .unwrap_or_default();
+        Ok(Some(frame.reassembled(payload)))
+    }
+
+    /// Forgets a rejected message, and drops the rest of its chunks if more are coming.
+    fn discard(&mut self, message_id: u32, last: bool) {
+        self.pending.remove(&message_id);
+
+        if !last {
+            // A peer that never finishes its messages can't make this grow forever
+            if self.discarded.len() >= MAX_PENDING_MESSAGES {
+                self.discarded.clear();
+            }
+            self.discarded.insert(message_id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunks(text: &str, message_id: u32, chunk_size: usize) -> Vec<BinaryMessage> {
+        BinaryMessage::new_message(text.to_string()).to_chunks(message_id, chunk_size)
+    }
+
+    #[test]
+    fn reassembles_chunks_in_order_with_other_frames_between_them() {
+        let mut assembler = ChunkAssembler::new(1024);
+        let first = chunks("abcdefgh", 1, 3);
+        let second = chunks("12345", 2, 3);
+        let heartbeat = BinaryMessage::new_command("HEARTBEAT".to_string());
+
+        assert_eq!(assembler.push(first[0].clone()), Ok(None));
+        assert_eq!(assembler.push(second[0].clone()), Ok(None));
+        assert_eq!(
+            assembler.push(heartbeat.clone()),
+            Ok(Some(heartbeat.clone()))
+        );
+        assert_eq!(assembler.push(first[1].clone()), Ok(None));
+        assert_eq!(
+            assembler.push(second[1].clone()),
+            Ok(Some(BinaryMessage::new_message("12345".to_string())))
+        );
+        assert_eq!(
+            assembler.push(first[2].clone()),
+            Ok(Some(BinaryMessage::new_message("abcdefgh".to_string())))
+        );
+    }
+
+    #[test]
+    fn rejects_a_missing_chunk_and_drops_the_rest_of_its_message() {
+        let mut assembler = ChunkAssembler::new(1024);
+        let message = chunks("abcdefghi", 1, 3);
+
+        assert_eq!(assembler.push(message[0].clone()), Ok(None));
+        assert!(matches!(
+            assembler.push(message[2].clone()),
+            Err(DecodeError::InvalidChunk { message_id: 1, .. })
+        ));
+
+        // The message is rejected once, a later message with the same id starts over
+        let retry = chunks("xyz", 1, 3);
+        assert_eq!(
+            assembler.push(retry[0].clone()),
+            Ok(Some(BinaryMessage::new_message("xyz".to_string())))
+        );
+    }
+
+    #[test]
+    fn rejects_a_message_that_doesnt_start_with_its_first_chunk() {
+        let mut assembler = ChunkAssembler::new(1024);
+        let message = chunks("abcdefghi", 1, 3);
+
+        assert!(matches!(
+            assembler.push(message[1].clone()),
+            Err(DecodeError::InvalidChunk { message_id: 1, .. })
+        ));
+        assert_eq!(assembler.push(message[2].clone()), Ok(None));
+    }
+
+    #[test]
+    fn rejects_messages_over_the_size_limit() {
+        let mut assembler = ChunkAssembler::new(5);
+        let message = chunks("abcdefghi", 1, 3);
+
+        assert_eq!(assembler.push(message[0].clone()), Ok(None));
+        assert_eq!(
+            assembler.push(message[1].clone()),
+            Err(DecodeError::TooLarge { length: 6, max: 5 })
+        );
+        assert_eq!(assembler.push(message[2].clone()), Ok(None));
+    }
+}
@@ -0,0 +1,393 @@
+use std::io::Read;
+
+use serde_json::{Map, Value};
+
+use crate::{
+    binary_message::{
+        BinaryMessage, DecodeError, Framer, MessageType, DEFAULT_MAX_FRAME_SIZE, READ_BUFFER_SIZE,
+    },
+    error_code::ErrorCode,
+    handshake::Capabilities,
+    text_protocol::LineFramer,
+};
+
+/// Size of the legacy frame header, 2 bytes of length and 1 byte of message type.
+pub const LEGACY_HEADER_SIZE: usize = 3;
+
+/// Largest payload the 16 bit length of a legacy frame can describe.
+pub const LEGACY_MAX_FRAME_SIZE: usize = u16::MAX as usize;
+
+/// Turns messages into bytes on the wire and back, so a connection can speak any of several formats.
+///
+/// Every codec carries the same [`BinaryMessage`]s, the handshake, commands and replies don't change with the format.
+/// A codec only has to support the features its format can express, see [`CodecKind::capabilities`].
+pub trait Codec: Send {
+    /// Returns which format the codec speaks.
+    fn kind(&self) -> CodecKind;
+
+    /// Appends freshly read bytes to the end of the buffer.
+    fn push(&mut self, data: &[u8]);
+
+    /// Reads once from the stream and appends the data to the buffer, returning the number of bytes read.
+    fn read_from(&mut self, stream: &mut dyn Read) -> std::io::Result<usize> {
+        let mut temp_buffer = vec![0u8; READ_BUFFER_SIZE];
+        let size = stream.read(&mut temp_buffer)?;
+        self.push(&temp_buffer[..size]);
+        Ok(size)
+    }
+
+    /// Takes the next complete message out of the buffer, or returns `None` if more bytes are needed.
+    fn next_frame(&mut self) -> Result<Option<BinaryMessage>, DecodeError>;
+
+    /// Serializes a message to be sent into one or more frames, rejecting it if it's too large.
+    fn encode(&mut self, message: &BinaryMessage) -> Result<Vec<Vec<u8>>, DecodeError>;
+
+    /// Sets the capabilities negotiated for this connection, they decide how messages are encoded.
+    fn set_capabilities(&mut self, capabilities: Capabilities);
+
+    /// Sets the largest payload accepted or sent on this connection.
+    fn set_max_frame_size(&mut self, max_frame_size: usize);
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Wire formats a connection can speak.
+pub enum CodecKind {
+    /// The current binary frames, see [`Framer`].
+    Binary,
+    /// The frames of the original `Message` type, a 16 bit length, a type byte and the payload, see [`LegacyCodec`].
+    Legacy,
+    /// One JSON object per line, meant for debugging with a terminal, see [`JsonCodec`].
+    Json,
+}
+
+impl CodecKind {
+    /// Every codec, in the order they are listed to users.
+    pub const ALL: [CodecKind; 3] = [CodecKind::Binary, CodecKind::Legacy, CodecKind::Json];
+
+    /// Gets the name used to pick the codec on the command line.
+    pub fn name(self) -> &'static str {
+        match self {
+            CodecKind::Binary => "binary",
+            CodecKind::Legacy => "legacy",
+            CodecKind::Json => "json",
+        }
+    }
+
+    /// Returns the optional features the format is able to carry, the rest are never negotiated with it.
+    pub fn capabilities(self) -> Capabilities {
+        match self {
+            CodecKind::Binary => Capabilities::supported(),
+            CodecKind::Legacy => Capabilities::NONE,
+            CodecKind::Json => Capabilities::LARGE_FRAMES | Capabilities::TYPED_ERRORS,
+        }
+    }
+
+    /// Creates a fresh codec of this kind for a new connection.
+    pub fn new_codec(self) -> Box<dyn Codec> {
+        match self {
+            CodecKind::Binary => Box::new(Framer::new()),
+            CodecKind::Legacy => Box::new(LegacyCodec::new()),
+            CodecKind::Json => Box::new(JsonCodec::new()),
+        }
+    }
+
+    /// Detects the codec from the first bytes a client sent, or returns `None` if more bytes are needed.
+    ///
+    /// Every connection starts with a `HELLO`, which is far shorter than 256 bytes. In a binary frame its 32 bit length
+    /// therefore starts with three zero bytes, while the third byte of a legacy frame is its message type, which is never zero.
+    pub fn detect(prefix: &[u8]) -> Option<Self> {
+        match prefix.first()? {
+            b'{' => Some(CodecKind::Json),
+            _ => match *prefix.get(2)? {
+                0 => Some(CodecKind::Binary),
+                _ => Some(CodecKind::Legacy),
+            },
+        }
+    }
+}
+
+impl std::fmt::Display for CodecKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl std::str::FromStr for CodecKind {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, String> {
+        CodecKind::ALL
+            .into_iter()
+            .find(|kind| kind.name() == name)
+            .ok_or_else(|| format!("unknown codec {name}, use binary, legacy or json"))
+    }
+}
+
+impl Codec for Framer {
+    fn kind(&self) -> CodecKind {
+        CodecKind::Binary
+    }
+
+    fn push(&mut self, data: &[u8]) {
+        Framer::push(self, data)
+    }
+
+    fn next_frame(&mut self) -> Result<Option<BinaryMessage>, DecodeError> {
+        Framer::next_frame(self)
+    }
+
+    fn encode(&mut self, message: &BinaryMessage) -> Result<Vec<Vec<u8>>, DecodeError> {
+        Framer::encode(self, message)
+    }
+
+    fn set_capabilities(&mut self, capabilities: Capabilities) {
+        Framer::set_capabilities(self, capabilities)
+    }
+
+    fn set_max_frame_size(&mut self, max_frame_size: usize) {
+        Framer::set_max_frame_size(self, max_frame_size)
+    }
+}
+
+#[derive(Debug, Default)]
+/// Codec for the frames of the original `Message` type, laid out as `| length u16 | type u8 | payload |`.
+///
+/// The format has no flags, so request ids and the push marker are dropped, and errors are sent as a plain `ERROR` command.
+pub struct LegacyCodec {
+    buffer: Vec<u8>,
+}
+
+impl LegacyCodec {
+    /// Creates a new, empty [`LegacyCodec`].
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Codec for LegacyCodec {
+    fn kind(&self) -> CodecKind {
+        CodecKind::Legacy
+    }
+
+    fn push(&mut self, data: &[u8]) {
+        self.buffer.extend_from_slice(data);
+    }
+
+    fn next_frame(&mut self) -> Result<Option<BinaryMessage>, DecodeError> {
+        if self.buffer.len() < LEGACY_HEADER_SIZE {
+            return Ok(None);
+        }
+
+        let length = u16::from_be_bytes([self.buffer[0], self.buffer[1]]) as usize;
+        if self.buffer.len() < LEGACY_HEADER_SIZE + length {
+            return Ok(None);
+        }
+
+        // The whole frame is taken out first, so an unknown type doesn't leave the rest of the stream out of step
+        let frame: Vec<u8> = self.buffer.drain(..LEGACY_HEADER_SIZE + length).collect();
+        let message_type = MessageType::try_from(frame[2])?;
+
+        Ok(Some(BinaryMessage::new(
+            message_type,
+            frame[LEGACY_HEADER_SIZE..].to_vec(),
+        )))
+    }
+
+    fn encode(&mut self, message: &BinaryMessage) -> Result<Vec<Vec<u8>>, DecodeError> {
+        if message.get_type() == MessageType::Error {
+            return self.encode(&message.untyped_error());
+        }
+
+        let payload = message.get_message();
+        if payload.len() > LEGACY_MAX_FRAME_SIZE {
+            return Err(DecodeError::TooLarge {
+                length: payload.len(),
+                max: LEGACY_MAX_FRAME_SIZE,
+            });
+        }
+
+        let mut bytes = Vec::with_capacity(LEGACY_HEADER_SIZE + payload.len());
+        bytes.extend((payload.len() as u16).to_be_bytes());
+        bytes.push(message.get_type() as u8);
+        bytes.extend_from_slice(payload);
+        Ok(vec![bytes])
+    }
+
+    fn set_capabilities(&mut self, _capabilities: Capabilities) {}
+
+    fn set_max_frame_size(&mut self, _max_frame_size: usize) {}
+}
+
+#[derive(Debug)]
+/// Codec sending every message as one JSON object per line, e.g.
+/// `{"type":"command","payload":"GUESS apple","request_id":7}`.
+///
+/// Payloads that aren't UTF-8 are sent as `payload_bytes`, an array of numbers,
+/// and error frames as `{"type":"error","code":103,"message":"..."}`. The maximum frame size limits the whole line.
+pub struct JsonCodec {
+    lines: LineFramer,
+    max_frame_size: usize,
+    capabilities: Capabilities,
+}
+
+impl Default for JsonCodec {
+    fn default() -> Self {
+        Self {
+            lines: LineFramer::new(),
+            max_frame_size: DEFAULT_MAX_FRAME_SIZE,
+            capabilities: Capabilities::NONE,
+        }
+    }
+}
+
+impl JsonCodec {
+    /// Creates a new, empty [`JsonCodec`] accepting lines up to [`DEFAULT_MAX_FRAME_SIZE`] bytes.
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Codec for JsonCodec {
+    fn kind(&self) -> CodecKind {
+        CodecKind::Json
+    }
+
+    fn push(&mut self, data: &[u8]) {
+        self.lines.push(data);
+    }
+
+    fn next_frame(&mut self) -> Result<Option<BinaryMessage>, DecodeError> {
+        loop {
+            match self.lines.next_line()? {
+                // Blank lines make typing into a terminal easier, they carry nothing
+                Some(line) if line.trim().is_empty() => continue,
+                Some(line) => return json_to_message(&line).map(Some),
+                None => return Ok(None),
+            }
+        }
+    }
+
+    fn encode(&mut self, message: &BinaryMessage) -> Result<Vec<Vec<u8>>, DecodeError> {
+        if message.get_type() == MessageType::Error
+            && !self.capabilities.contains(Capabilities::TYPED_ERRORS)
+        {
+            return self.encode(&message.untyped_error());
+        }
+
+        let mut line = message_to_json(message).to_string().into_bytes();
+        if line.len() > self.max_frame_size {
+            return Err(DecodeError::TooLarge {
+                length: line.len(),
+                max: self.max_frame_size,
+            });
+        }
+
+        line.push(b'\n');
+        Ok(vec![line])
+    }
+
+    fn set_capabilities(&mut self, capabilities: Capabilities) {
+        self.capabilities = capabilities;
+    }
+
+    fn set_max_frame_size(&mut self, max_frame_size: usize) {
+        self.max_frame_size = max_frame_size;
+        self.lines.set_max_line_length(max_frame_size);
+    }
+}
+
+/// Builds the JSON object of a message.
+fn message_to_json(message: &BinaryMessage) -> Value {
+    let mut object = Map::new();
+    object.insert("type".to_string(), message.get_type().name().into());
+
+    let payload = message.get_message();
+    match message.get_type() {
+        MessageType::Error => {
+            let code = payload
+                .get(..2)
+                .map_or(0, |code| u16::from_be_bytes([code[0], code[1]]));
+            let text = String::from_utf8_lossy(payload.get(2..).unwrap_or_default());
+            object.insert("code".to_string(), code.into());
+            object.insert("message".to_string(), text.into());
+        }
+        _ => match std::str::from_utf8(payload) {
+            Ok(text) => {
+                object.insert("payload".to_string(), text.into());
+            }
+            Err(_) => {
+                object.insert("payload_bytes".to_string(), payload.clone().into());
+            }
+        },
+    }
+
+    if let Some(request_id) = message.get_request_id() {
+        object.insert("request_id".to_string(), request_id.into());
+    }
+    if message.is_push() {
+        object.insert("push".to_string(), true.into());
+    }
+    Value::Object(object)
+}
+
+/// Reads a message from its JSON object, rejecting anything that doesn't describe a message.
+fn json_to_message(line: &str) -> Result<BinaryMessage, DecodeError> {
+    let invalid = |reason: &str| DecodeError::InvalidJson(reason.to_string());
+
+    let value: Value =
+        serde_json::from_str(line).map_err(|err| DecodeError::InvalidJson(err.to_string()))?;
+    let object = value.as_object().ok_or_else(|| invalid("not an object"))?;
+
+    let message_type = match object.get("type").and_then(Value::as_str) {
+        Some("unknown") => MessageType::Unknown,
+        Some("command") => MessageType::Command,
+        Some("message") => MessageType::Message,
+        Some("error") => MessageType::Error,
+        Some(_) => return Err(invalid("unknown type")),
+        None => return Err(invalid("missing type")),
+    };
+
+    let message = match message_type {
+        MessageType::Error => {
+            let code = object
+                .get("code")
+                .and_then(Value::as_u64)
+                .and_then(|code| u16::try_from(code).ok())
+                .ok_or_else(|| invalid("error without a valid code"))?;
+            let text = object
+                .get("message")
+                .and_then(Value::as_str)
+                .unwrap_or_default();
+            BinaryMessage::new_error(ErrorCode::from(code), text.to_string())
+        }
+        _ => {
+            let payload = match (object.get("payload"), object.get("payload_bytes")) {
+                (Some(Value::String(text)), None) => text.clone().into_bytes(),
+                (None, Some(Value::Array(bytes))) => bytes
+                    .iter()
+                    .map(|byte| byte.as_u64().and_then(|byte| u8::try_from(byte).ok()))
+                    .collect::<Option<Vec<u8>>>()
+                    .ok_or_else(|| invalid("payload_bytes must only hold bytes"))?,
+                (None, None) => Vec::new(),
+                _ => return Err(invalid("needs either a payload string or payload_bytes")),
+            };
+            BinaryMessage::new(message_type, payload)
+        }
+    };
+
+    let request_id = match object.get("request_id") {
+        Some(value) => Some(
+            value
+                .as_u64()
+                .and_then(|id| u32::try_from(id).ok())
+                .ok_or_else(|| invalid("request_id must be a 32 bit number"))?,
+        ),
+        None => None,
+    };
+
+    let message = message.with_request_id(request_id);
+    match object.get("push").and_then(Value::as_bool) {
+        Some(true) => Ok(message.into_push()),
+        _ => Ok(message),
+    }
+}
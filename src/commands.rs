@@ -0,0 +1,190 @@
+use crate::{
+    binary_message::{BinaryMessage, DecodeError, MessageType},
+    handshake::ProtocolInfo,
+    protocol_spec::{CANCEL, DM, DROP, GUESS, HEARTBEAT, HELLO, HINT, REQUEST, STARTGAME},
+};
+
+#[derive(Debug, PartialEq, Clone)]
+/// All the commands that the client can send and the server can receive and interpret
+pub enum ServerCommandList {
+    Unknown,
+    Hello(ProtocolInfo),
+    HeartBeat,
+    Drop,
+    DirectMessage { recipient: u64, text: String },
+    Hint(String),
+    Guess(String),
+    StartGame { opponent: u64, secret: String },
+    Message(String),
+    CancelGame,
+    RequestOpponents,
+}
+
+/// Builds the error for a command whose arguments don't parse.
+fn invalid_arguments(command: &str, reason: impl Into<String>) -> DecodeError {
+    DecodeError::InvalidArguments {
+        command: command.to_string(),
+        reason: reason.into(),
+    }
+}
+
+/// Parses a user ID argument.
+fn parse_id(command: &str, id: &str) -> Result<u64, DecodeError> {
+    id.parse::<u64>()
+        .map_err(|err| invalid_arguments(command, format!("invalid user ID '{id}': {err}")))
+}
+
+/// Parses a single non-empty text argument, such as a hint or a guess.
+fn parse_text(command: &str, arguments: Vec<u8>) -> Result<String, DecodeError> {
+    let text = String::from_utf8(arguments)?;
+    let text = text.trim();
+    if text.is_empty() {
+        return Err(invalid_arguments(command, "expected a text"));
+    }
+    Ok(text.to_string())
+}
+
+/// Parses the `ID TEXT...` arguments of a direct message, the text being the rest of the line.
+fn parse_direct_message(arguments: Vec<u8>) -> Result<ServerCommandList, DecodeError> {
+    let arguments = String::from_utf8(arguments)?;
+    let (id, text) = arguments
+        .trim()
+        .split_once(char::is_whitespace)
+        .ok_or_else(|| invalid_arguments(DM, "expected a user ID and a text"))?;
+
+    Ok(ServerCommandList::DirectMessage {
+        recipient: parse_id(DM, id)?,
+        text: text.trim().to_string(),
+    })
+}
+
+/// Parses the `ID SECRET` arguments of a game request, the secret being a single word.
+fn parse_start_game(arguments: Vec<u8>) -> Result<ServerCommandList, DecodeError> {
+    let arguments = String::from_utf8(arguments)?;
+    let tokens: Vec<&str> = arguments.split_whitespace().collect();
+
+    match tokens[..] {
+        [id, secret] => Ok(ServerCommandList::StartGame {
+            opponent: parse_id(STARTGAME, id)?,
+            secret: secret.to_string(),
+        }),
+        _ => Err(invalid_arguments(
+            STARTGAME,
+            "expected an opponent ID and a secret word",
+        )),
+    }
+}
+
+impl TryFrom<BinaryMessage> for ServerCommandList {
+    type Error = DecodeError;
+
+    fn try_from(binary_message: BinaryMessage) -> Result<Self, Self::Error> {
+        Ok(match binary_message.get_type() {
+            MessageType::Command => {
+                // Split data to command and the binary part
+                let (command, binary) = binary_message.split()?;
+
+                match command.to_ascii_uppercase().as_str() {
+                    HELLO => Self::Hello(ProtocolInfo::from_bytes(&binary).ok_or_else(|| {
+                        invalid_arguments(HELLO, "expected a version and capabilities")
+                    })?),
+                    DM => parse_direct_message(binary)?,
+                    HEARTBEAT => Self::HeartBeat,
+                    DROP => Self::Drop,
+                    HINT => Self::Hint(parse_text(HINT, binary)?),
+                    GUESS => Self::Guess(parse_text(GUESS, binary)?),
+                    STARTGAME => parse_start_game(binary)?,
+                    CANCEL => Self::CancelGame,
+                    REQUEST => Self::RequestOpponents,
+                    _ => Self::Unknown,
+                }
+            }
+            MessageType::Message => {
+                Self::Message(String::from_utf8(binary_message.get_message().clone())?)
+            }
+
+            _ => ServerCommandList::Unknown,
+        })
+    }
+}
+
+impl From<ServerCommandList> for BinaryMessage {
+    fn from(command: ServerCommandList) -> Self {
+        match command {
+            ServerCommandList::Unknown => BinaryMessage::new_command("UNKNOWN".to_string()),
+            ServerCommandList::Hello(info) => {
+                BinaryMessage::new_binary_command(HELLO, &info.to_bytes())
+            }
+            ServerCommandList::HeartBeat => BinaryMessage::new_command(HEARTBEAT.to_string()),
+            ServerCommandList::Drop => BinaryMessage::new_command(DROP.to_string()),
+            ServerCommandList::DirectMessage { recipient, text } => {
+                BinaryMessage::new_command(format!("{DM} {recipient} {text}"))
+            }
+            ServerCommandList::Hint(hint) => BinaryMessage::new_command(format!("{HINT} {hint}")),
+            ServerCommandList::Guess(guess) => {
+                BinaryMessage::new_command(format!("{GUESS} {guess}"))
+            }
+            ServerCommandList::StartGame { opponent, secret } => {
+                BinaryMessage::new_command(format!("{STARTGAME} {opponent} {secret}"))
+            }
+            ServerCommandList::Message(text) => BinaryMessage::new_message(text),
+            ServerCommandList::CancelGame => BinaryMessage::new_command(CANCEL.to_string()),
+            ServerCommandList::RequestOpponents => BinaryMessage::new_command(REQUEST.to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(command: &str) -> Result<ServerCommandList, DecodeError> {
+        ServerCommandList::try_from(BinaryMessage::new_command(command.to_string()))
+    }
+
+    #[test]
+    fn parses_typed_arguments() {
+        assert_eq!(
+            parse("DM 7 good luck"),
+            Ok(ServerCommandList::DirectMessage {
+                recipient: 7,
+                text: "good luck".to_string()
+            })
+        );
+        assert_eq!(
+            parse("STARTGAME 2 apple"),
+            Ok(ServerCommandList::StartGame {
+                opponent: 2,
+                secret: "apple".to_string()
+            })
+        );
+    }
+
+    #[test]
+    fn rejects_missing_and_malformed_arguments() {
+        for command in [
+            "DM",
+            "DM 7",
+            "DM seven hello",
+            "GUESS",
+            "HINT   ",
+            "STARTGAME 2",
+            "STARTGAME -1 apple",
+            "STARTGAME 2 two words",
+        ] {
+            assert!(
+                matches!(parse(command), Err(DecodeError::InvalidArguments { .. })),
+                "{command} should be rejected"
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_text_that_isnt_utf8() {
+        let message = BinaryMessage::new_binary_command("GUESS", &[0xff, 0xfe]);
+        assert_eq!(
+            ServerCommandList::try_from(message),
+            Err(DecodeError::InvalidUtf8)
+        );
+    }
+}
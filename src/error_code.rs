@@ -0,0 +1,130 @@
+use crate::binary_message::DecodeError;
+
+#[repr(u16)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Stable numeric codes carried by error frames, so clients can branch on the error without parsing its text.
+///
+/// Codes are grouped by hundreds and are never reused or renumbered:
+///
+/// | Code | Name                  | Meaning                                                        |
+/// |------|-----------------------|----------------------------------------------------------------|
+/// | 0    | `UNKNOWN`             | A code this build doesn't know about.                          |
+/// | 100  | `MALFORMED_FRAME`     | The frame couldn't be decoded.                                 |
+/// | 101  | `FRAME_TOO_LARGE`     | The frame is over the maximum frame size of the connection.    |
+/// | 102  | `MALFORMED_ARGUMENTS` | The command arguments are missing or malformed.                |
+/// | 103  | `UNKNOWN_COMMAND`     | The command is not part of the protocol.                       |
+/// | 104  | `CHECKSUM_MISMATCH`   | The frame's checksum is wrong or missing, it was corrupted.    |
+/// | 110  | `HANDSHAKE_REQUIRED`  | The first frame of the connection wasn't a `HELLO`.            |
+/// | 111  | `UNSUPPORTED_VERSION` | The client's protocol version is too old.                      |
+/// | 112  | `HANDSHAKE_COMPLETED` | A `HELLO` was sent after the handshake was already done.       |
+/// | 200  | `AUTH_FAILED`         | The password is incorrect.                                     |
+/// | 201  | `AUTH_REQUIRED`       | A command was sent before the password.                        |
+/// | 300  | `UNKNOWN_USER`        | No connected user has the given id.                            |
+/// | 301  | `NOT_IN_GAME`         | The command needs a game, but the user doesn't play one.       |
+/// | 302  | `SELF_MATCH`          | A user tried to start a game against themselves.               |
+/// | 303  | `NO_OPPONENTS`        | There is nobody to play against.                               |
+/// | 400  | `RATE_LIMITED`        | The client sends too much, too quickly.                        |
+/// | 401  | `IDLE_TIMEOUT`        | The client missed too many heartbeats and is disconnected.     |
+/// | 500  | `INTERNAL`            | The server failed in a way that isn't the client's fault.      |
+pub enum ErrorCode {
+    Unknown = 0,
+    MalformedFrame = 100,
+    FrameTooLarge = 101,
+    MalformedArguments = 102,
+    UnknownCommand = 103,
+    ChecksumMismatch = 104,
+    HandshakeRequired = 110,
+    UnsupportedVersion = 111,
+    HandshakeCompleted = 112,
+    AuthFailed = 200,
+    AuthRequired = 201,
+    UnknownUser = 300,
+    NotInGame = 301,
+    SelfMatch = 302,
+    NoOpponents = 303,
+    RateLimited = 400,
+    IdleTimeout = 401,
+    Internal = 500,
+}
+
+impl ErrorCode {
+    /// Every code in the catalogue, in ascending order.
+    pub const ALL: [ErrorCode; 18] = [
+        ErrorCode::Unknown,
+        ErrorCode::MalformedFrame,
+        ErrorCode::FrameTooLarge,
+        ErrorCode::MalformedArguments,
+        ErrorCode::UnknownCommand,
+        ErrorCode::ChecksumMismatch,
+        ErrorCode::HandshakeRequired,
+        ErrorCode::UnsupportedVersion,
+        ErrorCode::HandshakeCompleted,
+        ErrorCode::AuthFailed,
+        ErrorCode::AuthRequired,
+        ErrorCode::UnknownUser,
+        ErrorCode::NotInGame,
+        ErrorCode::SelfMatch,
+        ErrorCode::NoOpponents,
+        ErrorCode::RateLimited,
+        ErrorCode::IdleTimeout,
+        ErrorCode::Internal,
+    ];
+
+    /// Gets the numeric value sent on the wire.
+    pub fn code(self) -> u16 {
+        self as u16
+    }
+
+    /// Gets the name of the code, as listed in the catalogue.
+    pub fn name(self) -> &'static str {
+        match self {
+            ErrorCode::Unknown => "UNKNOWN",
+            ErrorCode::MalformedFrame => "MALFORMED_FRAME",
+            ErrorCode::FrameTooLarge => "FRAME_TOO_LARGE",
+            ErrorCode::MalformedArguments => "MALFORMED_ARGUMENTS",
+            ErrorCode::UnknownCommand => "UNKNOWN_COMMAND",
+            ErrorCode::ChecksumMismatch => "CHECKSUM_MISMATCH",
+            ErrorCode::HandshakeRequired => "HANDSHAKE_REQUIRED",
+            ErrorCode::UnsupportedVersion => "UNSUPPORTED_VERSION",
+            ErrorCode::HandshakeCompleted => "HANDSHAKE_COMPLETED",
+            ErrorCode::AuthFailed => "AUTH_FAILED",
+            ErrorCode::AuthRequired => "AUTH_REQUIRED",
+            ErrorCode::UnknownUser => "UNKNOWN_USER",
+            ErrorCode::NotInGame => "NOT_IN_GAME",
+            ErrorCode::SelfMatch => "SELF_MATCH",
+            ErrorCode::NoOpponents => "NO_OPPONENTS",
+            ErrorCode::RateLimited => "RATE_LIMITED",
+            ErrorCode::IdleTimeout => "IDLE_TIMEOUT",
+            ErrorCode::Internal => "INTERNAL",
+        }
+    }
+}
+
+impl From<u16> for ErrorCode {
+    /// Codes added by newer builds map to [`ErrorCode::Unknown`].
+    fn from(value: u16) -> Self {
+        ErrorCode::ALL
+            .into_iter()
+            .find(|code| code.code() == value)
+            .unwrap_or(ErrorCode::Unknown)
+    }
+}
+
+impl From<&DecodeError> for ErrorCode {
+    fn from(err: &DecodeError) -> Self {
+        match err {
+            DecodeError::TooLarge { .. } => ErrorCode::FrameTooLarge,
+            DecodeError::InvalidArguments { .. } => ErrorCode::MalformedArguments,
+            DecodeError::ChecksumMismatch { .. } | DecodeError::MissingChecksum => {
+                ErrorCode::ChecksumMismatch
+            }
+            _ => ErrorCode::MalformedFrame,
+        }
+    }
+}
+
+impl std::fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{} {}", self.code(), self.name())
+    }
+}
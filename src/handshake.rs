@@ -0,0 +1,123 @@
+use std::ops::BitOr;
+
+/// Version of the protocol spoken by this build.
+pub const PROTOCOL_VERSION: u16 = 3;
+
+/// Oldest protocol version this build is still able to talk to.
+///
+/// Version 2 widened the frame length to 32 bits and version 3 added the flags byte to the header,
+/// so frames of older versions can't be read anymore.
+pub const MIN_PROTOCOL_VERSION: u16 = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+/// Set of optional protocol features, one bit per feature.
+pub struct Capabilities(u32);
+
+impl Capabilities {
+    /// No optional features at all.
+    pub const NONE: Self = Self(0);
+    /// Payloads may be compressed.
+    pub const COMPRESSION: Self = Self(1 << 0);
+    /// Frames may be larger than 64 KiB.
+    pub const LARGE_FRAMES: Self = Self(1 << 1);
+    /// Errors are sent as typed error frames with an error code.
+    pub const TYPED_ERRORS: Self = Self(1 << 2);
+    /// Every frame ends with a CRC32 checksum.
+    pub const CHECKSUM: Self = Self(1 << 3);
+    /// Large messages may be split into chunks.
+    pub const CHUNKING: Self = Self(1 << 4);
+
+    /// Returns the features implemented by this build.
+    pub fn supported() -> Self {
+        Self::COMPRESSION
+            | Self::LARGE_FRAMES
+            | Self::TYPED_ERRORS
+            | Self::CHECKSUM
+            | Self::CHUNKING
+    }
+
+    /// Creates the set from its raw bits, ignoring the bits this build doesn't know about.
+    pub fn from_bits(bits: u32) -> Self {
+        Self(
+            bits & (Self::COMPRESSION
+                | Self::LARGE_FRAMES
+                | Self::TYPED_ERRORS
+                | Self::CHECKSUM
+                | Self::CHUNKING)
+                .0,
+        )
+    }
+
+    /// Returns the raw bits of the set.
+    pub fn bits(self) -> u32 {
+        self.0
+    }
+
+    /// Returns true if every feature of `other` is in the set.
+    pub fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// Returns the features present in both sets, i.e. the ones both sides agreed on.
+    pub fn intersection(self, other: Self) -> Self {
+        Self(self.0 & other.0)
+    }
+}
+
+impl BitOr for Capabilities {
+    type Output = Self;
+
+    fn bitor(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Protocol version and capabilities, carried by both `HELLO` and `WELCOME`.
+///
+/// The client opens every connection with a `HELLO` advertising what it speaks,
+/// the server answers with a `WELCOME` carrying its own version and the capabilities both sides share.
+pub struct ProtocolInfo {
+    pub version: u16,
+    pub capabilities: Capabilities,
+}
+
+impl ProtocolInfo {
+    /// Size of the encoded [`ProtocolInfo`], 2 bytes of version and 4 bytes of capabilities.
+    pub const SIZE: usize = 6;
+
+    /// Creates the [`ProtocolInfo`] describing this build.
+    pub fn current() -> Self {
+        Self {
+            version: PROTOCOL_VERSION,
+            capabilities: Capabilities::supported(),
+        }
+    }
+
+    /// Returns true if the peer's version is new enough for this build to talk to.
+    pub fn is_compatible(&self) -> bool {
+        self.version >= MIN_PROTOCOL_VERSION
+    }
+
+    /// Serializes the version and capabilities into big-endian bytes.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(Self::SIZE);
+        bytes.extend(self.version.to_be_bytes());
+        bytes.extend(self.capabilities.bits().to_be_bytes());
+        bytes
+    }
+
+    /// Deserializes the version and capabilities, returning `None` if the length is wrong.
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() != Self::SIZE {
+            return None;
+        }
+
+        Some(Self {
+            version: u16::from_be_bytes([bytes[0], bytes[1]]),
+            capabilities: Capabilities::from_bits(u32::from_be_bytes([
+                bytes[2], bytes[3], bytes[4], bytes[5],
+            ])),
+        })
+    }
+}
@@ -0,0 +1,24 @@
+//! Protocol shared by the client and the server.
+//!
+//! Both binaries depend on this crate, so the wire format and the command vocabulary
+//! are defined in a single place and the two sides can't disagree about them.
+
+pub mod address;
+pub mod binary_message;
+pub mod checksum;
+pub mod chunking;
+pub mod codec;
+pub mod commands;
+pub mod error_code;
+pub mod handshake;
+pub mod protocol_spec;
+pub mod responses;
+pub mod text_protocol;
+
+pub use binary_message::{BinaryMessage, DecodeError, Framer, MessageType};
+pub use codec::{Codec, CodecKind};
+pub use commands::ServerCommandList;
+pub use error_code::ErrorCode;
+pub use handshake::{Capabilities, ProtocolInfo};
+pub use responses::ServerMessageResponse;
+pub use text_protocol::LineFramer;
@@ -0,0 +1,487 @@
+use serde_json::{json, Value};
+
+use crate::{
+    binary_message::{
+        MessageType, CHECKSUM_SIZE, CHUNK_HEADER_SIZE, CHUNK_SIZE, COMPRESSION_THRESHOLD,
+        DEFAULT_MAX_FRAME_SIZE, DEFAULT_MAX_MESSAGE_SIZE, FLAG_CHECKSUM, FLAG_CHUNKED,
+        FLAG_COMPRESSED, FLAG_LAST_CHUNK, FLAG_PUSH, FLAG_REQUEST_ID, HEADER_SIZE,
+        LARGE_MAX_FRAME_SIZE,
+    },
+    codec::CodecKind,
+    error_code::ErrorCode,
+    handshake::{Capabilities, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION},
+};
+
+/// Keyword of the handshake sent by the client.
+pub const HELLO: &str = "HELLO";
+/// Keyword of a direct message to another user.
+pub const DM: &str = "DM";
+/// Keyword of the keep-alive sent by the client.
+pub const HEARTBEAT: &str = "HEARTBEAT";
+/// Keyword of the command leaving the server.
+pub const DROP: &str = "DROP";
+/// Keyword of a hint sent by the host of a game.
+pub const HINT: &str = "HINT";
+/// Keyword of a guess sent by the opponent in a game.
+pub const GUESS: &str = "GUESS";
+/// Keyword of a request to start a game.
+pub const STARTGAME: &str = "STARTGAME";
+/// Keyword of the command canceling the current game.
+pub const CANCEL: &str = "CANCEL";
+/// Keyword of the request for the list of opponents.
+pub const REQUEST: &str = "REQUEST";
+
+/// Keyword of the server's answer to the handshake.
+pub const WELCOME: &str = "WELCOME";
+/// Keyword of the id assigned to an authenticated user.
+pub const ID: &str = "ID";
+/// Keyword of an error sent to peers without `TYPED_ERRORS`.
+pub const ERROR: &str = "ERROR";
+/// Keyword of the acknowledgement of a game request.
+pub const REQUESTACK: &str = "REQUESTACK";
+/// Keyword of the event telling the opponent that a game started.
+pub const REQUESTEDGAME: &str = "REQUESTEDGAME";
+/// Keyword of the event telling a player they won.
+pub const VICTORY: &str = "VICTORY";
+/// Keyword of the event telling a player they lost.
+pub const DEFEAT: &str = "DEFEAT";
+/// Keyword of the event telling a player their game was canceled.
+pub const CANCELED: &str = "CANCELED";
+/// Keyword of the event telling the host their opponent guessed wrong.
+pub const WRONGGUESS: &str = "WRONGGUESS";
+/// Keyword of the event telling a client the server is shutting down.
+pub const SHUTDOWN: &str = "SHUTDOWN";
+/// Keyword of the event telling a client the server can't take its connection right now.
+pub const BUSY: &str = "BUSY";
+/// Keyword of the event asking a client to answer with a heartbeat.
+pub const PING: &str = "PING";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// How an argument is written into the payload.
+pub enum ArgumentEncoding {
+    /// A decimal number written as text.
+    Decimal,
+    /// A single word of text, without whitespace.
+    Word,
+    /// Text running to the end of the payload.
+    Text,
+    /// A big-endian unsigned 16 bit number.
+    U16,
+    /// A big-endian unsigned 32 bit number.
+    U32,
+    /// A big-endian unsigned 64 bit number.
+    U64,
+}
+
+impl ArgumentEncoding {
+    /// Gets the name of the encoding used in the protocol description.
+    pub fn name(self) -> &'static str {
+        match self {
+            ArgumentEncoding::Decimal => "decimal",
+            ArgumentEncoding::Word => "word",
+            ArgumentEncoding::Text => "text",
+            ArgumentEncoding::U16 => "u16",
+            ArgumentEncoding::U32 => "u32",
+            ArgumentEncoding::U64 => "u64",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+/// One argument of a message.
+pub struct ArgumentSpec {
+    pub name: &'static str,
+    pub encoding: ArgumentEncoding,
+    pub description: &'static str,
+}
+
+#[derive(Debug, Clone, Copy)]
+/// One message of the protocol.
+///
+/// Command frames start with the keyword, followed by a space and the arguments if there are any.
+/// Message and error frames have no keyword, their payload is the arguments alone.
+/// Text arguments are separated by a space, binary ones follow each other without a separator.
+pub struct MessageSpec {
+    /// Unique name of the message, in lowercase.
+    pub name: &'static str,
+    pub keyword: Option<&'static str>,
+    pub message_type: MessageType,
+    pub arguments: &'static [ArgumentSpec],
+    pub description: &'static str,
+}
+
+/// Arguments of `HELLO` and `WELCOME`, see [`ProtocolInfo`](crate::ProtocolInfo).
+const PROTOCOL_INFO: &[ArgumentSpec] = &[
+    ArgumentSpec {
+        name: "version",
+        encoding: ArgumentEncoding::U16,
+        description: "Protocol version of the sender.",
+    },
+    ArgumentSpec {
+        name: "capabilities",
+        encoding: ArgumentEncoding::U32,
+        description: "Bit set of the optional features, see `capabilities`.",
+    },
+];
+
+/// Every request the client can send, as parsed by [`ServerCommandList`](crate::ServerCommandList).
+pub const REQUESTS: &[MessageSpec] = &[
+    MessageSpec {
+        name: "hello",
+        keyword: Some(HELLO),
+        message_type: MessageType::Command,
+        arguments: PROTOCOL_INFO,
+        description: "Opens the handshake, it must be the first frame of every connection.",
+    },
+    MessageSpec {
+        name: "heartbeat",
+        keyword: Some(HEARTBEAT),
+        message_type: MessageType::Command,
+        arguments: &[],
+        description: "Tells the server the client is still there.",
+    },
+    MessageSpec {
+        name: "drop",
+        keyword: Some(DROP),
+        message_type: MessageType::Command,
+        arguments: &[],
+        description: "Leaves the server, ending the user's game.",
+    },
+    MessageSpec {
+        name: "dm",
+        keyword: Some(DM),
+        message_type: MessageType::Command,
+        arguments: &[
+            ArgumentSpec {
+                name: "recipient",
+                encoding: ArgumentEncoding::Decimal,
+                description: "Id of the user receiving the message.",
+            },
+            ArgumentSpec {
+                name: "text",
+                encoding: ArgumentEncoding::Text,
+                description: "The message.",
+            },
+        ],
+        description: "Sends a message to a single user.",
+    },
+    MessageSpec {
+        name: "hint",
+        keyword: Some(HINT),
+        message_type: MessageType::Command,
+        arguments: &[ArgumentSpec {
+            name: "hint",
+            encoding: ArgumentEncoding::Text,
+            description: "The hint, it can't be empty.",
+        }],
+        description: "Sends a hint to the opponent, only the host of a game may send it.",
+    },
+    MessageSpec {
+        name: "guess",
+        keyword: Some(GUESS),
+        message_type: MessageType::Command,
+        arguments: &[ArgumentSpec {
+            name: "guess",
+            encoding: ArgumentEncoding::Text,
+            description: "The guessed word, it can't be empty.",
+        }],
+        description: "Guesses the secret word, only the opponent in a game may send it.",
+    },
+    MessageSpec {
+        name: "startgame",
+        keyword: Some(STARTGAME),
+        message_type: MessageType::Command,
+        arguments: &[
+            ArgumentSpec {
+                name: "opponent",
+                encoding: ArgumentEncoding::Decimal,
+                description: "Id of the user to play against.",
+            },
+            ArgumentSpec {
+                name: "secret",
+                encoding: ArgumentEncoding::Word,
+                description: "The word the opponent has to guess.",
+            },
+        ],
+        description: "Starts a game against another user, the sender is the host.",
+    },
+    MessageSpec {
+        name: "cancel",
+        keyword: Some(CANCEL),
+        message_type: MessageType::Command,
+        arguments: &[],
+        description: "Cancels the user's game.",
+    },
+    MessageSpec {
+        name: "request",
+        keyword: Some(REQUEST),
+        message_type: MessageType::Command,
+        arguments: &[],
+        description: "Asks for the list of users to play against.",
+    },
+    MessageSpec {
+        name: "message",
+        keyword: None,
+        message_type: MessageType::Message,
+        arguments: &[ArgumentSpec {
+            name: "text",
+            encoding: ArgumentEncoding::Text,
+            description: "The message.",
+        }],
+        description: "The password before the user is authenticated, a chat message after.",
+    },
+];
+
+/// Every reply and event the server can send, as parsed by [`ServerMessageResponse`](crate::ServerMessageResponse).
+pub const RESPONSES: &[MessageSpec] = &[
+    MessageSpec {
+        name: "welcome",
+        keyword: Some(WELCOME),
+        message_type: MessageType::Command,
+        arguments: PROTOCOL_INFO,
+        description:
+            "Answers HELLO with the server's version and the capabilities both sides share.",
+    },
+    MessageSpec {
+        name: "id",
+        keyword: Some(ID),
+        message_type: MessageType::Command,
+        arguments: &[ArgumentSpec {
+            name: "id",
+            encoding: ArgumentEncoding::U64,
+            description: "Id assigned to the user.",
+        }],
+        description: "Answers a correct password.",
+    },
+    MessageSpec {
+        name: "error",
+        keyword: None,
+        message_type: MessageType::Error,
+        arguments: &[
+            ArgumentSpec {
+                name: "code",
+                encoding: ArgumentEncoding::U16,
+                description: "Stable error code, see `error_codes`.",
+            },
+            ArgumentSpec {
+                name: "message",
+                encoding: ArgumentEncoding::Text,
+                description: "Human-readable description of the error.",
+            },
+        ],
+        description: "Reports a failed request, once both sides agreed on TYPED_ERRORS.",
+    },
+    MessageSpec {
+        name: "legacy_error",
+        keyword: Some(ERROR),
+        message_type: MessageType::Command,
+        arguments: &[ArgumentSpec {
+            name: "message",
+            encoding: ArgumentEncoding::Text,
+            description: "Human-readable description of the error.",
+        }],
+        description:
+            "Reports a failed request to peers without TYPED_ERRORS, the code is left out.",
+    },
+    MessageSpec {
+        name: "message",
+        keyword: None,
+        message_type: MessageType::Message,
+        arguments: &[ArgumentSpec {
+            name: "text",
+            encoding: ArgumentEncoding::Text,
+            description: "The message.",
+        }],
+        description: "A chat message from another user, or the text reply to a command.",
+    },
+    MessageSpec {
+        name: "requestack",
+        keyword: Some(REQUESTACK),
+        message_type: MessageType::Command,
+        arguments: &[],
+        description: "Acknowledges a game request.",
+    },
+    MessageSpec {
+        name: "requestedgame",
+        keyword: Some(REQUESTEDGAME),
+        message_type: MessageType::Command,
+        arguments: &[],
+        description: "Tells the opponent that a game against them started.",
+    },
+    MessageSpec {
+        name: "victory",
+        keyword: Some(VICTORY),
+        message_type: MessageType::Command,
+        arguments: &[],
+        description: "Tells a player they won the game.",
+    },
+    MessageSpec {
+        name: "defeat",
+        keyword: Some(DEFEAT),
+        message_type: MessageType::Command,
+        arguments: &[],
+        description: "Tells a player they lost the game.",
+    },
+    MessageSpec {
+        name: "canceled",
+        keyword: Some(CANCELED),
+        message_type: MessageType::Command,
+        arguments: &[],
+        description: "Tells a player their game was canceled.",
+    },
+    MessageSpec {
+        name: "hint",
+        keyword: Some(HINT),
+        message_type: MessageType::Command,
+        arguments: &[ArgumentSpec {
+            name: "hint",
+            encoding: ArgumentEncoding::Text,
+            description: "The hint sent by the host.",
+        }],
+        description: "Passes a hint from the host on to the opponent.",
+    },
+    MessageSpec {
+        name: "wrongguess",
+        keyword: Some(WRONGGUESS),
+        message_type: MessageType::Command,
+        arguments: &[
+            ArgumentSpec {
+                name: "attempts",
+                encoding: ArgumentEncoding::U32,
+                description: "Attempts the opponent has left.",
+            },
+            ArgumentSpec {
+                name: "guess",
+                encoding: ArgumentEncoding::Text,
+                description: "The wrong guess.",
+            },
+        ],
+        description: "Tells the host their opponent guessed wrong.",
+    },
+    MessageSpec {
+        name: "shutdown",
+        keyword: Some(SHUTDOWN),
+        message_type: MessageType::Command,
+        arguments: &[ArgumentSpec {
+            name: "reason",
+            encoding: ArgumentEncoding::Text,
+            description: "Why the server is shutting down.",
+        }],
+        description:
+            "Tells a client the server is shutting down, the connection is closed right after.",
+    },
+    MessageSpec {
+        name: "busy",
+        keyword: Some(BUSY),
+        message_type: MessageType::Command,
+        arguments: &[ArgumentSpec {
+            name: "reason",
+            encoding: ArgumentEncoding::Text,
+            description: "Why the connection is refused.",
+        }],
+        description:
+            "Tells a client the server can't take its connection, it's closed right after.",
+    },
+    MessageSpec {
+        name: "ping",
+        keyword: Some(PING),
+        message_type: MessageType::Command,
+        arguments: &[],
+        description:
+            "Asks a quiet client for a heartbeat, a client missing too many is disconnected.",
+    },
+];
+
+/// Flags of the binary frame header, by name.
+pub const FLAGS: [(&str, u8); 6] = [
+    ("REQUEST_ID", FLAG_REQUEST_ID),
+    ("PUSH", FLAG_PUSH),
+    ("CHECKSUM", FLAG_CHECKSUM),
+    ("COMPRESSED", FLAG_COMPRESSED),
+    ("CHUNKED", FLAG_CHUNKED),
+    ("LAST_CHUNK", FLAG_LAST_CHUNK),
+];
+
+/// Optional features negotiated by the handshake, by name.
+pub const CAPABILITIES: [(&str, Capabilities); 5] = [
+    ("COMPRESSION", Capabilities::COMPRESSION),
+    ("LARGE_FRAMES", Capabilities::LARGE_FRAMES),
+    ("TYPED_ERRORS", Capabilities::TYPED_ERRORS),
+    ("CHECKSUM", Capabilities::CHECKSUM),
+    ("CHUNKING", Capabilities::CHUNKING),
+];
+
+/// Builds the machine-readable description of the protocol spoken by this build.
+///
+/// Everything in it comes from the constants the encoders and decoders use, so it can't drift from the code.
+/// The description is published as `docs/protocol.json`, a conformance test keeps that file up to date.
+pub fn describe() -> Value {
+    let capability_names = |capabilities: Capabilities| -> Vec<&str> {
+        CAPABILITIES
+            .iter()
+            .filter(|(_, capability)| capabilities.contains(*capability))
+            .map(|(name, _)| *name)
+            .collect()
+    };
+
+    let messages = |specs: &[MessageSpec]| -> Vec<Value> {
+        specs
+            .iter()
+            .map(|spec| {
+                json!({
+                    "name": spec.name,
+                    "keyword": spec.keyword,
+                    "type": spec.message_type.name(),
+                    "arguments": spec.arguments.iter().map(|argument| json!({
+                        "name": argument.name,
+                        "encoding": argument.encoding.name(),
+                        "description": argument.description,
+                    })).collect::<Vec<Value>>(),
+                    "description": spec.description,
+                })
+            })
+            .collect()
+    };
+
+    let message_types = [
+        MessageType::Unknown,
+        MessageType::Command,
+        MessageType::Message,
+        MessageType::Error,
+    ];
+
+    json!({
+        "version": PROTOCOL_VERSION,
+        "min_version": MIN_PROTOCOL_VERSION,
+        "frame": {
+            "layout": "| length u32 | type u8 | flags u8 | request id u32 | message id u32 | chunk index u32 | payload | crc32 u32 |",
+            "header_size": HEADER_SIZE,
+            "chunk_header_size": CHUNK_HEADER_SIZE,
+            "checksum_size": CHECKSUM_SIZE,
+            "chunk_size": CHUNK_SIZE,
+            "compression_threshold": COMPRESSION_THRESHOLD,
+            "default_max_frame_size": DEFAULT_MAX_FRAME_SIZE,
+            "large_max_frame_size": LARGE_MAX_FRAME_SIZE,
+            "default_max_message_size": DEFAULT_MAX_MESSAGE_SIZE,
+            "flags": FLAGS.iter().map(|(name, bit)| json!({ "name": name, "value": bit })).collect::<Vec<Value>>(),
+        },
+        "message_types": message_types.iter().map(|message_type| json!({
+            "name": message_type.name(),
+            "value": *message_type as u8,
+        })).collect::<Vec<Value>>(),
+        "capabilities": CAPABILITIES.iter().map(|(name, capability)| json!({
+            "name": name,
+            "value": capability.bits(),
+        })).collect::<Vec<Value>>(),
+        "codecs": CodecKind::ALL.iter().map(|kind| json!({
+            "name": kind.name(),
+            "capabilities": capability_names(kind.capabilities()),
+        })).collect::<Vec<Value>>(),
+        "error_codes": ErrorCode::ALL.iter().map(|code| json!({
+            "code": code.code(),
+            "name": code.name(),
+        })).collect::<Vec<Value>>(),
+        "requests": messages(REQUESTS),
+        "responses": messages(RESPONSES),
+    })
+}
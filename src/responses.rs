@@ -0,0 +1,147 @@
+use crate::{
+    binary_message::{BinaryMessage, DecodeError, MessageType},
+    error_code::ErrorCode,
+    handshake::ProtocolInfo,
+    protocol_spec::{
+        BUSY, CANCELED, DEFEAT, ERROR, HINT, ID, PING, REQUESTACK, REQUESTEDGAME, SHUTDOWN,
+        VICTORY, WELCOME, WRONGGUESS,
+    },
+};
+
+#[derive(Debug, PartialEq, Clone)]
+/// Possible responses and events that the server sends to the client
+pub enum ServerMessageResponse {
+    Unknown,
+    Welcome(ProtocolInfo),
+    ID(u64),
+    Error { code: ErrorCode, message: String },
+    Message(String),
+    RequestAck,
+    RequestedGame,
+    GameVictory,
+    GameDefeat,
+    GameCanceled,
+    Hint(String),
+    WrongGuess { guess: String, attempts: u32 },
+    Shutdown(String),
+    Busy(String),
+    Ping,
+}
+
+impl TryFrom<BinaryMessage> for ServerMessageResponse {
+    type Error = DecodeError;
+
+    fn try_from(binary_message: BinaryMessage) -> Result<Self, DecodeError> {
+        match binary_message.get_type() {
+            MessageType::Command => {
+                // Split data to command and the binary part
+                let (command, binary) = binary_message.split()?;
+
+                // Match the command
+                Ok(match command.to_ascii_uppercase().as_str() {
+                    WELCOME => Self::Welcome(ProtocolInfo::from_bytes(&binary).ok_or(
+                        DecodeError::InvalidArguments {
+                            command: command.clone(),
+                            reason: "expected a version and capabilities".to_string(),
+                        },
+                    )?),
+                    // The ID is always sent as 8 big-endian bytes
+                    ID => Self::ID(u64::from_be_bytes(binary.try_into().map_err(|_| {
+                        DecodeError::InvalidArguments {
+                            command: command.clone(),
+                            reason: "expected an 8 byte ID".to_string(),
+                        }
+                    })?)),
+                    // Peers without typed errors only get the text, the code is unknown
+                    ERROR => Self::Error {
+                        code: ErrorCode::Unknown,
+                        message: String::from_utf8(binary)?,
+                    },
+                    REQUESTACK => Self::RequestAck,
+                    REQUESTEDGAME => Self::RequestedGame,
+                    DEFEAT => Self::GameDefeat,
+                    CANCELED => Self::GameCanceled,
+                    HINT => Self::Hint(String::from_utf8(binary)?),
+                    // The attempts left are 4 big-endian bytes, the guess follows them
+                    WRONGGUESS => {
+                        if binary.len() < 4 {
+                            return Err(DecodeError::InvalidArguments {
+                                command,
+                                reason: "expected 4 bytes of attempts left".to_string(),
+                            });
+                        }
+                        Self::WrongGuess {
+                            attempts: u32::from_be_bytes([
+                                binary[0], binary[1], binary[2], binary[3],
+                            ]),
+                            guess: String::from_utf8(binary[4..].to_vec())?,
+                        }
+                    }
+                    VICTORY => Self::GameVictory,
+                    SHUTDOWN => Self::Shutdown(String::from_utf8(binary)?),
+                    BUSY => Self::Busy(String::from_utf8(binary)?),
+                    PING => Self::Ping,
+                    _ => Self::Unknown,
+                })
+            }
+            MessageType::Message => Ok(ServerMessageResponse::Message(String::from_utf8(
+                binary_message.get_message().clone(),
+            )?)),
+            MessageType::Error => {
+                let payload = binary_message.get_message();
+                if payload.len() < 2 {
+                    return Err(DecodeError::InvalidArguments {
+                        command: ERROR.to_string(),
+                        reason: "expected a 2 byte error code".to_string(),
+                    });
+                }
+
+                Ok(Self::Error {
+                    code: ErrorCode::from(u16::from_be_bytes([payload[0], payload[1]])),
+                    message: String::from_utf8(payload[2..].to_vec())?,
+                })
+            }
+            _ => Ok(Self::Unknown),
+        }
+    }
+}
+
+impl From<ServerMessageResponse> for BinaryMessage {
+    fn from(response: ServerMessageResponse) -> Self {
+        match response {
+            ServerMessageResponse::Unknown => BinaryMessage::new_command("UNKNOWN".to_string()),
+            ServerMessageResponse::Welcome(info) => {
+                BinaryMessage::new_binary_command(WELCOME, &info.to_bytes())
+            }
+            ServerMessageResponse::ID(id) => {
+                BinaryMessage::new_binary_command(ID, &id.to_be_bytes())
+            }
+            ServerMessageResponse::Error { code, message } => {
+                BinaryMessage::new_error(code, message)
+            }
+            ServerMessageResponse::Message(text) => BinaryMessage::new_message(text),
+            ServerMessageResponse::RequestAck => BinaryMessage::new_command(REQUESTACK.to_string()),
+            ServerMessageResponse::RequestedGame => {
+                BinaryMessage::new_command(REQUESTEDGAME.to_string())
+            }
+            ServerMessageResponse::GameVictory => BinaryMessage::new_command(VICTORY.to_string()),
+            ServerMessageResponse::GameDefeat => BinaryMessage::new_command(DEFEAT.to_string()),
+            ServerMessageResponse::GameCanceled => BinaryMessage::new_command(CANCELED.to_string()),
+            ServerMessageResponse::Hint(hint) => {
+                BinaryMessage::new_binary_command(HINT, hint.as_bytes())
+            }
+            ServerMessageResponse::WrongGuess { guess, attempts } => {
+                let mut arguments = attempts.to_be_bytes().to_vec();
+                arguments.extend_from_slice(guess.as_bytes());
+                BinaryMessage::new_binary_command(WRONGGUESS, &arguments)
+            }
+            ServerMessageResponse::Shutdown(reason) => {
+                BinaryMessage::new_binary_command(SHUTDOWN, reason.as_bytes())
+            }
+            ServerMessageResponse::Busy(reason) => {
+                BinaryMessage::new_binary_command(BUSY, reason.as_bytes())
+            }
+            ServerMessageResponse::Ping => BinaryMessage::new_command(PING.to_string()),
+        }
+    }
+}
@@ -0,0 +1,137 @@
+use std::io::Read;
+
+use crate::{
+    binary_message::{BinaryMessage, DecodeError, DEFAULT_MAX_FRAME_SIZE, READ_BUFFER_SIZE},
+    commands::ServerCommandList,
+    responses::ServerMessageResponse,
+};
+
+#[derive(Debug)]
+/// Per-connection buffer that splits a byte stream into newline-terminated lines.
+///
+/// This is the text counterpart of the [`Framer`](crate::Framer), used by clients such as `nc` that can't produce binary frames.
+/// Both `\n` and `\r\n` line endings are accepted.
+pub struct LineFramer {
+    buffer: Vec<u8>,
+    max_line_length: usize,
+    // The rest of an over-long line has to be thrown away up to its newline
+    discarding: bool,
+}
+
+impl Default for LineFramer {
+    fn default() -> Self {
+        Self {
+            buffer: Vec::default(),
+            max_line_length: DEFAULT_MAX_FRAME_SIZE,
+            discarding: false,
+        }
+    }
+}
+
+impl LineFramer {
+    /// Creates a new, empty [`LineFramer`] accepting lines up to [`DEFAULT_MAX_FRAME_SIZE`] bytes.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the longest line accepted, without its line ending.
+    pub fn set_max_line_length(&mut self, max_line_length: usize) {
+        self.max_line_length = max_line_length;
+    }
+
+    /// Appends freshly read bytes to the end of the buffer.
+    pub fn push(&mut self, data: &[u8]) {
+        self.buffer.extend_from_slice(data);
+    }
+
+    /// Reads once from the stream and appends the data to the buffer, returning the number of bytes read.
+    pub fn read_from(&mut self, stream: &mut impl Read) -> std::io::Result<usize> {
+        let mut temp_buffer = vec![0u8; READ_BUFFER_SIZE];
+        let size = stream.read(&mut temp_buffer)?;
+        self.push(&temp_buffer[..size]);
+        Ok(size)
+    }
+
+    /// Takes the next complete line out of the buffer without its line ending, or returns `None` if more bytes are needed.
+    ///
+    /// A line over the maximum length is rejected with [`DecodeError::TooLarge`] and skipped up to its newline.
+    pub fn next_line(&mut self) -> Result<Option<String>, DecodeError> {
+        while let Some(end) = self.buffer.iter().position(|&byte| byte == b'\n') {
+            let mut line: Vec<u8> = self.buffer.drain(..=end).collect();
+
+            // This is the tail of a line that was already rejected
+            if std::mem::take(&mut self.discarding) {
+                continue;
+            }
+
+            line.pop();
+            if line.last() == Some(&b'\r') {
+                line.pop();
+            }
+
+            if line.len() > self.max_line_length {
+                return Err(DecodeError::TooLarge {
+                    length: line.len(),
+                    max: self.max_line_length,
+                });
+            }
+
+            return Ok(Some(String::from_utf8(line)?));
+        }
+
+        // No newline yet, don't let a single line grow the buffer without a limit
+        if self.buffer.len() > self.max_line_length {
+            let length = self.buffer.len();
+            self.buffer.clear();
+
+            if !std::mem::replace(&mut self.discarding, true) {
+                return Err(DecodeError::TooLarge {
+                    length,
+                    max: self.max_line_length,
+                });
+            }
+        }
+
+        Ok(None)
+    }
+}
+
+/// Translates a line of text into a [`ServerCommandList`].
+///
+/// Lines starting with a `/` are commands, e.g. `/GUESS apple`, anything else is a chat message.
+pub fn parse_line(line: &str) -> Result<ServerCommandList, DecodeError> {
+    match line.trim().strip_prefix('/') {
+        Some(command) => {
+            ServerCommandList::try_from(BinaryMessage::new_command(command.to_string()))
+        }
+        None => Ok(ServerCommandList::Message(line.trim().to_string())),
+    }
+}
+
+/// Formats a server response or event as a single readable line, without the line ending.
+pub fn format_response(response: &ServerMessageResponse) -> String {
+    match response {
+        ServerMessageResponse::Unknown => "UNKNOWN".to_string(),
+        ServerMessageResponse::Welcome(info) => {
+            format!("WELCOME protocol version {}", info.version)
+        }
+        ServerMessageResponse::ID(id) => format!("ID {id}"),
+        ServerMessageResponse::Error { code, message } => format!("ERROR {code}: {message}"),
+        // A message could span several lines, it's kept on one so every line stays one event
+        ServerMessageResponse::Message(text) => text.replace(['\r', '\n'], " "),
+        ServerMessageResponse::RequestAck => "OK request acknowledged".to_string(),
+        ServerMessageResponse::RequestedGame => {
+            "GAME STARTED use /HINT to send a hint and /GUESS to send a guess".to_string()
+        }
+        ServerMessageResponse::GameVictory => "VICTORY".to_string(),
+        ServerMessageResponse::GameDefeat => "DEFEAT".to_string(),
+        ServerMessageResponse::GameCanceled => "GAME CANCELED".to_string(),
+        ServerMessageResponse::Hint(hint) => format!("HINT {hint}"),
+        ServerMessageResponse::WrongGuess { guess, attempts } => {
+            format!("WRONG GUESS {guess}, {attempts} attempts left")
+        }
+        ServerMessageResponse::Shutdown(reason) => format!("SHUTDOWN {reason}"),
+        ServerMessageResponse::Busy(reason) => format!("BUSY {reason}"),
+        ServerMessageResponse::Ping => "PING".to_string(),
+    }
+}
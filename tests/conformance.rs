@@ -0,0 +1,421 @@
+//! Golden wire-format vectors, so changes to the encoding of any request or event can't go unnoticed.
+//!
+//! The vectors of every protocol version live in `tests/golden/vVERSION`, one file per codec.
+//! After an intended change to the wire format, regenerate them, and `docs/protocol.json`, with
+//! `UPDATE_GOLDEN=1 cargo test --test conformance` and bump the protocol version if old peers can't read the new frames.
+
+use std::{collections::BTreeSet, path::PathBuf};
+
+use client_server_tcp::{
+    binary_message::LARGE_MAX_FRAME_SIZE,
+    handshake::PROTOCOL_VERSION,
+    protocol_spec::{describe, ERROR, REQUESTS, RESPONSES},
+    BinaryMessage, Capabilities, Codec, CodecKind, ErrorCode, ProtocolInfo, ServerCommandList,
+    ServerMessageResponse,
+};
+
+/// A request as sent by the client, with the request id it's tagged with.
+struct RequestSample {
+    name: &'static str,
+    request_id: u32,
+    command: ServerCommandList,
+}
+
+/// A reply or event as sent by the server, `None` as request id marks an event.
+struct ResponseSample {
+    name: &'static str,
+    request_id: Option<u32>,
+    message: BinaryMessage,
+    response: ServerMessageResponse,
+}
+
+/// One sample of every request, named after its entry in [`REQUESTS`].
+fn request_samples() -> Vec<RequestSample> {
+    let sample = |name, request_id, command| RequestSample {
+        name,
+        request_id,
+        command,
+    };
+
+    vec![
+        sample(
+            "hello",
+            1,
+            ServerCommandList::Hello(ProtocolInfo {
+                version: PROTOCOL_VERSION,
+                capabilities: Capabilities::supported(),
+            }),
+        ),
+        sample(
+            "message",
+            2,
+            ServerCommandList::Message("dota2".to_string()),
+        ),
+        sample("heartbeat", 3, ServerCommandList::HeartBeat),
+        sample(
+            "dm",
+            4,
+            ServerCommandList::DirectMessage {
+                recipient: 2,
+                text: "good luck".to_string(),
+            },
+        ),
+        sample(
+            "startgame",
+            5,
+            ServerCommandList::StartGame {
+                opponent: 2,
+                secret: "apple".to_string(),
+            },
+        ),
+        sample("hint", 6, ServerCommandList::Hint("a fruit".to_string())),
+        sample("guess", 7, ServerCommandList::Guess("apple".to_string())),
+        sample("cancel", 8, ServerCommandList::CancelGame),
+        sample("request", 9, ServerCommandList::RequestOpponents),
+        sample("drop", 10, ServerCommandList::Drop),
+    ]
+}
+
+/// One sample of every reply and event, named after its entry in [`RESPONSES`].
+fn response_samples() -> Vec<ResponseSample> {
+    let sample = |name, request_id: Option<u32>, response: ServerMessageResponse| {
+        let message = BinaryMessage::from(response.clone());
+        ResponseSample {
+            name,
+            request_id,
+            message: match request_id {
+                Some(_) => message.with_request_id(request_id),
+                None => message.into_push(),
+            },
+            response,
+        }
+    };
+
+    let legacy_error = "Unknown command received";
+
+    vec![
+        sample(
+            "welcome",
+            Some(1),
+            ServerMessageResponse::Welcome(ProtocolInfo {
+                version: PROTOCOL_VERSION,
+                capabilities: Capabilities::supported(),
+            }),
+        ),
+        sample("id", Some(2), ServerMessageResponse::ID(1)),
+        sample(
+            "message",
+            Some(3),
+            ServerMessageResponse::Message("OK Heartbeat received".to_string()),
+        ),
+        sample(
+            "error",
+            Some(4),
+            ServerMessageResponse::Error {
+                code: ErrorCode::NotInGame,
+                message: "you are not in a game".to_string(),
+            },
+        ),
+        // The server only sends this one through codecs without typed errors, so it's built by hand
+        ResponseSample {
+            name: "legacy_error",
+            request_id: Some(5),
+            message: BinaryMessage::new_binary_command(ERROR, legacy_error.as_bytes())
+                .with_request_id(Some(5)),
+            response: ServerMessageResponse::Error {
+                code: ErrorCode::Unknown,
+                message: legacy_error.to_string(),
+            },
+        },
+        sample("requestack", Some(6), ServerMessageResponse::RequestAck),
+        sample("requestedgame", None, ServerMessageResponse::RequestedGame),
+        sample("victory", None, ServerMessageResponse::GameVictory),
+        sample("defeat", None, ServerMessageResponse::GameDefeat),
+        sample("canceled", None, ServerMessageResponse::GameCanceled),
+        sample(
+            "hint",
+            None,
+            ServerMessageResponse::Hint("it's a fruit".to_string()),
+        ),
+        sample(
+            "wrongguess",
+            None,
+            ServerMessageResponse::WrongGuess {
+                guess: "pear".to_string(),
+                attempts: 2,
+            },
+        ),
+        sample(
+            "shutdown",
+            None,
+            ServerMessageResponse::Shutdown("the server received SIGTERM".to_string()),
+        ),
+        sample(
+            "busy",
+            None,
+            ServerMessageResponse::Busy("too many connections".to_string()),
+        ),
+        sample("ping", None, ServerMessageResponse::Ping),
+    ]
+}
+
+/// Creates a codec set up the way the client and the server set it up after the handshake.
+fn negotiated_codec(kind: CodecKind) -> Box<dyn Codec> {
+    let mut codec = kind.new_codec();
+    codec.set_capabilities(kind.capabilities());
+    codec.set_max_frame_size(LARGE_MAX_FRAME_SIZE);
+    codec
+}
+
+/// Encodes a message into the bytes of all of its frames.
+fn encode(kind: CodecKind, message: &BinaryMessage) -> Vec<u8> {
+    negotiated_codec(kind)
+        .encode(message)
+        .unwrap_or_else(|err| panic!("{kind} can't encode {message:?}: {err}"))
+        .concat()
+}
+
+/// Decodes bytes that must hold exactly one message.
+fn decode(kind: CodecKind, bytes: &[u8]) -> BinaryMessage {
+    let mut codec = negotiated_codec(kind);
+    codec.push(bytes);
+
+    let message = codec
+        .next_frame()
+        .unwrap_or_else(|err| panic!("{kind} can't decode {bytes:02x?}: {err}"))
+        .unwrap_or_else(|| panic!("{kind} needs more bytes than {bytes:02x?}"));
+    assert_eq!(
+        codec.next_frame(),
+        Ok(None),
+        "{kind} decoded trailing bytes"
+    );
+    message
+}
+
+/// Gets the response a client decodes, which loses the error code on codecs without typed errors.
+fn decoded_response(kind: CodecKind, response: &ServerMessageResponse) -> ServerMessageResponse {
+    match response {
+        ServerMessageResponse::Error { message, .. }
+            if !kind.capabilities().contains(Capabilities::TYPED_ERRORS) =>
+        {
+            ServerMessageResponse::Error {
+                code: ErrorCode::Unknown,
+                message: message.clone(),
+            }
+        }
+        _ => response.clone(),
+    }
+}
+
+/// Gets the request id a peer decodes, which the legacy format has no room for.
+fn decoded_request_id(kind: CodecKind, request_id: Option<u32>) -> Option<u32> {
+    match kind {
+        CodecKind::Legacy => None,
+        _ => request_id,
+    }
+}
+
+/// Gets the golden file of a codec for the current protocol version.
+fn golden_path(kind: CodecKind) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/golden")
+        .join(format!("v{PROTOCOL_VERSION}"))
+        .join(format!("{kind}.txt"))
+}
+
+/// Writes the bytes as lowercase hex, without separators.
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+/// Reads bytes written by [`to_hex`].
+fn from_hex(hex: &str) -> Vec<u8> {
+    (0..hex.len())
+        .step_by(2)
+        .map(|index| u8::from_str_radix(&hex[index..index + 2], 16).unwrap())
+        .collect()
+}
+
+/// Returns true if the golden files should be rewritten instead of checked.
+fn updating() -> bool {
+    std::env::var_os("UPDATE_GOLDEN").is_some()
+}
+
+/// Encodes every sample, as lines of `<direction> <name> <hex>`.
+fn encode_vectors(kind: CodecKind) -> Vec<(String, String, String)> {
+    let requests = request_samples().into_iter().map(|sample| {
+        let message = BinaryMessage::from(sample.command).with_request_id(Some(sample.request_id));
+        ("request", sample.name, encode(kind, &message))
+    });
+    let responses = response_samples()
+        .into_iter()
+        .map(|sample| ("response", sample.name, encode(kind, &sample.message)));
+
+    requests
+        .chain(responses)
+        .map(|(direction, name, bytes)| (direction.to_string(), name.to_string(), to_hex(&bytes)))
+        .collect()
+}
+
+/// Reads the golden vectors of a codec, rewriting them first if asked to.
+fn golden_vectors(kind: CodecKind) -> Vec<(String, String, String)> {
+    let path = golden_path(kind);
+
+    if updating() {
+        let mut text = format!(
+            "# Golden {kind} frames of protocol version {PROTOCOL_VERSION}, generated by tests/conformance.rs.\n\
+             # Every line is `<direction> <name> <hex>`, the names are the ones of docs/protocol.json.\n"
+        );
+        for (direction, name, hex) in encode_vectors(kind) {
+            text.push_str(&format!("{direction} {name} {hex}\n"));
+        }
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, text).unwrap();
+    }
+
+    std::fs::read_to_string(&path)
+        .unwrap_or_else(|err| panic!("can't read {}: {err}", path.display()))
+        .lines()
+        .filter(|line| !line.starts_with('#') && !line.trim().is_empty())
+        .map(|line| {
+            let fields: Vec<&str> = line.split(' ').collect();
+            match fields[..] {
+                [direction, name, hex] => {
+                    (direction.to_string(), name.to_string(), hex.to_string())
+                }
+                _ => panic!("malformed golden line in {}: {line}", path.display()),
+            }
+        })
+        .collect()
+}
+
+/// Finds the golden bytes of a sample.
+fn golden_bytes(vectors: &[(String, String, String)], direction: &str, name: &str) -> Vec<u8> {
+    vectors
+        .iter()
+        .find(|(vector_direction, vector_name, _)| {
+            vector_direction == direction && vector_name == name
+        })
+        .map(|(_, _, hex)| from_hex(hex))
+        .unwrap_or_else(|| panic!("no golden vector for {direction} {name}"))
+}
+
+#[test]
+fn requests_match_golden_vectors() {
+    for kind in CodecKind::ALL {
+        let vectors = golden_vectors(kind);
+
+        for sample in request_samples() {
+            let golden = golden_bytes(&vectors, "request", sample.name);
+
+            // The client has to produce exactly the golden bytes
+            let message = BinaryMessage::from(sample.command.clone())
+                .with_request_id(Some(sample.request_id));
+            assert_eq!(
+                to_hex(&encode(kind, &message)),
+                to_hex(&golden),
+                "{kind} encoding of request {} changed",
+                sample.name
+            );
+
+            // The server has to read the golden bytes back into the same command
+            let frame = decode(kind, &golden);
+            assert_eq!(
+                frame.get_request_id(),
+                decoded_request_id(kind, Some(sample.request_id)),
+                "{kind} request id of request {}",
+                sample.name
+            );
+            assert_eq!(
+                ServerCommandList::try_from(frame),
+                Ok(sample.command),
+                "{kind} decoding of request {}",
+                sample.name
+            );
+        }
+    }
+}
+
+#[test]
+fn responses_match_golden_vectors() {
+    for kind in CodecKind::ALL {
+        let vectors = golden_vectors(kind);
+
+        for sample in response_samples() {
+            let golden = golden_bytes(&vectors, "response", sample.name);
+
+            // The server has to produce exactly the golden bytes
+            assert_eq!(
+                to_hex(&encode(kind, &sample.message)),
+                to_hex(&golden),
+                "{kind} encoding of response {} changed",
+                sample.name
+            );
+
+            // The client has to read the golden bytes back into the same response
+            let frame = decode(kind, &golden);
+            assert_eq!(
+                frame.get_request_id(),
+                decoded_request_id(kind, sample.request_id),
+                "{kind} request id of response {}",
+                sample.name
+            );
+            if kind != CodecKind::Legacy {
+                assert_eq!(
+                    frame.is_push(),
+                    sample.request_id.is_none(),
+                    "{kind} push flag of response {}",
+                    sample.name
+                );
+            }
+            assert_eq!(
+                ServerMessageResponse::try_from(frame),
+                Ok(decoded_response(kind, &sample.response)),
+                "{kind} decoding of response {}",
+                sample.name
+            );
+        }
+    }
+}
+
+#[test]
+fn golden_vectors_cover_the_protocol() {
+    let names = |specs: &[client_server_tcp::protocol_spec::MessageSpec]| -> BTreeSet<&str> {
+        specs.iter().map(|spec| spec.name).collect()
+    };
+
+    let requests: BTreeSet<&str> = request_samples().iter().map(|sample| sample.name).collect();
+    let responses: BTreeSet<&str> = response_samples()
+        .iter()
+        .map(|sample| sample.name)
+        .collect();
+
+    assert_eq!(
+        requests,
+        names(REQUESTS),
+        "every request needs a golden vector"
+    );
+    assert_eq!(
+        responses,
+        names(RESPONSES),
+        "every response needs a golden vector"
+    );
+}
+
+#[test]
+fn protocol_description_is_up_to_date() {
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("docs/protocol.json");
+    let description = serde_json::to_string_pretty(&describe()).unwrap() + "\n";
+
+    if updating() {
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, &description).unwrap();
+    }
+
+    let published = std::fs::read_to_string(&path)
+        .unwrap_or_else(|err| panic!("can't read {}: {err}", path.display()));
+    assert_eq!(
+        published, description,
+        "docs/protocol.json is out of date, regenerate it with UPDATE_GOLDEN=1"
+    );
+}